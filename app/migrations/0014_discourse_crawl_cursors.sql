@@ -0,0 +1,13 @@
+-- Resumable backfill cursors, one per discourse instance and listing
+-- listing is either 'latest' or 'c/<category_id>'
+CREATE TABLE IF NOT EXISTS discourse_crawl_cursors (
+    discourse_id TEXT NOT NULL,
+    listing TEXT NOT NULL,
+    next_page INT NOT NULL DEFAULT 0,
+    topics_seen INT NOT NULL DEFAULT 0,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at TIMESTAMPTZ,
+    last_error TEXT,
+    PRIMARY KEY (discourse_id, listing)
+);
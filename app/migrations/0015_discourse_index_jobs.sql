@@ -0,0 +1,21 @@
+-- Persistent topic indexing queue, one row per pending (topic_id, page)
+-- priority: 0 = backfill, 1 = latest, 2 = user refresh
+-- status: 'queued' while the job can still be picked up, 'failed' once it ran out of attempts
+-- visible_at hides a job while a worker holds it or while it waits for a retry
+CREATE TABLE IF NOT EXISTS discourse_index_jobs (
+    job_id BIGSERIAL PRIMARY KEY,
+    discourse_id TEXT NOT NULL,
+    topic_id INT NOT NULL,
+    page INT NOT NULL,
+    priority SMALLINT NOT NULL DEFAULT 1,
+    status TEXT NOT NULL DEFAULT 'queued',
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    visible_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (discourse_id, topic_id, page)
+);
+
+CREATE INDEX IF NOT EXISTS idx_discourse_index_jobs_claim
+    ON discourse_index_jobs (discourse_id, status, priority DESC, visible_at, job_id);
@@ -0,0 +1,5 @@
+-- Topics from read_restricted categories are only served to authorized users
+ALTER TABLE topics
+ADD COLUMN IF NOT EXISTS visibility TEXT NOT NULL DEFAULT 'public';
+
+CREATE INDEX IF NOT EXISTS idx_topics_visibility ON topics (discourse_id, visibility);
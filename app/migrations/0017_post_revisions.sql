@@ -0,0 +1,23 @@
+-- Posts keep their Discourse revision number and are soft-deleted once they
+-- disappear from the topic's post stream
+ALTER TABLE posts
+ADD COLUMN IF NOT EXISTS version INT NOT NULL DEFAULT 1,
+ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+UPDATE posts SET version = (extra->>'version')::INT WHERE extra->>'version' IS NOT NULL;
+
+-- Every version of a post we have seen, the live content stays in posts
+CREATE TABLE IF NOT EXISTS post_revisions (
+    discourse_id TEXT NOT NULL,
+    post_id INT NOT NULL,
+    version INT NOT NULL,
+    cooked TEXT,
+    revised_at TIMESTAMPTZ,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (discourse_id, post_id, version)
+);
+
+-- Seed the history with the content we already have
+INSERT INTO post_revisions (discourse_id, post_id, version, cooked, revised_at)
+SELECT discourse_id, post_id, version, cooked, updated_at FROM posts
+ON CONFLICT DO NOTHING;
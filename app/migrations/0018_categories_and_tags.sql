@@ -0,0 +1,45 @@
+-- Categories as reported by each instance, refreshed by the indexer
+CREATE TABLE IF NOT EXISTS categories (
+    discourse_id TEXT NOT NULL,
+    category_id INT NOT NULL,
+    name TEXT NOT NULL,
+    slug TEXT NOT NULL,
+    color TEXT,
+    text_color TEXT,
+    description TEXT,
+    parent_category_id INT,
+    topic_count INT,
+    post_count INT,
+    position INT,
+    read_restricted BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (discourse_id, category_id)
+);
+
+-- Every tag seen on an indexed topic
+CREATE TABLE IF NOT EXISTS tags (
+    discourse_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (discourse_id, name)
+);
+
+ALTER TABLE topics
+ADD COLUMN IF NOT EXISTS category_id INT,
+ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
+
+-- Copy what we already have out of extra, tags are either names or {"name": ...} objects
+UPDATE topics SET category_id = (extra->>'category_id')::INT
+WHERE extra->>'category_id' IS NOT NULL;
+
+UPDATE topics SET tags = ARRAY(
+    SELECT COALESCE(tag->>'name', tag #>> '{}') FROM json_array_elements(extra->'tags') AS tag
+)
+WHERE json_typeof(extra->'tags') = 'array';
+
+INSERT INTO tags (discourse_id, name)
+SELECT DISTINCT discourse_id, unnest(tags) FROM topics
+ON CONFLICT DO NOTHING;
+
+CREATE INDEX IF NOT EXISTS idx_topics_category ON topics (discourse_id, category_id);
+CREATE INDEX IF NOT EXISTS idx_topics_tags ON topics USING GIN (tags);
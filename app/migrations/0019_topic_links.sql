@@ -0,0 +1,20 @@
+-- Issues, pull requests and EIPs referenced by a topic, found through the
+-- per-instance issue_rules. topics.pm_issue is kept for existing clients.
+CREATE TABLE IF NOT EXISTS topic_links (
+    discourse_id TEXT NOT NULL,
+    topic_id INT NOT NULL,
+    kind TEXT NOT NULL,
+    repository TEXT NOT NULL DEFAULT '',
+    number INT NOT NULL,
+    reference TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (discourse_id, topic_id, kind, repository, number)
+);
+
+CREATE INDEX IF NOT EXISTS idx_topic_links_target ON topic_links (kind, repository, number);
+
+INSERT INTO topic_links (discourse_id, topic_id, kind, repository, number, reference)
+SELECT discourse_id, topic_id, 'github_issue', 'ethereum/pm', pm_issue,
+    'https://github.com/ethereum/pm/issues/' || pm_issue
+FROM topics WHERE pm_issue IS NOT NULL
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,20 @@
+-- EIPs and ERCs mentioned in posts, rebuilt whenever a post is indexed
+CREATE TABLE IF NOT EXISTS eip_references (
+    discourse_id TEXT NOT NULL,
+    topic_id INT NOT NULL,
+    post_id INT NOT NULL,
+    post_number INT NOT NULL,
+    eip INT NOT NULL,
+    prefix TEXT NOT NULL,
+    PRIMARY KEY (discourse_id, post_id, eip, prefix)
+);
+
+CREATE INDEX IF NOT EXISTS idx_eip_references_eip ON eip_references (eip);
+CREATE INDEX IF NOT EXISTS idx_eip_references_topic ON eip_references (discourse_id, topic_id);
+
+-- Seed from the posts we already have, same pattern as models::eips
+INSERT INTO eip_references (discourse_id, topic_id, post_id, post_number, eip, prefix)
+SELECT DISTINCT p.discourse_id, p.topic_id, p.post_id, p.post_number, m[2]::INT, LOWER(m[1])
+FROM posts p, regexp_matches(p.cooked, '\m(EIP|ERC)[- ]?(\d{1,5})\M', 'gi') AS m
+WHERE p.cooked IS NOT NULL
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,14 @@
+-- Full-text search over topics and posts, used when Meilisearch is not configured
+ALTER TABLE topics
+ADD COLUMN IF NOT EXISTS search_vector TSVECTOR
+    GENERATED ALWAYS AS (to_tsvector('english', title)) STORED;
+
+-- Posts are indexed without their html tags, like the Meilisearch documents
+ALTER TABLE posts
+ADD COLUMN IF NOT EXISTS search_vector TSVECTOR
+    GENERATED ALWAYS AS (
+        to_tsvector('english', regexp_replace(COALESCE(cooked, ''), '<[^>]*>', ' ', 'g'))
+    ) STORED;
+
+CREATE INDEX IF NOT EXISTS idx_topics_search_vector ON topics USING GIN (search_vector);
+CREATE INDEX IF NOT EXISTS idx_posts_search_vector ON posts USING GIN (search_vector);
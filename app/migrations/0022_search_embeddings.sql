@@ -0,0 +1,12 @@
+-- Embeddings of the search documents, cached so reindexing doesn't embed unchanged content again.
+-- entity_id matches the Meilisearch document id (topic_<id> / post_<id>).
+CREATE TABLE IF NOT EXISTS search_embeddings (
+    discourse_id TEXT NOT NULL,
+    entity_id TEXT NOT NULL,
+    model TEXT NOT NULL,
+    -- sha256 of the embedded text
+    content_hash TEXT NOT NULL,
+    embedding REAL[] NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (discourse_id, entity_id, model)
+);
@@ -0,0 +1,27 @@
+-- Background rebuilds of the Meilisearch forum index, resumable from their cursor
+-- status: 'running', 'completed' or 'failed'
+-- phase: 'topics', then 'posts', then for swap jobs 'swap' and 'cleanup'
+-- cursor_discourse_id, cursor_id: key of the last topic or post indexed in the current phase
+-- target_index: 'forum', or for swap jobs the index that replaces it once complete
+CREATE TABLE IF NOT EXISTS search_reindex_jobs (
+    job_id BIGSERIAL PRIMARY KEY,
+    status TEXT NOT NULL DEFAULT 'running',
+    swap BOOLEAN NOT NULL DEFAULT FALSE,
+    target_index TEXT NOT NULL,
+    phase TEXT NOT NULL DEFAULT 'topics',
+    cursor_discourse_id TEXT,
+    cursor_id INT,
+    total_topics BIGINT NOT NULL DEFAULT 0,
+    total_posts BIGINT NOT NULL DEFAULT 0,
+    topics_indexed BIGINT NOT NULL DEFAULT 0,
+    posts_indexed BIGINT NOT NULL DEFAULT 0,
+    last_task_uid BIGINT,
+    last_error TEXT,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at TIMESTAMPTZ
+);
+
+-- At most one rebuild runs at a time
+CREATE UNIQUE INDEX IF NOT EXISTS idx_search_reindex_jobs_running
+    ON search_reindex_jobs ((TRUE)) WHERE status = 'running';
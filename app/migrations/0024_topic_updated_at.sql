@@ -0,0 +1,3 @@
+-- Last change to a topic field stored in its search document, compared against the
+-- forum index when reconciling
+ALTER TABLE topics ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
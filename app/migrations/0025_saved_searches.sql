@@ -0,0 +1,30 @@
+-- Queries users follow, posts the indexer ingests are matched against them
+-- Restricted topics only match while the owner's sign in grants access to their instance
+-- feed_token: secret in the feed url, feed readers can't send a JWT
+CREATE TABLE IF NOT EXISTS saved_searches (
+    search_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    query TEXT NOT NULL,
+    discourse_id TEXT,
+    username TEXT,
+    pm_issue INT,
+    feed_token UUID NOT NULL UNIQUE DEFAULT gen_random_uuid(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_saved_searches_user_id ON saved_searches(user_id);
+
+-- Posts created after a saved search that match it, unread until read_at is set
+CREATE TABLE IF NOT EXISTS saved_search_matches (
+    search_id UUID NOT NULL REFERENCES saved_searches(search_id) ON DELETE CASCADE,
+    discourse_id TEXT NOT NULL,
+    post_id INT NOT NULL,
+    topic_id INT NOT NULL,
+    matched_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    read_at TIMESTAMPTZ,
+    PRIMARY KEY (search_id, discourse_id, post_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_saved_search_matches_unread
+    ON saved_search_matches(search_id, matched_at) WHERE read_at IS NULL;
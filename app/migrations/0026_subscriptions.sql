@@ -0,0 +1,44 @@
+-- Topics and Discourse users followed by users, exactly one of topic_id and username is set
+-- muted: produces no notifications, a muted topic also silences user subscriptions in it
+-- Restricted topics only notify while the owner's sign in grants access to their instance
+CREATE TABLE IF NOT EXISTS subscriptions (
+    subscription_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    discourse_id TEXT NOT NULL,
+    topic_id INT,
+    username TEXT,
+    muted BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((topic_id IS NULL) <> (username IS NULL))
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_subscriptions_topic
+    ON subscriptions(user_id, discourse_id, topic_id) WHERE topic_id IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_subscriptions_username
+    ON subscriptions(user_id, discourse_id, username) WHERE username IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_subscriptions_discourse_topic ON subscriptions(discourse_id, topic_id);
+CREATE INDEX IF NOT EXISTS idx_subscriptions_discourse_username ON subscriptions(discourse_id, username);
+
+-- kind: 'new_post', 'post_edited' or 'summary'
+-- post_id and version are set for post notifications, summary_id for summaries
+CREATE TABLE IF NOT EXISTS notifications (
+    notification_id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    discourse_id TEXT NOT NULL,
+    topic_id INT NOT NULL,
+    post_id INT,
+    post_number INT,
+    version INT,
+    summary_id INT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    read_at TIMESTAMPTZ
+);
+
+-- A post version or summary notifies each user once, however often it is crawled
+CREATE UNIQUE INDEX IF NOT EXISTS idx_notifications_post
+    ON notifications(user_id, discourse_id, post_id, version) WHERE post_id IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_notifications_summary
+    ON notifications(user_id, summary_id) WHERE summary_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_notifications_user_unread
+    ON notifications(user_id, created_at) WHERE read_at IS NULL;
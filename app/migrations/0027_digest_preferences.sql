@@ -0,0 +1,14 @@
+-- Digest emails of followed topics, users without a row receive none
+-- schedule: 'off', 'daily' or 'weekly'
+-- send_hour: hour of the day in UTC, weekday: ISO day of the week for weekly digests (1 is Monday)
+-- unsubscribe_token: secret of the one-click unsubscribe link in every digest
+CREATE TABLE IF NOT EXISTS digest_preferences (
+    user_id UUID PRIMARY KEY REFERENCES users(user_id) ON DELETE CASCADE,
+    schedule TEXT NOT NULL DEFAULT 'off',
+    send_hour INT NOT NULL DEFAULT 8 CHECK (send_hour BETWEEN 0 AND 23),
+    weekday INT NOT NULL DEFAULT 1 CHECK (weekday BETWEEN 1 AND 7),
+    unsubscribe_token UUID NOT NULL UNIQUE DEFAULT gen_random_uuid(),
+    last_sent_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,14 @@
+-- Partial summaries of consecutive posts, combined into the topic_summaries of long topics
+-- first_post/last_post: post_number range of the chunk, sealed once later posts start the next chunk
+-- based_on: newest created_at/updated_at of the posts in the range when it was summarized
+CREATE TABLE IF NOT EXISTS topic_summary_chunks (
+    discourse_id TEXT NOT NULL,
+    topic_id INT NOT NULL,
+    first_post INT NOT NULL,
+    last_post INT NOT NULL,
+    sealed BOOLEAN NOT NULL DEFAULT FALSE,
+    based_on TIMESTAMPTZ NOT NULL,
+    summary_text TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (discourse_id, topic_id, first_post)
+);
@@ -0,0 +1,9 @@
+-- Claims of a topic summary with the posts backing them
+-- post_numbers: posts of the summarized topic, only numbers that existed when the summary was stored
+CREATE TABLE IF NOT EXISTS topic_summary_citations (
+    summary_id INT NOT NULL REFERENCES topic_summaries(summary_id) ON DELETE CASCADE,
+    position INT NOT NULL,
+    claim TEXT NOT NULL,
+    post_numbers INT[] NOT NULL,
+    PRIMARY KEY (summary_id, position)
+);
@@ -19,13 +19,24 @@ pub async fn main() -> Result<(), Error> {
     let state = state::AppStateInner::init().await;
     let state = Arc::new(state);
 
+    state.reindex.resume_interrupted(&state).await;
+    state.reindex.migrate_entity_ids(&state).await;
+
     let discourse_state = state.clone();
     let discourse_handle = async_std::task::spawn(async move {
         sleep(Duration::from_secs(5)).await;
         discourse_state.clone().discourse.start_all_indexers(discourse_state).await;
     });
+    let reconcile_state = state.clone();
+    let reconcile_handle = async_std::task::spawn(async move {
+        reconcile_state.reconcile.reconcile_periodically(&reconcile_state).await;
+    });
+    let digest_state = state.clone();
+    let digest_handle = async_std::task::spawn(async move {
+        modules::digest::send_digests_periodically(&digest_state).await;
+    });
     let server_handle = async_std::task::spawn(server::start_http(state));
 
-    join!(server_handle, discourse_handle);
+    join!(server_handle, discourse_handle, reconcile_handle, digest_handle);
     Ok(())
 }
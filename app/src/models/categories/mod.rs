@@ -0,0 +1,96 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as};
+
+use crate::{
+    models::{discourse::category::DiscourseCategory, topics::TopicAccess},
+    state::AppState,
+};
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Object, Clone)]
+pub struct Category {
+    pub discourse_id: String,
+    pub category_id: i32,
+    pub name: String,
+    pub slug: String,
+    pub color: Option<String>,
+    pub text_color: Option<String>,
+    pub description: Option<String>,
+    pub parent_category_id: Option<i32>,
+    pub topic_count: Option<i32>,
+    pub post_count: Option<i32>,
+    pub position: Option<i32>,
+    pub read_restricted: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Category {
+    /// Replace the stored categories of an instance with the ones it currently reports
+    pub async fn sync(
+        discourse_id: &str,
+        categories: &[DiscourseCategory],
+        state: &AppState,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = state.database.pool.begin().await?;
+
+        for category in categories {
+            query("INSERT INTO categories (discourse_id, category_id, name, slug, color, text_color, description, parent_category_id, topic_count, post_count, position, read_restricted, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, NOW()) ON CONFLICT (discourse_id, category_id) DO UPDATE SET name=$3, slug=$4, color=$5, text_color=$6, description=$7, parent_category_id=$8, topic_count=$9, post_count=$10, position=$11, read_restricted=$12, updated_at=NOW()")
+                .bind(discourse_id)
+                .bind(category.id)
+                .bind(&category.name)
+                .bind(&category.slug)
+                .bind(&category.color)
+                .bind(&category.text_color)
+                .bind(&category.description)
+                .bind(category.parent_category_id)
+                .bind(category.topic_count)
+                .bind(category.post_count)
+                .bind(category.position)
+                .bind(category.read_restricted.unwrap_or(false))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let category_ids: Vec<i32> = categories.iter().map(|category| category.id).collect();
+        query("DELETE FROM categories WHERE discourse_id = $1 AND NOT (category_id = ANY($2))")
+            .bind(discourse_id)
+            .bind(&category_ids)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Categories the viewer may see, optionally limited to one instance
+    pub async fn find_all(
+        discourse_id: Option<&str>,
+        access: &TopicAccess,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT * FROM categories WHERE ($1::TEXT IS NULL OR discourse_id = $1) AND (NOT read_restricted OR discourse_id = ANY($2)) ORDER BY discourse_id, position NULLS LAST, category_id",
+        )
+        .bind(discourse_id)
+        .bind(access.restricted())
+        .fetch_all(&state.database.pool)
+        .await
+    }
+
+    pub async fn find_by_id(
+        discourse_id: &str,
+        category_id: i32,
+        state: &AppState,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        query_as("SELECT * FROM categories WHERE discourse_id = $1 AND category_id = $2")
+            .bind(discourse_id)
+            .bind(category_id)
+            .fetch_optional(&state.database.pool)
+            .await
+    }
+
+    pub fn can_view(&self, access: &TopicAccess) -> bool {
+        !self.read_restricted || access.restricted().contains(&self.discourse_id)
+    }
+}
@@ -0,0 +1,196 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as};
+
+use crate::state::AppState;
+
+/// How long a claimed job stays hidden from other workers before it is retried
+pub const VISIBILITY_TIMEOUT: TimeDelta = TimeDelta::minutes(5);
+/// Jobs are marked as failed after this many attempts
+pub const MAX_ATTEMPTS: i32 = 5;
+
+/// Order in which queued jobs are picked up, higher runs first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum IndexPriority {
+    Backfill = 0,
+    Latest = 1,
+    Refresh = 2,
+}
+
+impl IndexPriority {
+    pub fn from_i16(value: i16) -> Self {
+        match value {
+            i16::MIN..=0 => Self::Backfill,
+            1 => Self::Latest,
+            _ => Self::Refresh,
+        }
+    }
+}
+
+/// A pending request to index one page of a topic, stored in `discourse_index_jobs`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, Object)]
+pub struct IndexJob {
+    pub job_id: i64,
+    pub discourse_id: String,
+    pub topic_id: i32,
+    pub page: i32,
+    pub priority: i16,
+    /// `queued` or `failed`
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub visible_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, Object)]
+pub struct IndexQueueStats {
+    pub discourse_id: String,
+    pub priority: i16,
+    pub status: String,
+    pub jobs: i64,
+}
+
+impl IndexJob {
+    pub fn priority(&self) -> IndexPriority {
+        IndexPriority::from_i16(self.priority)
+    }
+
+    /// Queue a page for indexing
+    ///
+    /// If the page is already queued it keeps its place but is raised to the higher
+    /// of both priorities, failed jobs are revived with a fresh attempt budget and
+    /// jobs enqueued again while running are picked up once more afterwards.
+    pub async fn enqueue(
+        discourse_id: &str,
+        topic_id: i32,
+        page: i32,
+        priority: IndexPriority,
+        state: &AppState,
+    ) -> Result<(), sqlx::Error> {
+        query(
+            "INSERT INTO discourse_index_jobs (discourse_id, topic_id, page, priority) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (discourse_id, topic_id, page) DO UPDATE SET
+                priority = GREATEST(discourse_index_jobs.priority, EXCLUDED.priority),
+                attempts = CASE WHEN discourse_index_jobs.status = 'failed' THEN 0 ELSE discourse_index_jobs.attempts END,
+                visible_at = LEAST(discourse_index_jobs.visible_at, NOW()),
+                status = 'queued',
+                updated_at = NOW()",
+        )
+        .bind(discourse_id)
+        .bind(topic_id)
+        .bind(page)
+        .bind(priority as i16)
+        .execute(&state.database.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Take the most important visible job and hide it for [`VISIBILITY_TIMEOUT`]
+    ///
+    /// Uses `FOR UPDATE SKIP LOCKED` so several workers can share the queue.
+    pub async fn claim(discourse_id: &str, state: &AppState) -> Result<Option<Self>, sqlx::Error> {
+        query_as(
+            "UPDATE discourse_index_jobs SET attempts = attempts + 1, visible_at = NOW() + $2, updated_at = NOW()
+             WHERE job_id = (
+                SELECT job_id FROM discourse_index_jobs
+                WHERE discourse_id = $1 AND status = 'queued' AND visible_at <= NOW()
+                ORDER BY priority DESC, visible_at ASC, job_id ASC
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+             )
+             RETURNING *",
+        )
+        .bind(discourse_id)
+        .bind(VISIBILITY_TIMEOUT)
+        .fetch_optional(&state.database.pool)
+        .await
+    }
+
+    /// Remove the job, unless it was enqueued again since it was claimed
+    pub async fn complete(&self, state: &AppState) -> Result<(), sqlx::Error> {
+        query("DELETE FROM discourse_index_jobs WHERE job_id = $1 AND updated_at = $2")
+            .bind(self.job_id)
+            .bind(self.updated_at)
+            .execute(&state.database.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Record a failed attempt, retrying later with exponential backoff until
+    /// [`MAX_ATTEMPTS`] is reached
+    pub async fn fail(&self, error: &str, state: &AppState) -> Result<(), sqlx::Error> {
+        let backoff = TimeDelta::seconds(30 * 2i64.pow(self.attempts.clamp(1, 10) as u32 - 1));
+        let status = if self.attempts >= MAX_ATTEMPTS {
+            "failed"
+        } else {
+            "queued"
+        };
+
+        query(
+            "UPDATE discourse_index_jobs SET status = $2, last_error = $3, visible_at = NOW() + $4, updated_at = NOW() WHERE job_id = $1",
+        )
+        .bind(self.job_id)
+        .bind(status)
+        .bind(error)
+        .bind(backoff)
+        .execute(&state.database.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Number of queued jobs at the given priority, used to throttle the backfill crawler
+    pub async fn count_queued(
+        discourse_id: &str,
+        priority: IndexPriority,
+        state: &AppState,
+    ) -> Result<i64, sqlx::Error> {
+        let (count,): (i64,) = query_as(
+            "SELECT COUNT(*) FROM discourse_index_jobs WHERE discourse_id = $1 AND priority = $2 AND status = 'queued'",
+        )
+        .bind(discourse_id)
+        .bind(priority as i16)
+        .fetch_one(&state.database.pool)
+        .await?;
+        Ok(count)
+    }
+
+    pub async fn stats(state: &AppState) -> Result<Vec<IndexQueueStats>, sqlx::Error> {
+        query_as(
+            "SELECT discourse_id, priority, status, COUNT(*) AS jobs FROM discourse_index_jobs
+             GROUP BY discourse_id, priority, status
+             ORDER BY discourse_id, priority DESC, status",
+        )
+        .fetch_all(&state.database.pool)
+        .await
+    }
+
+    pub async fn find_failed(
+        discourse_id: &str,
+        limit: i64,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT * FROM discourse_index_jobs WHERE discourse_id = $1 AND status = 'failed' ORDER BY updated_at DESC LIMIT $2",
+        )
+        .bind(discourse_id)
+        .bind(limit)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+
+    /// Put every failed job of an instance back in the queue
+    pub async fn retry_failed(discourse_id: &str, state: &AppState) -> Result<u64, sqlx::Error> {
+        let result = query(
+            "UPDATE discourse_index_jobs SET status = 'queued', attempts = 0, visible_at = NOW(), updated_at = NOW() WHERE discourse_id = $1 AND status = 'failed'",
+        )
+        .bind(discourse_id)
+        .execute(&state.database.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
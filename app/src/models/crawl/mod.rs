@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as};
+
+use crate::state::AppState;
+
+pub mod job;
+
+/// Progress of a backfill crawl through one listing of a discourse instance
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, Object)]
+pub struct CrawlCursor {
+    pub discourse_id: String,
+    /// `latest` or `c/<category_id>`
+    pub listing: String,
+    pub next_page: i32,
+    pub topics_seen: i32,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+impl CrawlCursor {
+    pub async fn get_or_create(
+        discourse_id: &str,
+        listing: &str,
+        state: &AppState,
+    ) -> Result<Self, sqlx::Error> {
+        query_as(
+            "INSERT INTO discourse_crawl_cursors (discourse_id, listing) VALUES ($1, $2) ON CONFLICT (discourse_id, listing) DO UPDATE SET discourse_id = EXCLUDED.discourse_id RETURNING *",
+        )
+        .bind(discourse_id)
+        .bind(listing)
+        .fetch_one(&state.database.pool)
+        .await
+    }
+
+    pub async fn find_by_discourse_id(
+        discourse_id: &str,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        query_as("SELECT * FROM discourse_crawl_cursors WHERE discourse_id = $1 ORDER BY listing ASC")
+            .bind(discourse_id)
+            .fetch_all(&state.database.pool)
+            .await
+    }
+
+    /// Record that `page` was crawled and `topics` were enqueued from it
+    pub async fn advance(&mut self, page: i32, topics: i32, state: &AppState) -> Result<(), sqlx::Error> {
+        *self = query_as(
+            "UPDATE discourse_crawl_cursors SET next_page = $3, topics_seen = topics_seen + $4, updated_at = NOW(), last_error = NULL WHERE discourse_id = $1 AND listing = $2 RETURNING *",
+        )
+        .bind(&self.discourse_id)
+        .bind(&self.listing)
+        .bind(page + 1)
+        .bind(topics)
+        .fetch_one(&state.database.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn complete(&mut self, state: &AppState) -> Result<(), sqlx::Error> {
+        *self = query_as(
+            "UPDATE discourse_crawl_cursors SET completed_at = NOW(), updated_at = NOW() WHERE discourse_id = $1 AND listing = $2 RETURNING *",
+        )
+        .bind(&self.discourse_id)
+        .bind(&self.listing)
+        .fetch_one(&state.database.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn record_error(&self, error: &str, state: &AppState) -> Result<(), sqlx::Error> {
+        query("UPDATE discourse_crawl_cursors SET last_error = $3, updated_at = NOW() WHERE discourse_id = $1 AND listing = $2")
+            .bind(&self.discourse_id)
+            .bind(&self.listing)
+            .bind(error)
+            .execute(&state.database.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Forget all progress so the next backfill starts from page 0
+    pub async fn reset(discourse_id: &str, state: &AppState) -> Result<(), sqlx::Error> {
+        query("DELETE FROM discourse_crawl_cursors WHERE discourse_id = $1")
+            .bind(discourse_id)
+            .execute(&state.database.pool)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiscourseCategoriesResponse {
+    pub category_list: DiscourseCategoryList,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiscourseCategoryList {
+    pub categories: Vec<DiscourseCategory>,
+    #[serde(flatten)]
+    pub extra: serde_json::Value, // unknown
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiscourseCategory {
+    pub id: i32,
+    pub name: String,
+    pub slug: String,
+    pub color: Option<String>,
+    pub text_color: Option<String>,
+    pub description: Option<String>,
+    pub topic_count: Option<i32>,
+    pub post_count: Option<i32>,
+    pub position: Option<i32>,
+    pub read_restricted: Option<bool>,
+    pub parent_category_id: Option<i32>,
+    pub subcategory_ids: Option<Vec<i32>>,
+    #[serde(flatten)]
+    pub extra: serde_json::Value, // unknown
+}
+
+/// `/site.json`, which lists every category the requester can see, including subcategories
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiscourseSiteResponse {
+    pub categories: Vec<DiscourseCategory>,
+    #[serde(flatten)]
+    pub extra: serde_json::Value, // unknown
+}
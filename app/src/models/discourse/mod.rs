@@ -1,3 +1,4 @@
+pub mod category;
 pub mod latest;
 pub mod topic;
 pub mod user;
@@ -42,6 +42,9 @@ pub struct DiscourseTopicResponse {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DiscourseTopicPostStream {
     pub posts: Vec<DiscourseTopicPost>,
+    /// Ids of every visible post in the topic, only sent with the first page
+    #[serde(default)]
+    pub stream: Option<Vec<i32>>,
     #[serde(flatten)]
     pub extra: serde_json::Value, // unknown
 }
@@ -59,6 +62,13 @@ pub struct DiscourseTopicPost {
     pub topic_id: i32,
     pub post_url: Option<String>,
     pub post_number: i32,
+    /// Revision number, bumped by Discourse on every edit
+    #[serde(default = "default_post_version")]
+    pub version: i32,
     #[serde(flatten)]
     pub extra: serde_json::Value, // unknown
 }
+
+fn default_post_version() -> i32 {
+    1
+}
@@ -0,0 +1,165 @@
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as};
+
+use crate::{
+    models::topics::{TopicAccess, post::Post},
+    state::AppState,
+};
+
+/// "EIP-4844", "ERC 20", "eip4844", links such as eips.ethereum.org/EIPS/eip-4844
+/// match through their last path segment
+static MENTION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\b(EIP|ERC)[- ]?(\d{1,5})\b").unwrap());
+
+/// An EIP or ERC referenced by a post, `prefix` is `eip` or `erc` as written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EipReference {
+    pub prefix: String,
+    pub eip: i32,
+}
+
+/// All distinct EIP/ERC references in the cooked html of a post
+pub fn extract_eip_references(cooked: &str) -> Vec<EipReference> {
+    let mut references: Vec<EipReference> = Vec::new();
+
+    for captures in MENTION.captures_iter(cooked) {
+        let Ok(eip) = captures[2].parse() else {
+            continue;
+        };
+        let reference = EipReference {
+            prefix: captures[1].to_ascii_lowercase(),
+            eip,
+        };
+        if !references.contains(&reference) {
+            references.push(reference);
+        }
+    }
+
+    references
+}
+
+/// Replace the references stored for a post with the ones in its current content
+pub async fn replace_for_post(
+    post: &Post,
+    references: &[EipReference],
+    state: &AppState,
+) -> Result<(), sqlx::Error> {
+    let mut tx = state.database.pool.begin().await?;
+
+    query("DELETE FROM eip_references WHERE discourse_id = $1 AND post_id = $2")
+        .bind(&post.discourse_id)
+        .bind(post.post_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for reference in references {
+        query("INSERT INTO eip_references (discourse_id, topic_id, post_id, post_number, eip, prefix) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING")
+            .bind(&post.discourse_id)
+            .bind(post.topic_id)
+            .bind(post.post_id)
+            .bind(post.post_number)
+            .bind(reference.eip)
+            .bind(&reference.prefix)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// A topic that mentions an EIP, with the posts that do
+#[derive(Debug, Serialize, Deserialize, FromRow, Object)]
+pub struct EipMention {
+    pub discourse_id: String,
+    pub topic_id: i32,
+    pub title: String,
+    pub slug: String,
+    pub mention_count: i64,
+    pub post_numbers: Vec<i32>,
+    pub last_mentioned_at: Option<DateTime<Utc>>,
+}
+
+/// An EIP referenced in a topic
+#[derive(Debug, Serialize, Deserialize, FromRow, Object)]
+pub struct TopicEip {
+    pub eip: i32,
+    /// `eip` or `erc`, whichever the topic used most
+    pub prefix: String,
+    pub mention_count: i64,
+    pub post_numbers: Vec<i32>,
+}
+
+impl EipMention {
+    /// Topics mentioning `eip`, most recently mentioned first
+    pub async fn find_by_eip(
+        eip: i32,
+        limit: i64,
+        offset: i64,
+        access: &TopicAccess,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT t.discourse_id, t.topic_id, t.title, t.slug, COUNT(*) AS mention_count, \
+             ARRAY_AGG(r.post_number ORDER BY r.post_number) AS post_numbers, MAX(p.created_at) AS last_mentioned_at \
+             FROM eip_references r \
+             JOIN posts p ON p.discourse_id = r.discourse_id AND p.post_id = r.post_id AND p.deleted_at IS NULL \
+             JOIN topics t ON t.discourse_id = r.discourse_id AND t.topic_id = r.topic_id \
+             WHERE r.eip = $1 AND (t.visibility <> 'restricted' OR t.discourse_id = ANY($2)) \
+             GROUP BY t.discourse_id, t.topic_id, t.title, t.slug \
+             ORDER BY last_mentioned_at DESC NULLS LAST LIMIT $3 OFFSET $4",
+        )
+        .bind(eip)
+        .bind(access.restricted())
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+}
+
+impl TopicEip {
+    /// EIPs referenced by the live posts of a topic, most mentioned first
+    pub async fn find_by_topic(
+        discourse_id: &str,
+        topic_id: i32,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT r.eip, MODE() WITHIN GROUP (ORDER BY r.prefix) AS prefix, COUNT(*) AS mention_count, \
+             ARRAY_AGG(r.post_number ORDER BY r.post_number) AS post_numbers \
+             FROM eip_references r \
+             JOIN posts p ON p.discourse_id = r.discourse_id AND p.post_id = r.post_id AND p.deleted_at IS NULL \
+             WHERE r.discourse_id = $1 AND r.topic_id = $2 \
+             GROUP BY r.eip ORDER BY mention_count DESC, r.eip",
+        )
+        .bind(discourse_id)
+        .bind(topic_id)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_eip_references() {
+        let cooked = r#"<p>EIP-4844 blobs, erc20 tokens, ERC 721 and <a href="https://eips.ethereum.org/EIPS/eip-7702">this</a>. EIP-4844 again, not EIP-ABC.</p>"#;
+
+        assert_eq!(
+            extract_eip_references(cooked)
+                .iter()
+                .map(|reference| (reference.prefix.as_str(), reference.eip))
+                .collect::<Vec<_>>(),
+            vec![("eip", 4844), ("erc", 20), ("erc", 721), ("eip", 7702)]
+        );
+        assert!(extract_eip_references("<p>shipped in 2024</p>").is_empty());
+    }
+}
@@ -1,6 +1,10 @@
+pub mod categories;
+pub mod crawl;
 pub mod discourse;
+pub mod eips;
 pub mod ical;
 pub mod topics;
 pub mod pm;
+pub mod search;
 pub mod user;
 pub mod workshop;
@@ -0,0 +1,140 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query_as};
+
+use crate::state::AppState;
+
+pub const PHASE_TOPICS: &str = "topics";
+pub const PHASE_POSTS: &str = "posts";
+/// Swap jobs only, the rebuilt index replaces `forum`
+pub const PHASE_SWAP: &str = "swap";
+/// Swap jobs only, the previous `forum` index is deleted
+pub const PHASE_CLEANUP: &str = "cleanup";
+
+/// A rebuild of the Meilisearch forum index, stored in `search_reindex_jobs`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, Object)]
+pub struct ReindexJob {
+    pub job_id: i64,
+    /// `running`, `completed` or `failed`
+    pub status: String,
+    /// Build into `target_index` and swap it with `forum` once complete
+    pub swap: bool,
+    pub target_index: String,
+    /// `topics`, `posts`, `swap` or `cleanup`
+    pub phase: String,
+    /// Key of the last topic or post indexed in the current phase
+    pub cursor_discourse_id: Option<String>,
+    pub cursor_id: Option<i32>,
+    pub total_topics: i64,
+    pub total_posts: i64,
+    pub topics_indexed: i64,
+    pub posts_indexed: i64,
+    /// Meilisearch task of the last batch
+    pub last_task_uid: Option<i64>,
+    pub last_error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl ReindexJob {
+    /// Start a job over every topic and post currently in the database
+    ///
+    /// Fails with a unique violation while another job is running.
+    pub async fn create(swap: bool, state: &AppState) -> Result<Self, sqlx::Error> {
+        query_as(
+            "WITH id AS (SELECT nextval(pg_get_serial_sequence('search_reindex_jobs', 'job_id')) AS job_id)
+             INSERT INTO search_reindex_jobs (job_id, swap, target_index, total_topics, total_posts)
+             SELECT job_id, $1, CASE WHEN $1 THEN 'forum_reindex_' || job_id ELSE 'forum' END,
+                (SELECT COUNT(*) FROM topics), (SELECT COUNT(*) FROM posts WHERE deleted_at IS NULL)
+             FROM id
+             RETURNING *",
+        )
+        .bind(swap)
+        .fetch_one(&state.database.pool)
+        .await
+    }
+
+    pub async fn find_by_id(job_id: i64, state: &AppState) -> Result<Option<Self>, sqlx::Error> {
+        query_as("SELECT * FROM search_reindex_jobs WHERE job_id = $1")
+            .bind(job_id)
+            .fetch_optional(&state.database.pool)
+            .await
+    }
+
+    pub async fn find_running(state: &AppState) -> Result<Option<Self>, sqlx::Error> {
+        query_as("SELECT * FROM search_reindex_jobs WHERE status = 'running'")
+            .fetch_optional(&state.database.pool)
+            .await
+    }
+
+    /// Record that a batch up to `cursor` was indexed by Meilisearch task `task_uid`
+    pub async fn advance(
+        &mut self,
+        cursor: (&str, i32),
+        topics: i64,
+        posts: i64,
+        task_uid: u32,
+        state: &AppState,
+    ) -> Result<(), sqlx::Error> {
+        *self = query_as(
+            "UPDATE search_reindex_jobs SET cursor_discourse_id = $2, cursor_id = $3, topics_indexed = topics_indexed + $4, posts_indexed = posts_indexed + $5, last_task_uid = $6, last_error = NULL, updated_at = NOW() WHERE job_id = $1 RETURNING *",
+        )
+        .bind(self.job_id)
+        .bind(cursor.0)
+        .bind(cursor.1)
+        .bind(topics)
+        .bind(posts)
+        .bind(task_uid as i64)
+        .fetch_one(&state.database.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Move on to `phase`, starting from its first key
+    pub async fn set_phase(&mut self, phase: &str, state: &AppState) -> Result<(), sqlx::Error> {
+        *self = query_as(
+            "UPDATE search_reindex_jobs SET phase = $2, cursor_discourse_id = NULL, cursor_id = NULL, updated_at = NOW() WHERE job_id = $1 RETURNING *",
+        )
+        .bind(self.job_id)
+        .bind(phase)
+        .fetch_one(&state.database.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn complete(&mut self, state: &AppState) -> Result<(), sqlx::Error> {
+        *self = query_as(
+            "UPDATE search_reindex_jobs SET status = 'completed', completed_at = NOW(), updated_at = NOW() WHERE job_id = $1 RETURNING *",
+        )
+        .bind(self.job_id)
+        .fetch_one(&state.database.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Stop the job, it keeps its cursor and can be resumed
+    pub async fn fail(&mut self, error: &str, state: &AppState) -> Result<(), sqlx::Error> {
+        *self = query_as(
+            "UPDATE search_reindex_jobs SET status = 'failed', last_error = $2, updated_at = NOW() WHERE job_id = $1 RETURNING *",
+        )
+        .bind(self.job_id)
+        .bind(error)
+        .fetch_one(&state.database.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Mark a failed job as running again, fails with a unique violation while
+    /// another job is running
+    pub async fn resume(&mut self, state: &AppState) -> Result<(), sqlx::Error> {
+        *self = query_as(
+            "UPDATE search_reindex_jobs SET status = 'running', updated_at = NOW() WHERE job_id = $1 RETURNING *",
+        )
+        .bind(self.job_id)
+        .fetch_one(&state.database.pool)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as, query_scalar};
+use uuid::Uuid;
+
+use crate::{
+    models::{topics::TopicAccess, user::User},
+    state::AppState,
+};
+
+/// A query a user follows, stored in `saved_searches`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, Object)]
+pub struct SavedSearch {
+    pub search_id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    /// Postgres `websearch_to_tsquery` syntax, e.g. `"inclusion lists" or EOF`
+    pub query: String,
+    pub discourse_id: Option<String>,
+    pub username: Option<String>,
+    pub pm_issue: Option<i32>,
+    /// Secret in the feed url, `/feeds/searches/:feed_token`
+    pub feed_token: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// Matches not yet marked as read, only set when listing
+    #[sqlx(default)]
+    pub unread: i64,
+}
+
+/// Query and filters of a new saved search
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct SavedSearchInput {
+    /// Defaults to the query
+    pub name: Option<String>,
+    pub query: String,
+    pub discourse_id: Option<String>,
+    pub username: Option<String>,
+    pub pm_issue: Option<i32>,
+}
+
+/// A post matching a saved search, with its topic
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, Object)]
+pub struct SavedSearchMatch {
+    pub search_id: Uuid,
+    pub discourse_id: String,
+    pub topic_id: i32,
+    pub post_id: i32,
+    pub post_number: i32,
+    pub title: String,
+    pub slug: String,
+    pub username: Option<String>,
+    pub cooked: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub matched_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+impl SavedSearch {
+    pub async fn create(
+        user_id: Uuid,
+        input: &SavedSearchInput,
+        state: &AppState,
+    ) -> Result<Self, sqlx::Error> {
+        query_as(
+            "INSERT INTO saved_searches (user_id, name, query, discourse_id, username, pm_issue) VALUES ($1, COALESCE($2, $3), $3, $4, $5, $6) RETURNING *",
+        )
+        .bind(user_id)
+        .bind(&input.name)
+        .bind(&input.query)
+        .bind(&input.discourse_id)
+        .bind(&input.username)
+        .bind(input.pm_issue)
+        .fetch_one(&state.database.pool)
+        .await
+    }
+
+    /// Saved searches of the user with their unread count, newest first
+    pub async fn find_by_user_id(user_id: Uuid, state: &AppState) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT s.*, (SELECT COUNT(*) FROM saved_search_matches m WHERE m.search_id = s.search_id AND m.read_at IS NULL) AS unread FROM saved_searches s WHERE s.user_id = $1 ORDER BY s.created_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+
+    pub async fn count_by_user_id(user_id: Uuid, state: &AppState) -> Result<i64, sqlx::Error> {
+        query_scalar("SELECT COUNT(*) FROM saved_searches WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&state.database.pool)
+            .await
+    }
+
+    pub async fn find_by_feed_token(feed_token: Uuid, state: &AppState) -> Result<Option<Self>, sqlx::Error> {
+        query_as("SELECT * FROM saved_searches WHERE feed_token = $1")
+            .bind(feed_token)
+            .fetch_optional(&state.database.pool)
+            .await
+    }
+
+    /// Users with saved searches that posts of the instance can match
+    pub async fn find_owners(discourse_id: &str, state: &AppState) -> Result<Vec<User>, sqlx::Error> {
+        query_as(
+            "SELECT u.* FROM users u WHERE EXISTS (SELECT 1 FROM saved_searches s WHERE s.user_id = u.user_id AND (s.discourse_id IS NULL OR s.discourse_id = $1))",
+        )
+        .bind(discourse_id)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+
+    /// Delete a saved search of the user with its matches, `false` when it doesn't exist
+    pub async fn delete(search_id: Uuid, user_id: Uuid, state: &AppState) -> Result<bool, sqlx::Error> {
+        let result = query("DELETE FROM saved_searches WHERE search_id = $1 AND user_id = $2")
+            .bind(search_id)
+            .bind(user_id)
+            .execute(&state.database.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Match freshly upserted posts against every saved search, returns the number of
+    /// new matches
+    ///
+    /// Only posts created after a search was saved match it, so crawling old pages
+    /// doesn't flood new searches. Opening posts also match on their topic title.
+    /// Recording is idempotent, an edit only adds a match when the post didn't match
+    /// before. Restricted topics only match searches of `readers`, the owners that
+    /// currently have access to the instance.
+    pub async fn record_matches(
+        discourse_id: &str,
+        post_ids: &[i32],
+        readers: &[Uuid],
+        state: &AppState,
+    ) -> Result<u64, sqlx::Error> {
+        let result = query(
+            "INSERT INTO saved_search_matches (search_id, discourse_id, post_id, topic_id)
+             SELECT s.search_id, p.discourse_id, p.post_id, p.topic_id
+             FROM posts p
+             JOIN topics t ON t.discourse_id = p.discourse_id AND t.topic_id = p.topic_id
+             JOIN saved_searches s ON s.discourse_id IS NULL OR s.discourse_id = p.discourse_id
+             CROSS JOIN LATERAL websearch_to_tsquery('english', s.query) AS q
+             WHERE p.discourse_id = $1 AND p.post_id = ANY($2)
+                AND p.deleted_at IS NULL
+                AND p.created_at >= s.created_at
+                AND (s.username IS NULL OR p.extra->>'username' = s.username)
+                AND (s.pm_issue IS NULL OR t.pm_issue = s.pm_issue)
+                AND (t.visibility <> 'restricted' OR s.user_id = ANY($3))
+                AND (p.search_vector @@ q OR (p.post_number = 1 AND t.search_vector @@ q))
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(discourse_id)
+        .bind(post_ids)
+        .bind(readers)
+        .execute(&state.database.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl SavedSearchMatch {
+    /// Matches of the user's saved searches, newest first, optionally of one search or
+    /// unread only
+    ///
+    /// Restricted topics are only returned for instances in `access`, the owner may
+    /// have lost access since saving the search.
+    pub async fn find(
+        user_id: Uuid,
+        search_id: Option<Uuid>,
+        unread_only: bool,
+        access: &TopicAccess,
+        limit: i64,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT m.search_id, m.discourse_id, m.topic_id, m.post_id, p.post_number, t.title, t.slug,
+                p.extra->>'username' AS username, p.cooked, p.created_at, m.matched_at, m.read_at
+             FROM saved_search_matches m
+             JOIN saved_searches s ON s.search_id = m.search_id
+             JOIN posts p ON p.discourse_id = m.discourse_id AND p.post_id = m.post_id
+             JOIN topics t ON t.discourse_id = m.discourse_id AND t.topic_id = m.topic_id
+             WHERE s.user_id = $1
+                AND ($2::UUID IS NULL OR m.search_id = $2)
+                AND (NOT $3 OR m.read_at IS NULL)
+                AND p.deleted_at IS NULL
+                AND (t.visibility <> 'restricted' OR t.discourse_id = ANY($4))
+             ORDER BY m.matched_at DESC, m.post_id DESC
+             LIMIT $5",
+        )
+        .bind(user_id)
+        .bind(search_id)
+        .bind(unread_only)
+        .bind(access.restricted())
+        .bind(limit)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+
+    /// Mark the user's matches up to `until` as read, optionally of one search only,
+    /// returns the number of matches marked
+    pub async fn mark_read(
+        user_id: Uuid,
+        search_id: Option<Uuid>,
+        until: DateTime<Utc>,
+        state: &AppState,
+    ) -> Result<u64, sqlx::Error> {
+        let result = query(
+            "UPDATE saved_search_matches m SET read_at = NOW()
+             FROM saved_searches s
+             WHERE s.search_id = m.search_id AND s.user_id = $1
+                AND ($2::UUID IS NULL OR m.search_id = $2)
+                AND m.read_at IS NULL
+                AND m.matched_at <= $3",
+        )
+        .bind(user_id)
+        .bind(search_id)
+        .bind(until)
+        .execute(&state.database.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
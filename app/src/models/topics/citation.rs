@@ -0,0 +1,245 @@
+use std::collections::{BTreeSet, HashSet};
+
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as, query_scalar};
+use tracing::warn;
+
+use crate::state::AppState;
+
+/// Fence of the block of claims models end their summaries with
+const CLAIMS_FENCE: &str = "```claims";
+
+/// A claim as written by the model, `posts` are the post_numbers it cites
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Claim {
+    pub claim: String,
+    #[serde(default)]
+    pub posts: Vec<i32>,
+}
+
+/// A claim of a topic summary with the posts backing it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow, Object)]
+pub struct SummaryCitation {
+    pub summary_id: i32,
+    /// Order of the claim in the summary
+    pub position: i32,
+    pub claim: String,
+    /// Posts of the topic, linked as `/t/:discourse_id/:topic_id#post_number`
+    pub post_numbers: Vec<i32>,
+}
+
+/// Split a generated summary into its markdown and the trailing ```` ```claims ```` block
+///
+/// A block that isn't valid JSON is still removed from the text, it yields no claims.
+pub fn extract_claims(summary_text: &str) -> (String, Vec<Claim>) {
+    let Some(start) = summary_text.rfind(CLAIMS_FENCE) else {
+        return (summary_text.trim().to_string(), Vec::new());
+    };
+
+    let block = &summary_text[start + CLAIMS_FENCE.len()..];
+    let (json, rest) = block.split_once("```").unwrap_or((block, ""));
+    let text = format!("{}{}", &summary_text[..start], rest)
+        .trim()
+        .to_string();
+
+    match serde_json::from_str(json) {
+        Ok(claims) => (text, claims),
+        Err(e) => {
+            warn!("Summary has an invalid claims block: {}", e);
+            (text, Vec::new())
+        }
+    }
+}
+
+/// Withholds the ```` ```claims ```` block from a summary streamed in chunks
+///
+/// Text that could be the start of the fence is held back until the next chunk settles
+/// it, everything from the fence on is never shown.
+#[derive(Debug, Default)]
+pub struct ClaimsFilter {
+    pending: String,
+    in_block: bool,
+}
+
+impl ClaimsFilter {
+    /// The text of `chunk` that can be shown so far
+    pub fn push(&mut self, chunk: &str) -> String {
+        if self.in_block {
+            return String::new();
+        }
+
+        self.pending.push_str(chunk);
+        if let Some(start) = self.pending.find(CLAIMS_FENCE) {
+            self.in_block = true;
+            self.pending.truncate(start);
+            return std::mem::take(&mut self.pending);
+        }
+
+        // The fence is ASCII, so a held back suffix starts on a char boundary
+        let held = (1..CLAIMS_FENCE.len())
+            .rev()
+            .find(|&len| self.pending.ends_with(&CLAIMS_FENCE[..len]))
+            .unwrap_or(0);
+        let rest = self.pending.split_off(self.pending.len() - held);
+        std::mem::replace(&mut self.pending, rest)
+    }
+
+    /// Text held back when the summary ended without a block
+    pub fn finish(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Keep the citations of posts that exist, claims left without any are dropped
+pub fn validate_claims(claims: Vec<Claim>, post_numbers: &HashSet<i32>) -> Vec<Claim> {
+    claims
+        .into_iter()
+        .filter_map(|claim| {
+            let posts: BTreeSet<i32> = claim
+                .posts
+                .into_iter()
+                .filter(|post_number| post_numbers.contains(post_number))
+                .collect();
+            let text = claim.claim.trim();
+
+            (!posts.is_empty() && !text.is_empty()).then(|| Claim {
+                claim: text.to_string(),
+                posts: posts.into_iter().collect(),
+            })
+        })
+        .collect()
+}
+
+impl SummaryCitation {
+    pub async fn find_by_summary_id(
+        summary_id: i32,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT * FROM topic_summary_citations WHERE summary_id = $1 ORDER BY position ASC",
+        )
+        .bind(summary_id)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+
+    /// Store the claims of a summary, citations of posts not in the topic are left out
+    pub async fn create_for_summary(
+        summary_id: i32,
+        discourse_id: &str,
+        topic_id: i32,
+        claims: Vec<Claim>,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        if claims.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let cited: Vec<i32> = claims
+            .iter()
+            .flat_map(|claim| claim.posts.iter().copied())
+            .collect();
+        let existing: Vec<i32> = query_scalar(
+            "SELECT post_number FROM posts WHERE discourse_id = $1 AND topic_id = $2 AND deleted_at IS NULL AND post_number = ANY($3)",
+        )
+        .bind(discourse_id)
+        .bind(topic_id)
+        .bind(&cited)
+        .fetch_all(&state.database.pool)
+        .await?;
+
+        let claims = validate_claims(claims, &existing.into_iter().collect());
+
+        let mut tx = state.database.pool.begin().await?;
+        let mut citations = Vec::with_capacity(claims.len());
+        for (position, claim) in claims.into_iter().enumerate() {
+            let citation = Self {
+                summary_id,
+                position: position as i32,
+                claim: claim.claim,
+                post_numbers: claim.posts,
+            };
+
+            query(
+                "INSERT INTO topic_summary_citations (summary_id, position, claim, post_numbers) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(citation.summary_id)
+            .bind(citation.position)
+            .bind(&citation.claim)
+            .bind(&citation.post_numbers)
+            .execute(&mut *tx)
+            .await?;
+
+            citations.push(citation);
+        }
+        tx.commit().await?;
+
+        Ok(citations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_claims() {
+        let summary = "## Summary\n\nEOF is delayed [@vitalik](/u/magicians/vitalik).\n\n```claims\n[\n  {\"claim\": \"EOF is delayed\", \"posts\": [4, 2, 4]},\n  {\"claim\": \"Nobody objects\", \"posts\": [99]},\n  {\"claim\": \" \", \"posts\": [1]}\n]\n```\n";
+
+        let (text, claims) = extract_claims(summary);
+        assert_eq!(
+            text,
+            "## Summary\n\nEOF is delayed [@vitalik](/u/magicians/vitalik)."
+        );
+        assert_eq!(claims.len(), 3);
+
+        let existing = HashSet::from([1, 2, 3, 4]);
+        assert_eq!(
+            validate_claims(claims, &existing),
+            vec![Claim {
+                claim: "EOF is delayed".to_string(),
+                posts: vec![2, 4],
+            }]
+        );
+
+        // Summaries without claims are kept as they are
+        assert_eq!(
+            extract_claims("Just text\n"),
+            ("Just text".to_string(), Vec::new())
+        );
+
+        // A broken block is dropped from the text
+        let (text, claims) = extract_claims("Text\n```claims\n[{\"claim\": ");
+        assert_eq!(text, "Text");
+        assert!(claims.is_empty());
+    }
+
+    #[test]
+    fn test_claims_filter() {
+        let summary = "EOF is `delayed` ✓.\n\n```claims\n[{\"claim\": \"EOF is delayed\", \"posts\": [4]}]\n```\n";
+        let shown = "EOF is `delayed` ✓.\n\n";
+
+        // Split at every position, the fence is never shown whichever chunk it lands in
+        for (split, _) in summary.char_indices() {
+            let mut filter = ClaimsFilter::default();
+            let mut text = filter.push(&summary[..split]);
+            text.push_str(&filter.push(&summary[split..]));
+            text.push_str(&filter.finish());
+            assert_eq!(text, shown, "split at {}", split);
+        }
+
+        let mut filter = ClaimsFilter::default();
+        let text: String = summary
+            .chars()
+            .map(|c| filter.push(&c.to_string()))
+            .collect();
+        assert_eq!(text + &filter.finish(), shown);
+
+        // Backticks at the end of a summary without claims are shown once it ends
+        let mut filter = ClaimsFilter::default();
+        assert_eq!(filter.push("Use `"), "Use ");
+        assert_eq!(filter.push("eth_call``"), "`eth_call");
+        assert_eq!(filter.finish(), "``");
+    }
+}
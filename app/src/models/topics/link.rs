@@ -0,0 +1,109 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as};
+
+use crate::state::AppState;
+
+/// Kind of reference a topic links to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum LinkKind {
+    GithubIssue,
+    GithubPr,
+    Eip,
+}
+
+impl LinkKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::GithubIssue => "github_issue",
+            Self::GithubPr => "github_pr",
+            Self::Eip => "eip",
+        }
+    }
+}
+
+/// A reference found in a topic by one of the instance's issue rules
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LinkedIssue {
+    pub kind: LinkKind,
+    /// `owner/repo` for GitHub links, empty for EIPs
+    pub repository: String,
+    pub number: i32,
+    /// The text the rule matched, usually the url
+    pub reference: String,
+}
+
+impl LinkedIssue {
+    /// The `ethereum/pm` issue kept on `topics.pm_issue` for existing clients
+    pub fn pm_issue(links: &[LinkedIssue]) -> Option<i32> {
+        links
+            .iter()
+            .find(|link| {
+                link.kind == LinkKind::GithubIssue
+                    && link.repository.eq_ignore_ascii_case("ethereum/pm")
+            })
+            .map(|link| link.number)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Object)]
+pub struct TopicLink {
+    pub discourse_id: String,
+    pub topic_id: i32,
+    /// `github_issue`, `github_pr` or `eip`
+    pub kind: String,
+    pub repository: String,
+    pub number: i32,
+    pub reference: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TopicLink {
+    /// Replace the links of a topic with the ones found in its latest version
+    pub async fn replace_for_topic(
+        discourse_id: &str,
+        topic_id: i32,
+        links: &[LinkedIssue],
+        state: &AppState,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = state.database.pool.begin().await?;
+
+        query("DELETE FROM topic_links WHERE discourse_id = $1 AND topic_id = $2")
+            .bind(discourse_id)
+            .bind(topic_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for link in links {
+            query("INSERT INTO topic_links (discourse_id, topic_id, kind, repository, number, reference) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING")
+                .bind(discourse_id)
+                .bind(topic_id)
+                .bind(link.kind.as_str())
+                .bind(&link.repository)
+                .bind(link.number)
+                .bind(&link.reference)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn find_by_topic(
+        discourse_id: &str,
+        topic_id: i32,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT * FROM topic_links WHERE discourse_id = $1 AND topic_id = $2 ORDER BY kind, repository, number",
+        )
+        .bind(discourse_id)
+        .bind(topic_id)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+}
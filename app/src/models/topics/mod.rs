@@ -1,19 +1,28 @@
 use chrono::{DateTime, Utc};
 use opentelemetry_http::HttpError;
 use poem_openapi::Object;
-use regex::Regex;
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, query, query_as};
-use tracing::info;
+use sqlx::{prelude::FromRow, query, query_as, query_scalar};
+use tracing::{error, info};
+use citation::{SummaryCitation, extract_claims};
 use post::Post;
 
-use crate::state::AppState;
+use crate::{models::user::notification::Notification, state::AppState};
 
 use super::discourse::topic::DiscourseTopicResponse;
 
+pub mod citation;
+pub mod link;
 pub mod post;
+pub mod summary_chunk;
 
 const POSTS_PER_PAGE: usize = 100;
+const TOPICS_PER_PAGE: i64 = 20;
+
+/// Topic in a category anyone can read
+pub const VISIBILITY_PUBLIC: &str = "public";
+/// Topic in a `read_restricted` category, only indexed through an API key
+pub const VISIBILITY_RESTRICTED: &str = "restricted";
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Object, Clone)]
 pub struct Topic {
@@ -30,6 +39,56 @@ pub struct Topic {
     pub bumped_at: Option<DateTime<Utc>>,
     pub pm_issue: Option<i32>,
     pub extra: Option<serde_json::Value>,
+    /// `public` or `restricted`
+    pub visibility: String,
+    pub category_id: Option<i32>,
+    pub tags: Vec<String>,
+    /// Last change to a field of the topic's search document
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Discourse instances whose restricted topics the current viewer may read
+#[derive(Debug, Clone, Default)]
+pub struct TopicAccess {
+    restricted: Vec<String>,
+}
+
+impl TopicAccess {
+    /// Only public topics, used for anonymous requests
+    pub fn public() -> Self {
+        Self::default()
+    }
+
+    pub fn with_restricted(discourse_ids: Vec<String>) -> Self {
+        Self {
+            restricted: discourse_ids,
+        }
+    }
+
+    /// Instances whose restricted topics are included
+    pub fn restricted(&self) -> &[String] {
+        &self.restricted
+    }
+
+    pub fn can_view(&self, topic: &Topic) -> bool {
+        topic.visibility != VISIBILITY_RESTRICTED || self.restricted.contains(&topic.discourse_id)
+    }
+
+    /// Meilisearch filter matching the documents the viewer may read
+    ///
+    /// Documents indexed before visibility was tracked have no `visibility`
+    /// field, `!=` keeps matching those.
+    pub fn meili_filter(&self) -> String {
+        if self.restricted.is_empty() {
+            format!("visibility != {}", VISIBILITY_RESTRICTED)
+        } else {
+            format!(
+                "(visibility != {} OR discourse_id IN [{}])",
+                VISIBILITY_RESTRICTED,
+                self.restricted.join(", ")
+            )
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Object)]
@@ -42,6 +101,65 @@ pub struct TopicSummary {
     pub created_at: DateTime<Utc>,
 }
 
+/// A summary with the claims it makes, as served by the API
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct CitedTopicSummary {
+    #[serde(flatten)]
+    #[oai(flatten)]
+    pub summary: TopicSummary,
+    /// Empty for summaries written before claims were requested
+    pub citations: Vec<SummaryCitation>,
+}
+
+impl CitedTopicSummary {
+    pub async fn from_summary(summary: TopicSummary, state: &AppState) -> Result<Self, sqlx::Error> {
+        let citations = SummaryCitation::find_by_summary_id(summary.summary_id, state).await?;
+
+        Ok(Self { summary, citations })
+    }
+}
+
+impl TopicSummary {
+    /// Store a fresh summary and notify the subscribers of the topic
+    ///
+    /// The block of claims the model ends the summary with is stored as citations
+    pub async fn create(
+        discourse_id: &str,
+        topic_id: i32,
+        based_on: DateTime<Utc>,
+        summary_text: &str,
+        state: &AppState,
+    ) -> Result<Self, sqlx::Error> {
+        let (summary_text, claims) = extract_claims(summary_text);
+        let summary: Self = query_as(
+            "INSERT INTO topic_summaries (discourse_id, topic_id, based_on, summary_text, created_at) VALUES ($1, $2, $3, $4, NOW()) RETURNING *",
+        )
+        .bind(discourse_id)
+        .bind(topic_id)
+        .bind(based_on)
+        .bind(&summary_text)
+        .fetch_one(&state.database.pool)
+        .await?;
+
+        if let Err(e) =
+            SummaryCitation::create_for_summary(summary.summary_id, discourse_id, topic_id, claims, state)
+                .await
+        {
+            error!("Error storing citations of summary {}: {:?}", summary.summary_id, e);
+        }
+
+        let notified = match state.discourse.subscription_readers(discourse_id, state).await {
+            Ok(readers) => Notification::record_summary(&summary, &readers, state).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = notified {
+            error!("Error notifying subscribers of summary {}: {:?}", summary.summary_id, e);
+        }
+
+        Ok(summary)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct User {
     pub user_id: i32,
@@ -53,24 +171,11 @@ pub struct User {
 
 impl Topic {
     pub fn from_discourse(discourse_id: &str, topic: &DiscourseTopicResponse) -> Self {
-        let mut pm_issue = None;
-
-        if let Some(category_id) = topic.extra.get("category_id") {
-            let category_id = category_id.as_i64().unwrap();
-
-            // If "Protocol Calls"
-            if category_id == 63 {
-                info!("Found category_id: {}", category_id);
-                // Get first post
-                if let Some(first_post) = topic.post_stream.posts.first() {
-                    info!("Found first post: {}", first_post.cooked);
-                    if let Some(found_pm_issue) = try_extract_pm_issue(&first_post.cooked) {
-                        info!("Found pm_issue: {}", found_pm_issue);
-                        pm_issue = Some(found_pm_issue);
-                    }
-                }
-            }
-        }
+        let category_id = topic
+            .extra
+            .get("category_id")
+            .and_then(|id| id.as_i64())
+            .map(|id| id as i32);
 
         Self {
             discourse_id: discourse_id.to_string(),
@@ -85,36 +190,59 @@ impl Topic {
             extra: Some(topic.extra.clone()),
             created_at: topic.created_at,
             view_count: topic.views,
-            pm_issue,
+            // Filled in by the indexer from the instance's issue rules
+            pm_issue: None,
+            visibility: VISIBILITY_PUBLIC.to_string(),
+            category_id,
+            tags: tags_from_extra(&topic.extra),
+            // Set by the database on upsert
+            updated_at: Utc::now(),
         }
     }
 
-    pub async fn upsert(&self, state: &AppState) -> Result<(), sqlx::Error> {
-        query!("INSERT INTO topics (discourse_id, topic_id, title, slug, post_count, view_count, like_count, image_url, created_at, last_post_at, bumped_at, extra, pm_issue) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) ON CONFLICT (discourse_id, topic_id) DO UPDATE SET discourse_id=$1, topic_id=$2, title=$3, slug=$4, post_count=$5, view_count=$6, like_count=$7, image_url=$8, created_at=$9, last_post_at=$10, bumped_at=$11, extra=$12, pm_issue=$13",
-            self.discourse_id,
-            self.topic_id,
-            self.title,
-            self.slug,
-            self.post_count,
-            self.view_count,
-            self.like_count,
-            self.image_url,
-            self.created_at,
-            self.last_post_at,
-            self.bumped_at,
-            self.extra,
-            self.pm_issue,
-        )
-        .execute(&state.database.pool)
+    /// Insert or update the topic, `updated_at` is only moved when a field of its search
+    /// document changed
+    pub async fn upsert(&mut self, state: &AppState) -> Result<(), sqlx::Error> {
+        let mut tx = state.database.pool.begin().await?;
+
+        self.updated_at = query_scalar("INSERT INTO topics (discourse_id, topic_id, title, slug, post_count, view_count, like_count, image_url, created_at, last_post_at, bumped_at, extra, pm_issue, visibility, category_id, tags) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16) ON CONFLICT (discourse_id, topic_id) DO UPDATE SET discourse_id=$1, topic_id=$2, title=$3, slug=$4, post_count=$5, view_count=$6, like_count=$7, image_url=$8, created_at=$9, last_post_at=$10, bumped_at=$11, extra=$12, pm_issue=$13, visibility=$14, category_id=$15, tags=$16, updated_at = CASE WHEN (topics.title, topics.slug, topics.pm_issue, topics.visibility, topics.category_id, topics.tags) IS DISTINCT FROM ($3, $4, $13, $14, $15, $16) THEN NOW() ELSE topics.updated_at END RETURNING updated_at")
+        .bind(&self.discourse_id)
+        .bind(self.topic_id)
+        .bind(&self.title)
+        .bind(&self.slug)
+        .bind(self.post_count)
+        .bind(self.view_count)
+        .bind(self.like_count)
+        .bind(&self.image_url)
+        .bind(self.created_at)
+        .bind(self.last_post_at)
+        .bind(self.bumped_at)
+        .bind(&self.extra)
+        .bind(self.pm_issue)
+        .bind(&self.visibility)
+        .bind(self.category_id)
+        .bind(&self.tags)
+        .fetch_one(&mut *tx)
         .await?;
+
+        query("INSERT INTO tags (discourse_id, name) SELECT $1, unnest($2::TEXT[]) ON CONFLICT DO NOTHING")
+            .bind(&self.discourse_id)
+            .bind(&self.tags)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
         Ok(())
     }
 
-    pub async fn get_by_latest_post_at(state: &AppState) -> Result<Vec<Self>, sqlx::Error> {
-        let topics = query_as!(
-            Self,
-            "SELECT * FROM topics ORDER BY last_post_at DESC LIMIT 20"
+    pub async fn get_by_latest_post_at(
+        access: &TopicAccess,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let topics = query_as(
+            "SELECT * FROM topics WHERE (visibility <> 'restricted' OR discourse_id = ANY($1)) ORDER BY last_post_at DESC LIMIT 20",
         )
+        .bind(&access.restricted)
         .fetch_all(&state.database.pool)
         .await?;
 
@@ -122,11 +250,57 @@ impl Topic {
     }
 
     // order by views and require that last_post_at is within 14 days
-    pub async fn get_by_trending(state: &AppState) -> Result<Vec<Self>, sqlx::Error> {
-        let topics = query_as!(
-            Self,
-            "SELECT * FROM topics WHERE last_post_at > NOW() - INTERVAL '14 days' ORDER BY view_count DESC LIMIT 20"
+    pub async fn get_by_trending(
+        access: &TopicAccess,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let topics = query_as(
+            "SELECT * FROM topics WHERE last_post_at > NOW() - INTERVAL '14 days' AND (visibility <> 'restricted' OR discourse_id = ANY($1)) ORDER BY view_count DESC LIMIT 20",
+        )
+        .bind(&access.restricted)
+        .fetch_all(&state.database.pool)
+        .await?;
+
+        Ok(topics)
+    }
+
+    pub async fn get_by_category(
+        discourse_id: &str,
+        category_id: i32,
+        page: i32,
+        access: &TopicAccess,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let topics = query_as(
+            "SELECT * FROM topics WHERE discourse_id = $1 AND category_id = $2 AND (visibility <> 'restricted' OR discourse_id = ANY($3)) ORDER BY last_post_at DESC LIMIT $4 OFFSET $5",
+        )
+        .bind(discourse_id)
+        .bind(category_id)
+        .bind(&access.restricted)
+        .bind(TOPICS_PER_PAGE)
+        .bind((page.max(1) as i64 - 1) * TOPICS_PER_PAGE)
+        .fetch_all(&state.database.pool)
+        .await?;
+
+        Ok(topics)
+    }
+
+    /// Topics carrying `tag`, across every instance unless `discourse_id` is given
+    pub async fn get_by_tag(
+        tag: &str,
+        discourse_id: Option<&str>,
+        page: i32,
+        access: &TopicAccess,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let topics = query_as(
+            "SELECT * FROM topics WHERE $1 = ANY(tags) AND ($2::TEXT IS NULL OR discourse_id = $2) AND (visibility <> 'restricted' OR discourse_id = ANY($3)) ORDER BY last_post_at DESC LIMIT $4 OFFSET $5",
         )
+        .bind(tag)
+        .bind(discourse_id)
+        .bind(&access.restricted)
+        .bind(TOPICS_PER_PAGE)
+        .bind((page.max(1) as i64 - 1) * TOPICS_PER_PAGE)
         .fetch_all(&state.database.pool)
         .await?;
 
@@ -138,24 +312,20 @@ impl Topic {
         topic_id: i32,
         state: &AppState,
     ) -> Result<Self, sqlx::Error> {
-        let topic = query_as!(
-            Self,
-            "SELECT * FROM topics WHERE discourse_id = $1 AND topic_id = $2",
-            discourse_id,
-            topic_id
-        )
-        .fetch_one(&state.database.pool)
-        .await?;
+        let topic = query_as("SELECT * FROM topics WHERE discourse_id = $1 AND topic_id = $2")
+            .bind(discourse_id)
+            .bind(topic_id)
+            .fetch_one(&state.database.pool)
+            .await?;
         Ok(topic)
     }
 
     pub async fn get_first_post(&self, state: &AppState) -> Result<Post, sqlx::Error> {
-        let post = query_as!(
-            Post,
-            "SELECT * FROM posts WHERE discourse_id = $1 AND topic_id = $2 ORDER BY post_number ASC LIMIT 1",
-            self.discourse_id,
-            self.topic_id
+        let post = query_as(
+            "SELECT * FROM posts WHERE discourse_id = $1 AND topic_id = $2 AND deleted_at IS NULL ORDER BY post_number ASC LIMIT 1",
         )
+        .bind(&self.discourse_id)
+        .bind(self.topic_id)
         .fetch_one(&state.database.pool)
         .await?;
         Ok(post)
@@ -210,6 +380,11 @@ impl Topic {
             return Ok(summary);
         }
 
+        // Without a model provider an outdated summary beats none
+        if !state.workshop.is_enabled() {
+            return Ok(summary);
+        }
+
         Self::create_new_summary(discourse_id, topic_id, state, &topic).await
     }
 
@@ -236,7 +411,8 @@ impl Topic {
                     // The summary should already be saved by the background task, but let's check
                     if let Ok(existing_summary) = query_as!(
                         TopicSummary,
-                        "SELECT * FROM topic_summaries WHERE topic_id = $1 ORDER BY based_on DESC LIMIT 1",
+                        "SELECT * FROM topic_summaries WHERE discourse_id = $1 AND topic_id = $2 ORDER BY based_on DESC LIMIT 1",
+                        discourse_id,
                         topic_id
                     ).fetch_optional(&state.database.pool).await {
                         if let Some(summary) = existing_summary {
@@ -253,16 +429,9 @@ impl Topic {
                     let based_on_datetime =
                         DateTime::from_timestamp(based_on as i64, 0).unwrap_or_else(|| Utc::now());
 
-                    let summary = query_as!(
-                        TopicSummary,
-                        "INSERT INTO topic_summaries (discourse_id, topic_id, based_on, summary_text, created_at) VALUES ($1, $2, $3, $4, NOW()) RETURNING *",
-                        discourse_id,
-                        topic_id,
-                        based_on_datetime,
-                        summary_text
-                    )
-                    .fetch_one(&state.database.pool)
-                    .await?;
+                    let summary =
+                        TopicSummary::create(discourse_id, topic_id, based_on_datetime, &summary_text, state)
+                            .await?;
 
                     return Ok(summary);
                 }
@@ -289,16 +458,8 @@ impl Topic {
         let based_on_datetime =
             DateTime::from_timestamp(based_on as i64, 0).unwrap_or_else(|| Utc::now());
 
-        let summary = query_as!(
-            TopicSummary,
-            "INSERT INTO topic_summaries (discourse_id, topic_id, based_on, summary_text, created_at) VALUES ($1, $2, $3, $4, NOW()) RETURNING *",
-            discourse_id,
-            topic_id,
-            based_on_datetime,
-            summary
-            )
-            .fetch_one(&state.database.pool)
-            .await?;
+        let summary =
+            TopicSummary::create(discourse_id, topic_id, based_on_datetime, &summary, state).await?;
 
         info!(
             "Created new summary for topic_id: {} with summary_id: {}",
@@ -309,10 +470,32 @@ impl Topic {
     }
 }
 
-// Match for <a href=\"https://github.com/ethereum/pm/issues/1518\">GitHub Issue</a>
-fn try_extract_pm_issue(cooked: &str) -> Option<i32> {
-    let re = Regex::new(r#"https://github\.com/ethereum/pm/issues/(\d+)"#).unwrap();
-    let caps = re.captures(cooked);
+/// Tags are plain names on most instances and `{ "name": .. }` objects on newer ones
+fn tags_from_extra(extra: &serde_json::Value) -> Vec<String> {
+    let Some(tags) = extra.get("tags").and_then(|tags| tags.as_array()) else {
+        return Vec::new();
+    };
+
+    tags.iter()
+        .filter_map(|tag| match tag {
+            serde_json::Value::String(name) => Some(name.clone()),
+            tag => tag.get("name").and_then(|name| name.as_str()).map(str::to_string),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    caps.map(|caps| caps.get(1).unwrap().as_str().parse().unwrap())
+    #[test]
+    fn test_tags_from_extra() {
+        let extra = serde_json::json!({ "tags": ["eip", "erc-20"] });
+        assert_eq!(tags_from_extra(&extra), vec!["eip", "erc-20"]);
+
+        let extra = serde_json::json!({ "tags": [{ "id": 1, "name": "eip", "slug": "eip" }] });
+        assert_eq!(tags_from_extra(&extra), vec!["eip"]);
+
+        assert!(tags_from_extra(&serde_json::json!({})).is_empty());
+    }
 }
@@ -20,12 +20,48 @@ pub struct Post {
     pub cooked: Option<String>,
     pub post_url: Option<String>,
     pub extra: Option<serde_json::Value>,
+    pub version: i32,
+    /// Set once the post disappears from Discourse
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// A version of a post as we saw it
+#[derive(Debug, Serialize, Deserialize, FromRow, Object)]
+pub struct PostRevision {
+    pub discourse_id: String,
+    pub post_id: i32,
+    pub version: i32,
+    pub cooked: Option<String>,
+    /// `updated_at` reported by Discourse for this version
+    pub revised_at: Option<DateTime<Utc>>,
+    /// When the indexer first saw this version
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// What an upsert did to the stored post
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostChange {
+    Created,
+    /// Stored before with a lower version
+    Edited,
+    Unchanged,
+}
+
+impl PostChange {
+    fn from_versions(previous: Option<i32>, version: i32) -> Self {
+        match previous {
+            None => Self::Created,
+            Some(previous) if previous < version => Self::Edited,
+            Some(_) => Self::Unchanged,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WorkshopPost {
     pub discourse_id: String,
     pub post_id: i32,
+    pub post_number: i32,
     pub user_id: i32,
     pub username: Option<String>,
     pub updated_at: Option<DateTime<Utc>>,
@@ -38,6 +74,7 @@ impl From<Post> for WorkshopPost {
         Self {
             discourse_id: post.discourse_id,
             post_id: post.post_id,
+            post_number: post.post_number,
             user_id: post.user_id,
             updated_at: post.updated_at,
             created_at: post.created_at,
@@ -67,24 +104,73 @@ impl Post {
             cooked: Some(post.cooked),
             post_url: post.post_url,
             extra: Some(extra),
+            version: post.version,
+            deleted_at: None,
         }
     }
 
-    pub async fn upsert(&self, state: &AppState) -> Result<(), sqlx::Error> {
-        query!("INSERT INTO posts (discourse_id, post_id, topic_id, user_id, post_number, updated_at, cooked, post_url, extra) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) ON CONFLICT (discourse_id, post_id) DO UPDATE SET discourse_id=$1, post_id=$2, topic_id=$3, user_id=$4, post_number=$5, updated_at = $6, cooked = $7, post_url = $8, extra = $9",
-            self.discourse_id,
-            self.post_id,
-            self.topic_id,
-            self.user_id,
-            self.post_number,
-            self.updated_at,
-            self.cooked,
-            self.post_url,
-            self.extra,
-        )
-        .execute(&state.database.pool)
+    /// Upsert the post and record its version in `post_revisions`
+    ///
+    /// A post that shows up again after being soft-deleted is restored.
+    pub async fn upsert(&self, state: &AppState) -> Result<PostChange, sqlx::Error> {
+        let mut tx = state.database.pool.begin().await?;
+
+        query("INSERT INTO post_revisions (discourse_id, post_id, version, cooked, revised_at) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (discourse_id, post_id, version) DO NOTHING")
+        .bind(&self.discourse_id)
+        .bind(self.post_id)
+        .bind(self.version)
+        .bind(&self.cooked)
+        .bind(self.updated_at)
+        .execute(&mut *tx)
+        .await?;
+
+        // The CTE reads the row as it was before the upsert
+        let previous: Option<i32> = query_scalar("WITH previous AS (SELECT version FROM posts WHERE discourse_id = $1 AND post_id = $2) INSERT INTO posts (discourse_id, post_id, topic_id, user_id, post_number, updated_at, cooked, post_url, extra, version, created_at, deleted_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, COALESCE($11, NOW()), NULL) ON CONFLICT (discourse_id, post_id) DO UPDATE SET discourse_id=$1, post_id=$2, topic_id=$3, user_id=$4, post_number=$5, updated_at = $6, cooked = $7, post_url = $8, extra = $9, version = $10, created_at = COALESCE($11, posts.created_at), deleted_at = NULL RETURNING (SELECT version FROM previous)")
+        .bind(&self.discourse_id)
+        .bind(self.post_id)
+        .bind(self.topic_id)
+        .bind(self.user_id)
+        .bind(self.post_number)
+        .bind(self.updated_at)
+        .bind(&self.cooked)
+        .bind(&self.post_url)
+        .bind(&self.extra)
+        .bind(self.version)
+        .bind(self.created_at)
+        .fetch_one(&mut *tx)
         .await?;
-        Ok(())
+
+        tx.commit().await?;
+        Ok(PostChange::from_versions(previous, self.version))
+    }
+
+    /// Soft-delete a post, returns whether it was live before
+    pub async fn soft_delete(
+        discourse_id: &str,
+        post_id: i32,
+        state: &AppState,
+    ) -> Result<bool, sqlx::Error> {
+        let result = query("UPDATE posts SET deleted_at = NOW() WHERE discourse_id = $1 AND post_id = $2 AND deleted_at IS NULL")
+            .bind(discourse_id)
+            .bind(post_id)
+            .execute(&state.database.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Soft-delete the live posts of a topic missing from `stream`, returns their ids
+    pub async fn soft_delete_missing(
+        discourse_id: &str,
+        topic_id: i32,
+        stream: &[i32],
+        state: &AppState,
+    ) -> Result<Vec<i32>, sqlx::Error> {
+        query_scalar("UPDATE posts SET deleted_at = NOW() WHERE discourse_id = $1 AND topic_id = $2 AND deleted_at IS NULL AND NOT (post_id = ANY($3)) RETURNING post_id")
+            .bind(discourse_id)
+            .bind(topic_id)
+            .bind(stream)
+            .fetch_all(&state.database.pool)
+            .await
     }
 
     pub async fn find_by_topic_id(
@@ -96,14 +182,13 @@ impl Post {
     ) -> Result<(Vec<Self>, bool), sqlx::Error> {
         let size = size.unwrap_or(POSTS_PER_PAGE as i32);
         let offset = (page - 1) * size;
-        let posts = query_as!(
-            Self,
-            "SELECT * FROM posts WHERE discourse_id = $1 AND topic_id = $2 ORDER BY post_number ASC LIMIT $3 OFFSET $4",
-            discourse_id,
-            topic_id,
-            (size + 1) as i64,
-            offset as i64
+        let posts: Vec<Self> = query_as(
+            "SELECT * FROM posts WHERE discourse_id = $1 AND topic_id = $2 AND deleted_at IS NULL ORDER BY post_number ASC LIMIT $3 OFFSET $4",
         )
+        .bind(discourse_id)
+        .bind(topic_id)
+        .bind((size + 1) as i64)
+        .bind(offset as i64)
         .fetch_all(&state.database.pool)
         .await?;
 
@@ -113,19 +198,69 @@ impl Post {
         Ok((posts, has_more))
     }
 
+    /// Whether any of `posts` is not stored, or stored at a lower version
+    pub async fn any_outdated(
+        discourse_id: &str,
+        posts: &[DiscourseTopicPost],
+        state: &AppState,
+    ) -> Result<bool, sqlx::Error> {
+        let (post_ids, versions): (Vec<i32>, Vec<i32>) =
+            posts.iter().map(|post| (post.id, post.version)).unzip();
+
+        query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM unnest($2::INT[], $3::INT[]) AS fetched(post_id, version) LEFT JOIN posts p ON p.discourse_id = $1 AND p.post_id = fetched.post_id AND p.deleted_at IS NULL WHERE p.version IS NULL OR p.version < fetched.version)",
+        )
+        .bind(discourse_id)
+        .bind(&post_ids)
+        .bind(&versions)
+        .fetch_one(&state.database.pool)
+        .await
+    }
+
     pub async fn count_by_topic_id(
         discourse_id: &str,
         topic_id: i32,
         state: &AppState,
     ) -> Result<i32, sqlx::Error> {
-        let count = query_scalar!(
-            "SELECT COUNT(*) FROM posts WHERE discourse_id = $1 AND topic_id = $2",
-            discourse_id,
-            topic_id
+        let count: i64 = query_scalar(
+            "SELECT COUNT(*) FROM posts WHERE discourse_id = $1 AND topic_id = $2 AND deleted_at IS NULL",
         )
+        .bind(discourse_id)
+        .bind(topic_id)
         .fetch_one(&state.database.pool)
         .await?;
 
-        Ok(count.unwrap_or_default() as i32)
+        Ok(count as i32)
+    }
+
+    /// Revisions of the post at `post_number`, oldest first
+    pub async fn find_revisions(
+        discourse_id: &str,
+        topic_id: i32,
+        post_number: i32,
+        state: &AppState,
+    ) -> Result<Vec<PostRevision>, sqlx::Error> {
+        query_as(
+            "SELECT r.* FROM post_revisions r JOIN posts p ON p.discourse_id = r.discourse_id AND p.post_id = r.post_id WHERE p.discourse_id = $1 AND p.topic_id = $2 AND p.post_number = $3 ORDER BY r.version ASC",
+        )
+        .bind(discourse_id)
+        .bind(topic_id)
+        .bind(post_number)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_post_change_from_versions() {
+        assert_eq!(PostChange::from_versions(None, 1), PostChange::Created);
+        assert_eq!(PostChange::from_versions(Some(1), 2), PostChange::Edited);
+        assert_eq!(PostChange::from_versions(Some(2), 2), PostChange::Unchanged);
+        // A stale page crawled after a newer webhook delivery
+        assert_eq!(PostChange::from_versions(Some(3), 2), PostChange::Unchanged);
     }
 }
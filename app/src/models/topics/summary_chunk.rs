@@ -0,0 +1,107 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as};
+
+use crate::state::AppState;
+
+/// Consecutive posts summarized together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRange {
+    pub first_post: i32,
+    pub last_post: i32,
+    /// Later posts start the next chunk, the range won't grow anymore
+    pub sealed: bool,
+}
+
+impl ChunkRange {
+    pub fn contains(&self, post_number: i32) -> bool {
+        (self.first_post..=self.last_post).contains(&post_number)
+    }
+}
+
+/// Summary of consecutive posts of a long topic, the reduce step combines them
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TopicSummaryChunk {
+    pub discourse_id: String,
+    pub topic_id: i32,
+    pub first_post: i32,
+    pub last_post: i32,
+    pub sealed: bool,
+    /// Newest change to a post of the range when it was summarized
+    pub based_on: DateTime<Utc>,
+    pub summary_text: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TopicSummaryChunk {
+    pub fn range(&self) -> ChunkRange {
+        ChunkRange {
+            first_post: self.first_post,
+            last_post: self.last_post,
+            sealed: self.sealed,
+        }
+    }
+
+    pub async fn find_by_topic(
+        discourse_id: &str,
+        topic_id: i32,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT * FROM topic_summary_chunks WHERE discourse_id = $1 AND topic_id = $2 ORDER BY first_post ASC",
+        )
+        .bind(discourse_id)
+        .bind(topic_id)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+
+    pub async fn upsert(
+        discourse_id: &str,
+        topic_id: i32,
+        range: &ChunkRange,
+        based_on: DateTime<Utc>,
+        summary_text: &str,
+        state: &AppState,
+    ) -> Result<Self, sqlx::Error> {
+        query_as(
+            "INSERT INTO topic_summary_chunks (discourse_id, topic_id, first_post, last_post, sealed, based_on, summary_text)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (discourse_id, topic_id, first_post) DO UPDATE SET
+                last_post = EXCLUDED.last_post,
+                sealed = EXCLUDED.sealed,
+                based_on = EXCLUDED.based_on,
+                summary_text = EXCLUDED.summary_text,
+                created_at = NOW()
+             RETURNING *",
+        )
+        .bind(discourse_id)
+        .bind(topic_id)
+        .bind(range.first_post)
+        .bind(range.last_post)
+        .bind(range.sealed)
+        .bind(based_on)
+        .bind(summary_text)
+        .fetch_one(&state.database.pool)
+        .await
+    }
+
+    /// Drop chunks whose range no longer exists, e.g. after the chunks were planned anew
+    pub async fn delete_except(
+        discourse_id: &str,
+        topic_id: i32,
+        first_posts: &[i32],
+        state: &AppState,
+    ) -> Result<u64, sqlx::Error> {
+        let result = query(
+            "DELETE FROM topic_summary_chunks WHERE discourse_id = $1 AND topic_id = $2 AND first_post <> ALL($3)",
+        )
+        .bind(discourse_id)
+        .bind(topic_id)
+        .bind(first_posts)
+        .execute(&state.database.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,200 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as};
+use uuid::Uuid;
+
+use crate::{models::topics::TopicAccess, state::AppState};
+
+/// Topics listed per digest, most active first
+const DIGEST_TOPICS: i64 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum DigestSchedule {
+    Off,
+    Daily,
+    Weekly,
+}
+
+impl DigestSchedule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+        }
+    }
+}
+
+/// When a user receives digest emails, stored in `digest_preferences`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, Object)]
+pub struct DigestPreferences {
+    pub user_id: Uuid,
+    /// `off`, `daily` or `weekly`
+    pub schedule: String,
+    /// Hour of the day in UTC
+    pub send_hour: i32,
+    /// ISO day of the week for weekly digests, 1 is Monday
+    pub weekday: i32,
+    /// Secret of the one-click unsubscribe link
+    #[oai(skip)]
+    #[serde(skip)]
+    pub unsubscribe_token: Uuid,
+    pub last_sent_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct DigestPreferencesInput {
+    pub schedule: DigestSchedule,
+    /// Hour of the day in UTC, 0 to 23, defaults to 8
+    pub send_hour: Option<i32>,
+    /// ISO day of the week for weekly digests, 1 (Monday) to 7, defaults to 1
+    pub weekday: Option<i32>,
+}
+
+/// Preferences of a user with an email address
+#[derive(Debug, Clone, FromRow)]
+pub struct DigestRecipient {
+    #[sqlx(flatten)]
+    pub preferences: DigestPreferences,
+    pub email: String,
+    pub display_name: Option<String>,
+    /// Decides with `email` which restricted topics the digest may list
+    pub sso_provider: String,
+}
+
+/// A followed topic with posts since the last digest
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DigestTopic {
+    pub discourse_id: String,
+    pub topic_id: i32,
+    pub title: String,
+    pub new_posts: i64,
+    pub last_post_at: Option<DateTime<Utc>>,
+    /// Latest summary of the topic
+    pub summary: Option<String>,
+}
+
+impl DigestPreferences {
+    /// Preferences of the user, digests are off until they are set
+    pub async fn find_or_create(user_id: Uuid, state: &AppState) -> Result<Self, sqlx::Error> {
+        query_as(
+            "INSERT INTO digest_preferences (user_id) VALUES ($1) ON CONFLICT (user_id) DO UPDATE SET user_id = EXCLUDED.user_id RETURNING *",
+        )
+        .bind(user_id)
+        .fetch_one(&state.database.pool)
+        .await
+    }
+
+    pub async fn upsert(
+        user_id: Uuid,
+        input: &DigestPreferencesInput,
+        state: &AppState,
+    ) -> Result<Self, sqlx::Error> {
+        query_as(
+            "INSERT INTO digest_preferences (user_id, schedule, send_hour, weekday) VALUES ($1, $2, COALESCE($3, 8), COALESCE($4, 1)) ON CONFLICT (user_id) DO UPDATE SET schedule = $2, send_hour = COALESCE($3, digest_preferences.send_hour), weekday = COALESCE($4, digest_preferences.weekday), updated_at = NOW() RETURNING *",
+        )
+        .bind(user_id)
+        .bind(input.schedule.as_str())
+        .bind(input.send_hour)
+        .bind(input.weekday)
+        .fetch_one(&state.database.pool)
+        .await
+    }
+
+    /// Turn digests off for the owner of the token, `false` when the token is unknown
+    pub async fn unsubscribe(
+        unsubscribe_token: Uuid,
+        state: &AppState,
+    ) -> Result<bool, sqlx::Error> {
+        let result = query(
+            "UPDATE digest_preferences SET schedule = 'off', updated_at = NOW() WHERE unsubscribe_token = $1",
+        )
+        .bind(unsubscribe_token)
+        .execute(&state.database.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn mark_sent(
+        user_id: Uuid,
+        sent_at: DateTime<Utc>,
+        state: &AppState,
+    ) -> Result<(), sqlx::Error> {
+        query("UPDATE digest_preferences SET last_sent_at = $2 WHERE user_id = $1")
+            .bind(user_id)
+            .bind(sent_at)
+            .execute(&state.database.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl DigestRecipient {
+    /// Users with digests on and an email address
+    pub async fn find_enabled(state: &AppState) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT d.*, u.email, u.display_name, u.sso_provider FROM digest_preferences d JOIN users u ON u.user_id = d.user_id WHERE d.schedule <> 'off' AND u.email IS NOT NULL AND u.email <> ''",
+        )
+        .fetch_all(&state.database.pool)
+        .await
+    }
+
+    /// The user, whatever their schedule, `None` without an email address
+    pub async fn find_by_user_id(
+        user_id: Uuid,
+        state: &AppState,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        DigestPreferences::find_or_create(user_id, state).await?;
+
+        query_as(
+            "SELECT d.*, u.email, u.display_name, u.sso_provider FROM digest_preferences d JOIN users u ON u.user_id = d.user_id WHERE d.user_id = $1 AND u.email IS NOT NULL AND u.email <> ''",
+        )
+        .bind(user_id)
+        .fetch_optional(&state.database.pool)
+        .await
+    }
+}
+
+impl DigestTopic {
+    /// Followed, unmuted topics of the user with posts created after `since`
+    ///
+    /// Restricted topics are only listed for instances in `access`, the user may have
+    /// lost access since subscribing.
+    pub async fn find_active(
+        user_id: Uuid,
+        since: DateTime<Utc>,
+        access: &TopicAccess,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT t.discourse_id, t.topic_id, t.title, COUNT(*) AS new_posts, MAX(p.created_at) AS last_post_at,
+                (SELECT ts.summary_text FROM topic_summaries ts
+                 WHERE ts.discourse_id = t.discourse_id AND ts.topic_id = t.topic_id
+                 ORDER BY ts.based_on DESC LIMIT 1) AS summary
+             FROM subscriptions s
+             JOIN topics t ON t.discourse_id = s.discourse_id AND t.topic_id = s.topic_id
+             JOIN posts p ON p.discourse_id = t.discourse_id AND p.topic_id = t.topic_id
+             WHERE s.user_id = $1
+                AND NOT s.muted
+                AND p.created_at > $2
+                AND p.deleted_at IS NULL
+                AND (t.visibility <> 'restricted' OR t.discourse_id = ANY($3))
+             GROUP BY t.discourse_id, t.topic_id, t.title
+             ORDER BY new_posts DESC, last_post_at DESC
+             LIMIT $4",
+        )
+        .bind(user_id)
+        .bind(since)
+        .bind(access.restricted())
+        .bind(DIGEST_TOPICS)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+}
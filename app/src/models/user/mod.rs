@@ -5,6 +5,10 @@ use chrono::{DateTime, Utc};
 use anyhow::Result;
 use poem_openapi::Object;
 
+pub mod digest;
+pub mod notification;
+pub mod subscription;
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow, Object)]
 pub struct User {
     pub user_id: Uuid,
@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as};
+use uuid::Uuid;
+
+use crate::{
+    models::topics::{TopicAccess, TopicSummary},
+    state::AppState,
+};
+
+pub const KIND_NEW_POST: &str = "new_post";
+pub const KIND_POST_EDITED: &str = "post_edited";
+pub const KIND_SUMMARY: &str = "summary";
+
+/// Activity in a followed topic or by a followed user, with its topic
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, Object)]
+pub struct Notification {
+    pub notification_id: i64,
+    /// `new_post`, `post_edited` or `summary`
+    pub kind: String,
+    pub discourse_id: String,
+    pub topic_id: i32,
+    pub title: String,
+    pub post_id: Option<i32>,
+    pub post_number: Option<i32>,
+    pub username: Option<String>,
+    /// Version of the post the notification is about
+    pub version: Option<i32>,
+    pub summary_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+impl Notification {
+    /// Notify the subscribers of created and edited posts, returns the number of
+    /// notifications added
+    ///
+    /// Only activity after a subscription was created notifies, so crawling old pages
+    /// doesn't flood new subscribers. Each post version notifies a user once, even
+    /// when both the topic and the author are followed. Restricted topics only notify
+    /// `readers`, the users the instance currently grants access.
+    pub async fn record_posts(
+        discourse_id: &str,
+        created: &[i32],
+        edited: &[i32],
+        readers: &[Uuid],
+        state: &AppState,
+    ) -> Result<u64, sqlx::Error> {
+        let result = query(
+            "INSERT INTO notifications (user_id, kind, discourse_id, topic_id, post_id, post_number, version)
+             SELECT DISTINCT s.user_id, CASE WHEN p.post_id = ANY($2) THEN $4 ELSE $5 END,
+                p.discourse_id, p.topic_id, p.post_id, p.post_number, p.version
+             FROM posts p
+             JOIN topics t ON t.discourse_id = p.discourse_id AND t.topic_id = p.topic_id
+             JOIN subscriptions s ON s.discourse_id = p.discourse_id
+                AND (s.topic_id = p.topic_id OR s.username = p.extra->>'username')
+             WHERE p.discourse_id = $1 AND (p.post_id = ANY($2) OR p.post_id = ANY($3))
+                AND p.deleted_at IS NULL
+                AND NOT s.muted
+                AND CASE WHEN p.post_id = ANY($2) THEN p.created_at ELSE COALESCE(p.updated_at, p.created_at) END >= s.created_at
+                AND (t.visibility <> 'restricted' OR s.user_id = ANY($6))
+                AND NOT EXISTS (
+                    SELECT 1 FROM subscriptions muted
+                    WHERE muted.user_id = s.user_id AND muted.discourse_id = p.discourse_id
+                        AND muted.topic_id = p.topic_id AND muted.muted
+                )
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(discourse_id)
+        .bind(created)
+        .bind(edited)
+        .bind(KIND_NEW_POST)
+        .bind(KIND_POST_EDITED)
+        .bind(readers)
+        .execute(&state.database.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Notify the subscribers of a topic of its new summary, returns the number of
+    /// notifications added
+    ///
+    /// Summaries of restricted topics only notify `readers`.
+    pub async fn record_summary(
+        summary: &TopicSummary,
+        readers: &[Uuid],
+        state: &AppState,
+    ) -> Result<u64, sqlx::Error> {
+        let result = query(
+            "INSERT INTO notifications (user_id, kind, discourse_id, topic_id, summary_id)
+             SELECT s.user_id, $4, s.discourse_id, s.topic_id, $3
+             FROM subscriptions s
+             JOIN topics t ON t.discourse_id = s.discourse_id AND t.topic_id = s.topic_id
+             WHERE s.discourse_id = $1 AND s.topic_id = $2
+                AND NOT s.muted
+                AND (t.visibility <> 'restricted' OR s.user_id = ANY($5))
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(&summary.discourse_id)
+        .bind(summary.topic_id)
+        .bind(summary.summary_id)
+        .bind(KIND_SUMMARY)
+        .bind(readers)
+        .execute(&state.database.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Notifications of the user, newest first
+    ///
+    /// Restricted topics are only returned for instances in `access`, the user may
+    /// have lost access since subscribing.
+    pub async fn find_by_user_id(
+        user_id: Uuid,
+        unread_only: bool,
+        access: &TopicAccess,
+        limit: i64,
+        state: &AppState,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        query_as(
+            "SELECT n.notification_id, n.kind, n.discourse_id, n.topic_id, t.title, n.post_id, n.post_number,
+                p.extra->>'username' AS username, n.version, n.summary_id, n.created_at, n.read_at
+             FROM notifications n
+             JOIN topics t ON t.discourse_id = n.discourse_id AND t.topic_id = n.topic_id
+             LEFT JOIN posts p ON p.discourse_id = n.discourse_id AND p.post_id = n.post_id
+             WHERE n.user_id = $1
+                AND (NOT $2 OR n.read_at IS NULL)
+                AND (t.visibility <> 'restricted' OR t.discourse_id = ANY($3))
+             ORDER BY n.created_at DESC, n.notification_id DESC
+             LIMIT $4",
+        )
+        .bind(user_id)
+        .bind(unread_only)
+        .bind(access.restricted())
+        .bind(limit)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+
+    /// Mark the user's notifications up to `until` as read, only `notification_ids` when
+    /// given, returns the number of notifications marked
+    pub async fn mark_read(
+        user_id: Uuid,
+        notification_ids: Option<&[i64]>,
+        until: DateTime<Utc>,
+        state: &AppState,
+    ) -> Result<u64, sqlx::Error> {
+        let result = query(
+            "UPDATE notifications SET read_at = NOW()
+             WHERE user_id = $1
+                AND read_at IS NULL
+                AND ($2::BIGINT[] IS NULL OR notification_id = ANY($2))
+                AND created_at <= $3",
+        )
+        .bind(user_id)
+        .bind(notification_ids)
+        .bind(until)
+        .execute(&state.database.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
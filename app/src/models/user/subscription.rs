@@ -0,0 +1,104 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as};
+use uuid::Uuid;
+
+use crate::{models::user::User, state::AppState};
+
+/// A topic or Discourse user followed by a user, stored in `subscriptions`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, Object)]
+pub struct Subscription {
+    pub subscription_id: Uuid,
+    pub user_id: Uuid,
+    pub discourse_id: String,
+    /// Set when following a topic
+    pub topic_id: Option<i32>,
+    /// Set when following a Discourse user
+    pub username: Option<String>,
+    /// Produces no notifications, a muted topic also silences user subscriptions in it
+    pub muted: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What to follow, exactly one of `topic_id` and `username`
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct SubscriptionInput {
+    pub discourse_id: String,
+    pub topic_id: Option<i32>,
+    pub username: Option<String>,
+    /// Subscribe muted, e.g. to silence a topic followed through a user
+    #[oai(default)]
+    #[serde(default)]
+    pub muted: bool,
+}
+
+impl Subscription {
+    /// Follow a topic or user, subscribing again updates `muted`
+    pub async fn upsert(
+        user_id: Uuid,
+        input: &SubscriptionInput,
+        state: &AppState,
+    ) -> Result<Self, sqlx::Error> {
+        let conflict = if input.topic_id.is_some() {
+            "(user_id, discourse_id, topic_id) WHERE topic_id IS NOT NULL"
+        } else {
+            "(user_id, discourse_id, username) WHERE username IS NOT NULL"
+        };
+
+        query_as(&format!(
+            "INSERT INTO subscriptions (user_id, discourse_id, topic_id, username, muted) VALUES ($1, $2, $3, $4, $5) ON CONFLICT {} DO UPDATE SET muted = $5 RETURNING *",
+            conflict
+        ))
+        .bind(user_id)
+        .bind(&input.discourse_id)
+        .bind(input.topic_id)
+        .bind(&input.username)
+        .bind(input.muted)
+        .fetch_one(&state.database.pool)
+        .await
+    }
+
+    /// Users with unmuted subscriptions on the instance
+    pub async fn find_subscribers(discourse_id: &str, state: &AppState) -> Result<Vec<User>, sqlx::Error> {
+        query_as(
+            "SELECT u.* FROM users u WHERE EXISTS (SELECT 1 FROM subscriptions s WHERE s.user_id = u.user_id AND s.discourse_id = $1 AND NOT s.muted)",
+        )
+        .bind(discourse_id)
+        .fetch_all(&state.database.pool)
+        .await
+    }
+
+    pub async fn find_by_user_id(user_id: Uuid, state: &AppState) -> Result<Vec<Self>, sqlx::Error> {
+        query_as("SELECT * FROM subscriptions WHERE user_id = $1 ORDER BY created_at DESC")
+            .bind(user_id)
+            .fetch_all(&state.database.pool)
+            .await
+    }
+
+    /// Mute or unmute a subscription of the user, `None` when it doesn't exist
+    pub async fn set_muted(
+        subscription_id: Uuid,
+        user_id: Uuid,
+        muted: bool,
+        state: &AppState,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        query_as("UPDATE subscriptions SET muted = $3 WHERE subscription_id = $1 AND user_id = $2 RETURNING *")
+            .bind(subscription_id)
+            .bind(user_id)
+            .bind(muted)
+            .fetch_optional(&state.database.pool)
+            .await
+    }
+
+    /// Unsubscribe, `false` when the subscription doesn't exist
+    pub async fn delete(subscription_id: Uuid, user_id: Uuid, state: &AppState) -> Result<bool, sqlx::Error> {
+        let result = query("DELETE FROM subscriptions WHERE subscription_id = $1 AND user_id = $2")
+            .bind(subscription_id)
+            .bind(user_id)
+            .execute(&state.database.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
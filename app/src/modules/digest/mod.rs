@@ -0,0 +1,491 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, TimeDelta, Utc};
+use tracing::{error, info};
+
+use crate::{
+    models::{
+        ical::CalendarEvent,
+        user::digest::{DigestPreferences, DigestRecipient, DigestTopic},
+    },
+    modules::email::Email,
+    state::AppState,
+};
+
+/// How often due digests are looked for, the send hour is kept within this
+const CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const MAX_CALLS: usize = 10;
+/// Characters of a summary shown per topic
+const SUMMARY_LENGTH: usize = 300;
+
+struct Template {
+    html: &'static str,
+    text: &'static str,
+}
+
+const DIGEST: Template = Template {
+    html: include_str!("./templates/digest.html"),
+    text: include_str!("./templates/digest.txt"),
+};
+const TOPIC: Template = Template {
+    html: include_str!("./templates/topic.html"),
+    text: include_str!("./templates/topic.txt"),
+};
+const CALLS: Template = Template {
+    html: include_str!("./templates/calls.html"),
+    text: include_str!("./templates/calls.txt"),
+};
+const CALL: Template = Template {
+    html: include_str!("./templates/call.html"),
+    text: include_str!("./templates/call.txt"),
+};
+
+/// Activity in followed topics since the previous digest, with upcoming calls
+pub struct Digest {
+    /// `daily` or `weekly`
+    pub schedule: String,
+    pub name: Option<String>,
+    pub topics: Vec<DigestTopic>,
+    pub calls: Vec<CalendarEvent>,
+    pub public_url: String,
+    pub unsubscribe_url: String,
+}
+
+/// Send due digests every 15 minutes, does nothing without SMTP configured
+pub async fn send_digests_periodically(state: &AppState) {
+    if state.email.is_none() {
+        return;
+    }
+
+    loop {
+        if let Err(e) = send_due_digests(state).await {
+            error!("Error sending digests: {:?}", e);
+        }
+        async_std::task::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+async fn send_due_digests(state: &AppState) -> Result<()> {
+    let now = Utc::now();
+    for recipient in DigestRecipient::find_enabled(state).await? {
+        if !is_due(&recipient.preferences, now) {
+            continue;
+        }
+
+        // Failed deliveries are retried on the next check, empty digests are skipped
+        match send_digest(&recipient, now, state).await {
+            Ok(sent) => {
+                if sent {
+                    info!(
+                        "Sent {} digest to user {}",
+                        recipient.preferences.schedule, recipient.preferences.user_id
+                    );
+                }
+                // The digest is out, a failure here must not hold up the other recipients
+                if let Err(e) =
+                    DigestPreferences::mark_sent(recipient.preferences.user_id, now, state).await
+                {
+                    error!(
+                        "Error marking digest of user {} as sent: {:?}",
+                        recipient.preferences.user_id, e
+                    );
+                }
+            }
+            Err(e) => error!(
+                "Error sending digest to user {}: {:?}",
+                recipient.preferences.user_id, e
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Send the digest of activity since the previous one, `false` when there was no
+/// activity to send
+pub async fn send_digest(
+    recipient: &DigestRecipient,
+    now: DateTime<Utc>,
+    state: &AppState,
+) -> Result<bool> {
+    let mailer = state.email.as_ref().context("Email is not configured")?;
+    let preferences = &recipient.preferences;
+    let period = period(&preferences.schedule);
+
+    let since = preferences.last_sent_at.unwrap_or(now - period);
+    let access = state
+        .discourse
+        .access_for_sign_in(&recipient.sso_provider, &recipient.email);
+    let topics = DigestTopic::find_active(preferences.user_id, since, &access, state).await?;
+    if topics.is_empty() {
+        return Ok(false);
+    }
+
+    let digest = Digest {
+        schedule: preferences.schedule.clone(),
+        name: recipient.display_name.clone(),
+        topics,
+        calls: upcoming_calls(now, now + period, state).await,
+        public_url: state.public_url.clone(),
+        unsubscribe_url: format!(
+            "{}/digest/unsubscribe/{}",
+            state.public_url, preferences.unsubscribe_token
+        ),
+    };
+    mailer.send(&digest.to_email(&recipient.email)).await?;
+
+    Ok(true)
+}
+
+/// Calls starting before `until`, a calendar that fails to load only drops the section
+async fn upcoming_calls(
+    now: DateTime<Utc>,
+    until: DateTime<Utc>,
+    state: &AppState,
+) -> Vec<CalendarEvent> {
+    let Some(ical) = &state.ical else {
+        return vec![];
+    };
+
+    match ical.fetch_upcoming(state).await {
+        Ok(events) => events
+            .into_iter()
+            .filter(|event| {
+                event
+                    .start
+                    .is_some_and(|start| start >= now && start < until)
+            })
+            .take(MAX_CALLS)
+            .collect(),
+        Err(e) => {
+            error!("Error fetching upcoming calls for digest: {:?}", e);
+            vec![]
+        }
+    }
+}
+
+fn period(schedule: &str) -> TimeDelta {
+    match schedule {
+        "weekly" => TimeDelta::days(7),
+        _ => TimeDelta::days(1),
+    }
+}
+
+/// Whether the latest send slot at or before `now` came after the previous digest
+///
+/// Slots are at `send_hour` every day, or on `weekday` for weekly digests. Without
+/// a previous digest the slot has to follow the last change of the preferences, so
+/// turning digests on doesn't send one right away.
+fn is_due(preferences: &DigestPreferences, now: DateTime<Utc>) -> bool {
+    if preferences.schedule != "daily" && preferences.schedule != "weekly" {
+        return false;
+    }
+
+    let Some(mut slot) = now
+        .date_naive()
+        .and_hms_opt(preferences.send_hour.clamp(0, 23) as u32, 0, 0)
+        .map(|slot| slot.and_utc())
+    else {
+        return false;
+    };
+    if slot > now {
+        slot -= TimeDelta::days(1);
+    }
+    if preferences.schedule == "weekly" {
+        while slot.weekday().number_from_monday() as i32 != preferences.weekday {
+            slot -= TimeDelta::days(1);
+        }
+    }
+
+    preferences.last_sent_at.unwrap_or(preferences.updated_at) < slot
+}
+
+impl Digest {
+    pub fn subject(&self) -> String {
+        let topics = match self.topics.len() {
+            1 => "1 followed topic".to_string(),
+            count => format!("{} followed topics", count),
+        };
+        format!(
+            "Your {} Ethereum Forum digest: activity in {}",
+            self.schedule, topics
+        )
+    }
+
+    pub fn to_email(&self, to: &str) -> Email {
+        Email {
+            to: to.to_string(),
+            subject: self.subject(),
+            text: self.render(false),
+            html: self.render(true),
+            headers: vec![
+                (
+                    "List-Unsubscribe".to_string(),
+                    format!("<{}>", self.unsubscribe_url),
+                ),
+                // RFC 8058, mail clients unsubscribe with a POST to the url
+                (
+                    "List-Unsubscribe-Post".to_string(),
+                    "List-Unsubscribe=One-Click".to_string(),
+                ),
+            ],
+        }
+    }
+
+    fn render(&self, html: bool) -> String {
+        let pick = |template: &Template| if html { template.html } else { template.text };
+        let escape = |value: &str| {
+            if html {
+                escape_html(value)
+            } else {
+                value.to_string()
+            }
+        };
+
+        let topics: String = self
+            .topics
+            .iter()
+            .map(|topic| {
+                let url = format!(
+                    "{}/t/{}/{}",
+                    self.public_url, topic.discourse_id, topic.topic_id
+                );
+                let new_posts = match topic.new_posts {
+                    1 => "1 new post".to_string(),
+                    count => format!("{} new posts", count),
+                };
+                let summary = topic
+                    .summary
+                    .as_deref()
+                    .map(|summary| excerpt(summary, SUMMARY_LENGTH))
+                    .unwrap_or_else(|| "No summary yet.".to_string());
+
+                render(
+                    pick(&TOPIC),
+                    &[
+                        ("url", &escape(&url)),
+                        ("title", &escape(&topic.title)),
+                        ("new_posts", &new_posts),
+                        ("discourse_id", &escape(&topic.discourse_id)),
+                        ("summary", &escape(&summary)),
+                    ],
+                )
+            })
+            .collect();
+
+        let calls = if self.calls.is_empty() {
+            String::new()
+        } else {
+            let items: String = self
+                .calls
+                .iter()
+                .map(|call| {
+                    let start = call
+                        .start
+                        .map(|start| start.format("%a %e %b %H:%M UTC").to_string())
+                        .unwrap_or_default();
+                    render(
+                        pick(&CALL),
+                        &[
+                            ("start", &escape(&start)),
+                            (
+                                "summary",
+                                &escape(call.summary.as_deref().unwrap_or("Untitled call")),
+                            ),
+                        ],
+                    )
+                })
+                .collect();
+            render(
+                pick(&CALLS),
+                &[
+                    ("items", &items),
+                    ("url", &escape(&format!("{}/c", self.public_url))),
+                ],
+            )
+        };
+
+        let greeting = match &self.name {
+            Some(name) => format!(
+                "Hi {}, here is what happened in the topics you follow.",
+                name
+            ),
+            None => "Here is what happened in the topics you follow.".to_string(),
+        };
+
+        render(
+            pick(&DIGEST),
+            &[
+                ("subject", &escape(&self.subject())),
+                ("greeting", &escape(&greeting)),
+                ("schedule", &escape(&self.schedule)),
+                ("topics", &topics),
+                ("calls", &calls),
+                ("public_url", &escape(&self.public_url)),
+                ("unsubscribe_url", &escape(&self.unsubscribe_url)),
+            ],
+        )
+    }
+}
+
+/// Replace `{{name}}` placeholders in one pass, so inserted values are never expanded
+fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+
+        let name = &after[..end];
+        match values.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => output.push_str(value),
+            None => output.push_str(&rest[start..start + end + 4]),
+        }
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+
+    output
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// First paragraph of a markdown summary without headings, cut at a word boundary
+fn excerpt(summary: &str, max_chars: usize) -> String {
+    let paragraph = summary
+        .split("\n\n")
+        .map(|paragraph| {
+            paragraph
+                .lines()
+                .filter(|line| !line.trim_start().starts_with('#'))
+                .map(|line| line.trim().trim_start_matches(['-', '*', ' ']))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .find(|paragraph| !paragraph.trim().is_empty())
+        .unwrap_or_default()
+        .replace("**", "");
+
+    if paragraph.chars().count() <= max_chars {
+        return paragraph;
+    }
+
+    let cut: String = paragraph.chars().take(max_chars).collect();
+    let cut = cut.rsplit_once(' ').map(|(cut, _)| cut).unwrap_or(&cut);
+    format!("{}…", cut.trim_end_matches([',', '.', ';', ':']))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn preferences(schedule: &str, last_sent_at: Option<DateTime<Utc>>) -> DigestPreferences {
+        DigestPreferences {
+            user_id: Uuid::nil(),
+            schedule: schedule.to_string(),
+            send_hour: 8,
+            // Wednesday
+            weekday: 3,
+            unsubscribe_token: Uuid::nil(),
+            last_sent_at,
+            created_at: Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap(),
+            updated_at: Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_is_due() {
+        // Friday 2025-06-06
+        let before_hour = Utc.with_ymd_and_hms(2025, 6, 6, 7, 0, 0).unwrap();
+        let after_hour = Utc.with_ymd_and_hms(2025, 6, 6, 9, 0, 0).unwrap();
+        let yesterday = Some(Utc.with_ymd_and_hms(2025, 6, 5, 8, 5, 0).unwrap());
+
+        assert!(!is_due(&preferences("daily", yesterday), before_hour));
+        assert!(is_due(&preferences("daily", yesterday), after_hour));
+        assert!(!is_due(&preferences("daily", Some(after_hour)), after_hour));
+        assert!(!is_due(&preferences("off", None), after_hour));
+
+        // The latest weekly slot was Wednesday 2025-06-04 08:00
+        assert!(!is_due(&preferences("weekly", yesterday), after_hour));
+        let last_week = Some(Utc.with_ymd_and_hms(2025, 5, 28, 8, 5, 0).unwrap());
+        assert!(is_due(&preferences("weekly", last_week), after_hour));
+
+        // Turned on after today's slot
+        let mut turned_on = preferences("daily", None);
+        turned_on.updated_at = Utc.with_ymd_and_hms(2025, 6, 6, 8, 30, 0).unwrap();
+        assert!(!is_due(&turned_on, after_hour));
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render("{{a}} and {{b}} {{unknown}}", &[("a", "{{b}}"), ("b", "x")]),
+            "{{b}} and x {{unknown}}"
+        );
+
+        let digest = Digest {
+            schedule: "daily".to_string(),
+            name: Some("Alice".to_string()),
+            topics: vec![DigestTopic {
+                discourse_id: "magicians".to_string(),
+                topic_id: 12,
+                title: "EOF <again>".to_string(),
+                new_posts: 3,
+                last_post_at: None,
+                summary: Some("## Summary\nShip **EOF** now.\n\nMore details".to_string()),
+            }],
+            calls: vec![],
+            public_url: "https://ethereum.forum".to_string(),
+            unsubscribe_url: "https://ethereum.forum/digest/unsubscribe/token".to_string(),
+        };
+        let email = digest.to_email("alice@example.com");
+
+        assert_eq!(
+            email.subject,
+            "Your daily Ethereum Forum digest: activity in 1 followed topic"
+        );
+        assert!(email.html.contains("EOF &lt;again&gt;"));
+        assert!(
+            email
+                .html
+                .contains("href=\"https://ethereum.forum/t/magicians/12\"")
+        );
+        assert!(email.html.contains("3 new posts"));
+        assert!(
+            email
+                .text
+                .contains("* EOF <again> (3 new posts, magicians)")
+        );
+        assert!(email.text.contains("  Ship EOF now.\n"));
+        assert!(!email.text.contains("UPCOMING PROTOCOL CALLS"));
+        assert!(
+            email
+                .text
+                .contains("Unsubscribe: https://ethereum.forum/digest/unsubscribe/token")
+        );
+    }
+
+    #[test]
+    fn test_excerpt() {
+        assert_eq!(
+            excerpt("# Title\n\n- First point\n- second", 100),
+            "First point second"
+        );
+        assert_eq!(excerpt("one two three four", 10), "one two…");
+    }
+}
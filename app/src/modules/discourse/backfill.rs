@@ -0,0 +1,139 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
+use anyhow::Result;
+use tracing::{error, info, warn};
+
+use crate::{
+    models::crawl::{
+        CrawlCursor,
+        job::{IndexJob, IndexPriority},
+    },
+    state::AppState,
+};
+
+use super::DiscourseIndexer;
+
+/// Stop feeding the queue while it holds more than this many backfill jobs
+const BACKFILL_HIGH_WATER: i64 = 200;
+/// Delay between listing pages to stay polite towards the discourse instance
+const PAGE_DELAY: Duration = Duration::from_secs(2);
+
+impl DiscourseIndexer {
+    pub fn is_backfilling(&self) -> bool {
+        self.backfill_running.load(Ordering::SeqCst)
+    }
+
+    /// Crawl every page of `/latest.json` and of each category listing, feeding
+    /// all topics into the queue at backfill priority
+    ///
+    /// Progress is stored per listing in `discourse_crawl_cursors`, so an
+    /// interrupted crawl resumes where it left off. Completed listings are
+    /// skipped unless `restart` is set.
+    pub async fn backfill(&self, state: &AppState, restart: bool) -> Result<()> {
+        if self.backfill_running.swap(true, Ordering::SeqCst) {
+            warn!(
+                "Backfill for {} is already running",
+                self.config.discourse_id
+            );
+            return Ok(());
+        }
+
+        let result = self.backfill_inner(state, restart).await;
+        self.backfill_running.store(false, Ordering::SeqCst);
+        result
+    }
+
+    async fn backfill_inner(&self, state: &AppState, restart: bool) -> Result<()> {
+        let discourse_id = &self.config.discourse_id;
+
+        if restart {
+            info!("Resetting backfill cursors for {}", discourse_id);
+            CrawlCursor::reset(discourse_id, state).await?;
+        }
+
+        let mut listings = vec!["latest".to_string()];
+        match self.client.fetch_categories().await {
+            Ok(categories) => listings.extend(
+                categories
+                    .category_list
+                    .categories
+                    .iter()
+                    .map(|category| format!("c/{}", category.id)),
+            ),
+            Err(e) => warn!(
+                "Could not list categories for {}, only crawling latest: {:?}",
+                discourse_id, e
+            ),
+        }
+
+        info!(
+            "Starting backfill for {} over {} listings",
+            discourse_id,
+            listings.len()
+        );
+
+        for listing in listings {
+            let mut cursor = CrawlCursor::get_or_create(discourse_id, &listing, state).await?;
+            if cursor.completed_at.is_some() {
+                info!(
+                    "Backfill of {}/{} already completed, skipping",
+                    discourse_id, listing
+                );
+                continue;
+            }
+
+            if let Err(e) = self.crawl_listing(&mut cursor, state).await {
+                error!("Backfill of {}/{} stopped: {:?}", discourse_id, listing, e);
+                cursor.record_error(&e.to_string(), state).await?;
+                return Err(e);
+            }
+        }
+
+        info!("Backfill for {} finished", discourse_id);
+        Ok(())
+    }
+
+    async fn crawl_listing(&self, cursor: &mut CrawlCursor, state: &AppState) -> Result<()> {
+        loop {
+            // Let the indexer catch up before feeding it more work
+            while IndexJob::count_queued(&self.config.discourse_id, IndexPriority::Backfill, state)
+                .await?
+                > BACKFILL_HIGH_WATER
+            {
+                async_std::task::sleep(Duration::from_secs(5)).await;
+            }
+
+            let page = cursor.next_page as u32;
+            let response = self
+                .client
+                .fetch_listing_page(&cursor.listing, page)
+                .await?;
+            let topics = response.topic_list.topics;
+
+            for topic in &topics {
+                self.enqueue(topic.id, 1, IndexPriority::Backfill, state)
+                    .await?;
+            }
+
+            cursor
+                .advance(page as i32, topics.len() as i32, state)
+                .await?;
+
+            info!(
+                "Backfill {}/{} page {}: {} topics ({} total)",
+                self.config.discourse_id,
+                cursor.listing,
+                page,
+                topics.len(),
+                cursor.topics_seen
+            );
+
+            if topics.is_empty() || response.topic_list.more_topics_url.is_none() {
+                cursor.complete(state).await?;
+                return Ok(());
+            }
+
+            async_std::task::sleep(PAGE_DELAY).await;
+        }
+    }
+}
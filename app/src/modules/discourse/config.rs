@@ -0,0 +1,489 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
+
+use figment::{
+    Figment, Provider,
+    providers::{Env, Serialized},
+    value::Dict,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+use crate::models::topics::link::LinkKind;
+
+use super::{
+    issues::IssueRules,
+    schedule::{ScrapeSchedule, parse_duration},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscourseConfig {
+    /// Populated from the `[discourse.<id>]` table key
+    #[serde(skip)]
+    pub discourse_id: String,
+    pub url: String,
+    /// Human duration (`"30m"`) or cron expression (`"*/5 * * * *"`)
+    #[serde(default = "default_scrape_interval")]
+    pub scrape_interval: String,
+    /// Maximum random delay added to each scheduled run
+    pub scrape_jitter: Option<String>,
+    /// Crawl the full history of the instance in the background at startup
+    #[serde(default)]
+    pub backfill: bool,
+    /// Maximum number of requests in flight to this instance
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    /// Sustained request rate, enforced with a token bucket
+    #[serde(default = "default_requests_per_minute")]
+    pub requests_per_minute: u32,
+    /// Number of requests that may be sent back to back before the rate applies
+    #[serde(default = "default_request_burst")]
+    pub request_burst: u32,
+    /// Human duration after which a single request is aborted
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: String,
+    /// Admin API key, sent as `Api-Key` together with `Api-Username`
+    #[serde(default, skip_serializing)]
+    pub api_key: Option<Secret>,
+    /// User the API key acts as, defaults to `system`
+    pub api_username: Option<String>,
+    /// User API key, sent as `User-Api-Key`, as an alternative to `api_key`
+    #[serde(default, skip_serializing)]
+    pub user_api_key: Option<Secret>,
+    /// SSO providers whose users may read topics from restricted categories
+    #[serde(default)]
+    pub restricted_sso_providers: Vec<String>,
+    /// Email domains whose users may read topics from restricted categories
+    #[serde(default)]
+    pub restricted_email_domains: Vec<String>,
+    /// Secret configured on the Discourse webhook, webhooks are rejected without one
+    #[serde(default, skip_serializing)]
+    pub webhook_secret: Option<Secret>,
+    /// Rules linking topics to issues, pull requests and EIPs mentioned in their first post
+    #[serde(default)]
+    pub issue_rules: Vec<IssueRule>,
+}
+
+/// `[[discourse.<id>.issue_rules]]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IssueRule {
+    pub kind: LinkKind,
+    /// Regex with a `number` group and, for GitHub links, an optional `repo` group
+    pub pattern: String,
+    /// Repository used when the pattern has no `repo` group
+    pub repository: Option<String>,
+    /// Only apply to topics in these categories, every category when empty
+    #[serde(default)]
+    pub categories: Vec<i32>,
+}
+
+/// A credential that is never printed in logs
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+impl DiscourseConfig {
+    pub fn schedule(&self) -> Result<ScrapeSchedule, String> {
+        self.scrape_interval.parse()
+    }
+
+    pub fn jitter(&self) -> Result<Option<Duration>, String> {
+        self.scrape_jitter
+            .as_deref()
+            .map(parse_duration)
+            .transpose()
+    }
+
+    pub fn timeout(&self) -> Result<Duration, String> {
+        parse_duration(&self.request_timeout)
+    }
+
+    pub fn has_credentials(&self) -> bool {
+        self.api_key.is_some() || self.user_api_key.is_some()
+    }
+
+    /// Whether a user signed in through `sso_provider` with `email` may read
+    /// topics from restricted categories of this instance
+    pub fn grants_restricted_access(&self, sso_provider: &str, email: &str) -> bool {
+        if self
+            .restricted_sso_providers
+            .iter()
+            .any(|provider| provider == sso_provider)
+        {
+            return true;
+        }
+
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return false;
+        };
+        self.restricted_email_domains
+            .iter()
+            .any(|allowed| allowed.trim_start_matches('@').eq_ignore_ascii_case(domain))
+    }
+}
+
+fn default_scrape_interval() -> String {
+    "30m".to_string()
+}
+
+fn default_max_concurrent_requests() -> usize {
+    2
+}
+
+fn default_requests_per_minute() -> u32 {
+    60
+}
+
+fn default_request_burst() -> u32 {
+    5
+}
+
+fn default_request_timeout() -> String {
+    "30s".to_string()
+}
+
+#[derive(Debug, Error)]
+pub enum DiscourseConfigError {
+    #[error("failed to read discourse configuration: {0}")]
+    Figment(Box<figment::Error>),
+    #[error(
+        "no discourse instances configured, add at least one [discourse.<id>] table to config.toml"
+    )]
+    Empty,
+    #[error(
+        "discourse id '{0}' is invalid, only lowercase letters, digits, '-' and '_' are allowed"
+    )]
+    InvalidId(String),
+    #[error("discourse id '{0}' is configured more than once")]
+    DuplicateId(String),
+    #[error("discourse '{discourse_id}' has an invalid url '{url}': {reason}")]
+    InvalidUrl {
+        discourse_id: String,
+        url: String,
+        reason: String,
+    },
+    #[error("discourse '{discourse_id}' has an invalid schedule: {reason}")]
+    InvalidSchedule {
+        discourse_id: String,
+        reason: String,
+    },
+    #[error("discourse '{discourse_id}' has an invalid rate limit: {reason}")]
+    InvalidRateLimit {
+        discourse_id: String,
+        reason: String,
+    },
+    #[error("discourse '{discourse_id}' has invalid credentials: {reason}")]
+    InvalidCredentials {
+        discourse_id: String,
+        reason: String,
+    },
+    #[error("discourse '{discourse_id}' has an invalid issue rule: {reason}")]
+    InvalidIssueRule {
+        discourse_id: String,
+        reason: String,
+    },
+    #[error("discourse '{discourse_id}' uses url '{url}' which is already used by '{other}'")]
+    DuplicateUrl {
+        discourse_id: String,
+        url: String,
+        other: String,
+    },
+}
+
+/// Load all `[discourse.<id>]` tables from the given figment
+///
+/// Values can be overridden from the environment using `DISCOURSE_<ID>__<FIELD>`,
+/// for example `DISCOURSE_MAGICIANS__SCRAPE_INTERVAL=5m`. Only instances configured in
+/// the figment are overridden, other `DISCOURSE_*` variables are ignored.
+pub fn init_discourse_configs(
+    figment: Figment,
+) -> Result<Vec<DiscourseConfig>, DiscourseConfigError> {
+    load_discourse_configs(figment, Env::prefixed("DISCOURSE_").split("__"))
+}
+
+/// Load the instances of `figment`, with `overrides` keyed by instance id merged on top
+fn load_discourse_configs(
+    figment: Figment,
+    overrides: impl Provider,
+) -> Result<Vec<DiscourseConfig>, DiscourseConfigError> {
+    let ids: Vec<String> = figment
+        .find_value("discourse")
+        .ok()
+        .and_then(|value| value.into_dict())
+        .map(|instances| instances.into_keys().collect())
+        .unwrap_or_default();
+
+    let mut overrides = Figment::from(overrides)
+        .extract::<Dict>()
+        .map_err(|e| DiscourseConfigError::Figment(Box::new(e)))?;
+    overrides.retain(|id, _| ids.iter().any(|known| known.eq_ignore_ascii_case(id)));
+
+    let figment = figment
+        .focus("discourse")
+        .merge(Serialized::defaults(overrides));
+
+    let instances = figment
+        .extract::<BTreeMap<String, DiscourseConfig>>()
+        .map_err(|e| DiscourseConfigError::Figment(Box::new(e)))?;
+
+    let configs = instances
+        .into_iter()
+        .map(|(discourse_id, config)| DiscourseConfig {
+            discourse_id,
+            ..config
+        })
+        .collect();
+
+    validate_discourse_configs(configs)
+}
+
+/// Validate ids and urls, normalizing urls by removing any trailing slash
+pub fn validate_discourse_configs(
+    configs: Vec<DiscourseConfig>,
+) -> Result<Vec<DiscourseConfig>, DiscourseConfigError> {
+    if configs.is_empty() {
+        return Err(DiscourseConfigError::Empty);
+    }
+
+    let mut seen_ids = HashSet::new();
+    let mut seen_urls: Vec<(String, String)> = Vec::new();
+    let mut validated = Vec::with_capacity(configs.len());
+
+    for mut config in configs {
+        let valid_id = !config.discourse_id.is_empty()
+            && config
+                .discourse_id
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if !valid_id {
+            return Err(DiscourseConfigError::InvalidId(config.discourse_id));
+        }
+
+        if !seen_ids.insert(config.discourse_id.clone()) {
+            return Err(DiscourseConfigError::DuplicateId(config.discourse_id));
+        }
+
+        let invalid_url = |reason: String| DiscourseConfigError::InvalidUrl {
+            discourse_id: config.discourse_id.clone(),
+            url: config.url.clone(),
+            reason,
+        };
+
+        let parsed = Url::parse(&config.url).map_err(|e| invalid_url(e.to_string()))?;
+        if parsed.scheme() != "https" && parsed.scheme() != "http" {
+            return Err(invalid_url(format!(
+                "unsupported scheme '{}'",
+                parsed.scheme()
+            )));
+        }
+        if parsed.host_str().is_none() {
+            return Err(invalid_url("missing host".to_string()));
+        }
+        if parsed.query().is_some() || parsed.fragment().is_some() {
+            return Err(invalid_url(
+                "query strings and fragments are not allowed".to_string(),
+            ));
+        }
+
+        let url = parsed.as_str().trim_end_matches('/').to_string();
+        if let Some((other, _)) = seen_urls.iter().find(|(_, seen)| *seen == url) {
+            return Err(DiscourseConfigError::DuplicateUrl {
+                discourse_id: config.discourse_id,
+                url,
+                other: other.clone(),
+            });
+        }
+        seen_urls.push((config.discourse_id.clone(), url.clone()));
+
+        if let Err(reason) = config.schedule().and_then(|_| config.jitter()) {
+            return Err(DiscourseConfigError::InvalidSchedule {
+                discourse_id: config.discourse_id,
+                reason,
+            });
+        }
+
+        let rate_limit = if config.max_concurrent_requests == 0 {
+            Err("max_concurrent_requests must be at least 1".to_string())
+        } else if config.requests_per_minute == 0 {
+            Err("requests_per_minute must be at least 1".to_string())
+        } else if config.request_burst == 0 {
+            Err("request_burst must be at least 1".to_string())
+        } else {
+            config.timeout().map(|_| ())
+        };
+        if let Err(reason) = rate_limit {
+            return Err(DiscourseConfigError::InvalidRateLimit {
+                discourse_id: config.discourse_id,
+                reason,
+            });
+        }
+
+        let credentials = if config.api_key.is_some() && config.user_api_key.is_some() {
+            Err("api_key and user_api_key can't be used together")
+        } else if config.api_username.is_some() && config.api_key.is_none() {
+            Err("api_username requires api_key")
+        } else {
+            Ok(())
+        };
+        if let Err(reason) = credentials {
+            return Err(DiscourseConfigError::InvalidCredentials {
+                discourse_id: config.discourse_id,
+                reason: reason.to_string(),
+            });
+        }
+
+        if let Err(reason) = IssueRules::compile(&config.issue_rules) {
+            return Err(DiscourseConfigError::InvalidIssueRule {
+                discourse_id: config.discourse_id,
+                reason,
+            });
+        }
+
+        config.url = url;
+        validated.push(config);
+    }
+
+    Ok(validated)
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::providers::{Format, Toml};
+
+    use super::*;
+
+    fn load(toml: &str) -> Result<Vec<DiscourseConfig>, DiscourseConfigError> {
+        init_discourse_configs(Figment::new().merge(Toml::string(toml)))
+    }
+
+    #[test]
+    fn test_load_discourse_configs() {
+        let configs = load(
+            r#"
+            [discourse.magicians]
+            url = "https://ethereum-magicians.org/"
+            scrape_interval = "5m"
+
+            [discourse.research]
+            url = "https://ethresear.ch"
+            scrape_interval = "0 */6 * * *"
+            scrape_jitter = "2m"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0].discourse_id, "magicians");
+        assert_eq!(configs[0].url, "https://ethereum-magicians.org");
+        assert_eq!(configs[0].scrape_interval, "5m");
+        assert_eq!(configs[1].discourse_id, "research");
+        assert_eq!(configs[1].scrape_interval, "0 */6 * * *");
+        assert_eq!(configs[1].jitter().unwrap(), Some(Duration::from_secs(120)));
+        assert_eq!(configs[1].max_concurrent_requests, 2);
+        assert_eq!(configs[1].timeout().unwrap(), Duration::from_secs(30));
+        assert!(configs[1].issue_rules.is_empty());
+    }
+
+    #[test]
+    fn test_ignore_unrelated_env() {
+        let configs = load_discourse_configs(
+            Figment::new().merge(Toml::string(
+                r#"
+                [discourse.magicians]
+                url = "https://ethereum-magicians.org"
+                "#,
+            )),
+            // As read from `DISCOURSE_MAGICIANS__SCRAPE_INTERVAL` and
+            // `DISCOURSE_UNRELATED__TOKEN`, the latter is not an instance and would
+            // otherwise add an `[discourse.unrelated]` table without url
+            Serialized::defaults(serde_json::json!({
+                "magicians": { "scrape_interval": "5m" },
+                "unrelated": { "token": "1" },
+            })),
+        )
+        .unwrap();
+
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].scrape_interval, "5m");
+    }
+
+    #[test]
+    fn test_reject_invalid_discourse_configs() {
+        assert!(matches!(load(""), Err(DiscourseConfigError::Empty)));
+        assert!(matches!(
+            load("[discourse.magicians]\nurl = \"ethereum-magicians.org\""),
+            Err(DiscourseConfigError::InvalidUrl { .. })
+        ));
+        assert!(matches!(
+            load("[discourse.magicians]\nurl = \"ftp://ethereum-magicians.org\""),
+            Err(DiscourseConfigError::InvalidUrl { .. })
+        ));
+        assert!(matches!(
+            load("[discourse.Magicians]\nurl = \"https://ethereum-magicians.org\""),
+            Err(DiscourseConfigError::InvalidId(_))
+        ));
+        assert!(matches!(
+            load(
+                "[discourse.a]\nurl = \"https://ethresear.ch\"\n[discourse.b]\nurl = \"https://ethresear.ch/\""
+            ),
+            Err(DiscourseConfigError::DuplicateUrl { .. })
+        ));
+        assert!(matches!(
+            load("[discourse.a]\nurl = \"https://ethresear.ch\"\nscrape_interval = \"often\""),
+            Err(DiscourseConfigError::InvalidSchedule { .. })
+        ));
+        assert!(matches!(
+            load("[discourse.a]\nurl = \"https://ethresear.ch\"\nrequests_per_minute = 0"),
+            Err(DiscourseConfigError::InvalidRateLimit { .. })
+        ));
+        assert!(matches!(
+            load(
+                "[discourse.a]\nurl = \"https://ethresear.ch\"\napi_key = \"a\"\nuser_api_key = \"b\""
+            ),
+            Err(DiscourseConfigError::InvalidCredentials { .. })
+        ));
+        assert!(matches!(
+            load(
+                "[discourse.a]\nurl = \"https://ethresear.ch\"\n[[discourse.a.issue_rules]]\nkind = \"eip\"\npattern = \"EIP-\\\\d+\""
+            ),
+            Err(DiscourseConfigError::InvalidIssueRule { .. })
+        ));
+    }
+
+    #[test]
+    fn test_restricted_access() {
+        let configs = load(
+            r#"
+            [discourse.internal]
+            url = "https://forum.example.org"
+            api_key = "hunter2"
+            restricted_sso_providers = ["internal"]
+            restricted_email_domains = ["example.org"]
+            "#,
+        )
+        .unwrap();
+        let config = &configs[0];
+
+        assert!(config.has_credentials());
+        assert!(!format!("{:?}", config).contains("hunter2"));
+        assert!(config.grants_restricted_access("internal", "someone@gmail.com"));
+        assert!(config.grants_restricted_access("github", "someone@Example.org"));
+        assert!(!config.grants_restricted_access("github", "someone@example.org.evil"));
+        assert!(!config.grants_restricted_access("github", "example.org"));
+    }
+}
@@ -0,0 +1,392 @@
+use std::{
+    num::NonZero,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow};
+use async_lock::Semaphore;
+use async_std::sync::RwLock;
+use chrono::{DateTime, Utc};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use moka::future::Cache;
+use reqwest::{
+    Client, Response, StatusCode,
+    header::{
+        ETAG, HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER,
+    },
+};
+use serde::de::DeserializeOwned;
+use tracing::warn;
+
+use crate::models::discourse::{
+    category::{DiscourseCategoriesResponse, DiscourseSiteResponse},
+    latest::DiscourseLatestResponse,
+    topic::DiscourseTopicResponse,
+    user::{DiscourseUserProfile, DiscourseUserSummaryResponse},
+};
+
+use super::{DiscourseConfig, TopicId};
+
+const USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/v3xlabs/ethereum-forum)"
+);
+const MAX_ATTEMPTS: u32 = 5;
+/// Used when a 429 response does not carry a usable `Retry-After` header
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(15 * 60);
+
+/// Result of a conditional request
+#[derive(Debug)]
+pub enum Fetched<T> {
+    Modified(T),
+    /// The server answered `304 Not Modified` for the validators we sent
+    NotModified,
+}
+
+#[derive(Debug, Clone)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// HTTP client shared by everything that talks to one discourse instance
+///
+/// Limits concurrency and request rate, waits out `429 Retry-After` for the whole
+/// instance, retries server errors with exponential backoff and remembers
+/// `ETag`/`Last-Modified` validators for conditional requests.
+pub struct DiscourseClient {
+    discourse_id: String,
+    base_url: String,
+    client: Client,
+    /// Without the instance credentials, for data served to anyone
+    public_client: Client,
+    permits: Semaphore,
+    limiter: DefaultDirectRateLimiter,
+    paused_until: RwLock<Option<Instant>>,
+    validators: Cache<String, Validators>,
+}
+
+impl DiscourseClient {
+    pub fn new(config: &DiscourseConfig) -> Self {
+        let build = |headers: HeaderMap| {
+            Client::builder()
+                .use_rustls_tls()
+                .user_agent(USER_AGENT)
+                .default_headers(headers)
+                .timeout(config.timeout().unwrap_or(Duration::from_secs(30)))
+                .build()
+                .expect("failed to build discourse http client")
+        };
+
+        Self {
+            discourse_id: config.discourse_id.clone(),
+            base_url: config.url.clone(),
+            client: build(credential_headers(config)),
+            public_client: build(HeaderMap::new()),
+            permits: Semaphore::new(config.max_concurrent_requests.max(1)),
+            limiter: RateLimiter::direct(
+                Quota::per_minute(
+                    NonZero::new(config.requests_per_minute).unwrap_or(NonZero::<u32>::MIN),
+                )
+                .allow_burst(NonZero::new(config.request_burst).unwrap_or(NonZero::<u32>::MIN)),
+            ),
+            paused_until: RwLock::new(None),
+            validators: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(Duration::from_secs(24 * 60 * 60))
+                .build(),
+        }
+    }
+
+    pub async fn fetch_latest_topics(&self) -> Result<Fetched<DiscourseLatestResponse>> {
+        self.get_json_if_modified("/latest.json").await
+    }
+
+    /// Fetch a page of a topic listing, `listing` is either `latest` or `c/<category_id>`
+    pub async fn fetch_listing_page(
+        &self,
+        listing: &str,
+        page: u32,
+    ) -> Result<DiscourseLatestResponse> {
+        self.get_json(&format!("/{}.json?page={}", listing, page))
+            .await
+    }
+
+    pub async fn fetch_categories(&self) -> Result<DiscourseCategoriesResponse> {
+        self.get_json("/categories.json").await
+    }
+
+    pub async fn fetch_site(&self) -> Result<DiscourseSiteResponse> {
+        self.get_json("/site.json").await
+    }
+
+    pub async fn fetch_topic(
+        &self,
+        topic_id: TopicId,
+        page: u32,
+    ) -> Result<Fetched<DiscourseTopicResponse>> {
+        self.get_json_if_modified(&topic_path(topic_id, page)).await
+    }
+
+    /// Drop the stored validators so the next fetch of the page is unconditional
+    pub async fn forget_topic(&self, topic_id: TopicId, page: u32) {
+        self.validators
+            .invalidate(&topic_path(topic_id, page))
+            .await;
+    }
+
+    /// Fetched without credentials, profiles are served to anonymous visitors and would
+    /// otherwise include activity in restricted categories
+    pub async fn fetch_user(&self, username: &str) -> Result<DiscourseUserProfile> {
+        let response = self
+            .send_with(&self.public_client, &format!("/u/{}.json", username), None)
+            .await?
+            .error_for_status()?;
+        let body = response.text().await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Fetched without credentials, like [`Self::fetch_user`]
+    pub async fn fetch_user_summary(&self, username: &str) -> Result<DiscourseUserSummaryResponse> {
+        let response = self
+            .send_with(
+                &self.public_client,
+                &format!("/u/{}/summary.json", username),
+                None,
+            )
+            .await?;
+
+        // Check if the response is a 404 (profile hidden or user not found)
+        if response.status() == StatusCode::NOT_FOUND {
+            // Return an empty summary response for hidden profiles
+            return Ok(DiscourseUserSummaryResponse {
+                topics: None,
+                badges: None,
+                badge_types: None,
+                users: None,
+                user_summary: None,
+            });
+        }
+
+        let body = response.error_for_status()?.text().await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self.send(path, None).await?.error_for_status()?;
+        let body = response.text().await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    async fn get_json_if_modified<T: DeserializeOwned>(&self, path: &str) -> Result<Fetched<T>> {
+        let validators = self.validators.get(path).await;
+        let response = self.send(path, validators.as_ref()).await?;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+
+        let response = response.error_for_status()?;
+        let validators = validators_from_headers(response.headers());
+        let body = response.text().await?;
+        let parsed = serde_json::from_str(&body)?;
+
+        match validators {
+            Some(validators) => self.validators.insert(path.to_string(), validators).await,
+            None => self.validators.invalidate(path).await,
+        }
+
+        Ok(Fetched::Modified(parsed))
+    }
+
+    /// Send a GET request within the instance limits, retrying 429s, 5xx responses
+    /// and transport errors
+    async fn send(&self, path: &str, validators: Option<&Validators>) -> Result<Response> {
+        self.send_with(&self.client, path, validators).await
+    }
+
+    async fn send_with(
+        &self,
+        client: &Client,
+        path: &str,
+        validators: Option<&Validators>,
+    ) -> Result<Response> {
+        let url = format!("{}{}", self.base_url, path);
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            self.wait_until_unpaused().await;
+
+            let permit = self.permits.acquire().await;
+            self.limiter.until_ready().await;
+
+            let mut request = client.get(&url);
+            if let Some(validators) = validators {
+                if let Some(etag) = &validators.etag {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &validators.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+
+            let backoff = Duration::from_secs(2u64.pow(attempt));
+            match request.send().await {
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    let retry_after = response
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| parse_retry_after(value, Utc::now()))
+                        .unwrap_or(DEFAULT_RETRY_AFTER)
+                        .min(MAX_RETRY_AFTER);
+
+                    warn!(
+                        "Rate limited by {} on {}, pausing for {:?}",
+                        self.discourse_id, path, retry_after
+                    );
+                    self.pause_for(retry_after).await;
+
+                    if attempt >= MAX_ATTEMPTS {
+                        return Err(anyhow!("rate limited by {} on {}", self.discourse_id, path));
+                    }
+                }
+                Ok(response) if response.status().is_server_error() && attempt < MAX_ATTEMPTS => {
+                    warn!(
+                        "{} returned {} for {} (attempt {}), retrying in {:?}",
+                        self.discourse_id,
+                        response.status(),
+                        path,
+                        attempt,
+                        backoff
+                    );
+                    // Other requests may go ahead while this one backs off
+                    drop(permit);
+                    async_std::task::sleep(backoff).await;
+                }
+                Ok(response) => return Ok(response),
+                Err(e)
+                    if attempt < MAX_ATTEMPTS
+                        && (e.is_timeout() || e.is_connect() || e.is_request()) =>
+                {
+                    warn!(
+                        "Request to {} for {} failed (attempt {}), retrying in {:?}: {:?}",
+                        self.discourse_id, path, attempt, backoff, e
+                    );
+                    drop(permit);
+                    async_std::task::sleep(backoff).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    async fn pause_for(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut paused_until = self.paused_until.write().await;
+        if paused_until.is_none_or(|current| current < until) {
+            *paused_until = Some(until);
+        }
+    }
+
+    async fn wait_until_unpaused(&self) {
+        let paused_until = *self.paused_until.read().await;
+        if let Some(until) = paused_until {
+            let now = Instant::now();
+            if until > now {
+                async_std::task::sleep(until - now).await;
+            }
+        }
+    }
+}
+
+/// `Api-Key`/`Api-Username` or `User-Api-Key` headers for instances with credentials
+fn credential_headers(config: &DiscourseConfig) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let mut insert = |name: &'static str, value: &str| match HeaderValue::from_str(value) {
+        Ok(mut value) => {
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+        Err(_) => warn!("Ignoring invalid {} for {}", name, config.discourse_id),
+    };
+
+    if let Some(api_key) = &config.api_key {
+        insert("Api-Key", api_key.expose());
+        insert(
+            "Api-Username",
+            config.api_username.as_deref().unwrap_or("system"),
+        );
+    }
+    if let Some(user_api_key) = &config.user_api_key {
+        insert("User-Api-Key", user_api_key.expose());
+    }
+
+    headers
+}
+
+fn topic_path(topic_id: TopicId, page: u32) -> String {
+    format!("/t/{}.json?page={}", topic_id, page)
+}
+
+fn validators_from_headers(headers: &HeaderMap) -> Option<Validators> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+
+    let validators = Validators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+
+    (validators.etag.is_some() || validators.last_modified.is_some()).then_some(validators)
+}
+
+/// Parse a `Retry-After` header, either delay-seconds or an HTTP date
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2025-06-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 01 Jun 2025 10:00:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        // Dates in the past mean "retry now"
+        assert_eq!(
+            parse_retry_after("Sun, 01 Jun 2025 09:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+}
@@ -0,0 +1,148 @@
+use regex::Regex;
+
+use crate::models::topics::link::{LinkKind, LinkedIssue};
+
+use super::config::IssueRule;
+
+struct CompiledRule {
+    kind: LinkKind,
+    regex: Regex,
+    repository: Option<String>,
+    categories: Vec<i32>,
+}
+
+/// The issue rules of one instance, compiled once when the indexer starts
+#[derive(Default)]
+pub struct IssueRules {
+    rules: Vec<CompiledRule>,
+}
+
+impl IssueRules {
+    pub fn compile(rules: &[IssueRule]) -> Result<Self, String> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let regex = Regex::new(&rule.pattern)
+                    .map_err(|e| format!("invalid pattern '{}': {}", rule.pattern, e))?;
+                if !regex.capture_names().any(|name| name == Some("number")) {
+                    return Err(format!(
+                        "pattern '{}' has no 'number' capture group",
+                        rule.pattern
+                    ));
+                }
+
+                Ok(CompiledRule {
+                    kind: rule.kind,
+                    regex,
+                    repository: rule.repository.clone(),
+                    categories: rule.categories.clone(),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self { rules })
+    }
+
+    /// Every reference in `cooked` matched by a rule that applies to `category_id`
+    pub fn extract(&self, category_id: Option<i32>, cooked: &str) -> Vec<LinkedIssue> {
+        let mut links: Vec<LinkedIssue> = Vec::new();
+
+        for rule in &self.rules {
+            if !rule.categories.is_empty()
+                && !category_id.is_some_and(|id| rule.categories.contains(&id))
+            {
+                continue;
+            }
+
+            for captures in rule.regex.captures_iter(cooked) {
+                let Some(number) = captures
+                    .name("number")
+                    .and_then(|number| number.as_str().parse().ok())
+                else {
+                    continue;
+                };
+                let repository = captures
+                    .name("repo")
+                    .map(|repo| repo.as_str().to_string())
+                    .or_else(|| rule.repository.clone())
+                    .unwrap_or_default();
+
+                let link = LinkedIssue {
+                    kind: rule.kind,
+                    repository,
+                    number,
+                    reference: captures[0].to_string(),
+                };
+                if !links.iter().any(|seen| {
+                    seen.kind == link.kind
+                        && seen.repository == link.repository
+                        && seen.number == link.number
+                }) {
+                    links.push(link);
+                }
+            }
+        }
+
+        links
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(kind: LinkKind, pattern: &str, categories: Vec<i32>) -> IssueRule {
+        IssueRule {
+            kind,
+            pattern: pattern.to_string(),
+            repository: None,
+            categories,
+        }
+    }
+
+    #[test]
+    fn test_extract_links() {
+        let rules = IssueRules::compile(&[
+            rule(
+                LinkKind::GithubIssue,
+                r"https://github\.com/(?P<repo>ethereum/pm)/issues/(?P<number>\d+)",
+                vec![63],
+            ),
+            rule(
+                LinkKind::GithubPr,
+                r"https://github\.com/(?P<repo>[\w.-]+/[\w.-]+)/pull/(?P<number>\d+)",
+                vec![],
+            ),
+            rule(LinkKind::Eip, r"\bEIP-(?P<number>\d+)", vec![]),
+        ])
+        .unwrap();
+
+        let cooked = r#"<p>Agenda: <a href="https://github.com/ethereum/pm/issues/1518">GitHub Issue</a>,
+            see EIP-7702 and <a href="https://github.com/ethereum/EIPs/pull/9000">the PR</a>, again EIP-7702</p>"#;
+
+        let links = rules.extract(Some(63), cooked);
+        assert_eq!(
+            links
+                .iter()
+                .map(|link| (link.kind, link.repository.as_str(), link.number))
+                .collect::<Vec<_>>(),
+            vec![
+                (LinkKind::GithubIssue, "ethereum/pm", 1518),
+                (LinkKind::GithubPr, "ethereum/EIPs", 9000),
+                (LinkKind::Eip, "", 7702),
+            ]
+        );
+        assert_eq!(LinkedIssue::pm_issue(&links), Some(1518));
+
+        // The pm rule is limited to category 63
+        let links = rules.extract(Some(5), cooked);
+        assert_eq!(links.len(), 2);
+        assert_eq!(LinkedIssue::pm_issue(&links), None);
+    }
+
+    #[test]
+    fn test_reject_invalid_rules() {
+        assert!(IssueRules::compile(&[rule(LinkKind::Eip, r"EIP-(\d+", vec![])]).is_err());
+        assert!(IssueRules::compile(&[rule(LinkKind::Eip, r"EIP-(\d+)", vec![])]).is_err());
+    }
+}
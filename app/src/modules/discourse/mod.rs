@@ -1,46 +1,57 @@
-use std::{collections::{HashMap, HashSet}, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{Arc, atomic::AtomicBool},
+    time::Duration,
+};
 
 use crate::{
     models::{
+        categories::Category,
+        crawl::job::{IndexJob, IndexPriority},
+        eips,
+        search::saved::SavedSearch,
+        user::{User, notification::Notification, subscription::Subscription},
         discourse::{
-            latest::DiscourseLatestResponse,
-            topic::DiscourseTopicResponse,
+            topic::DiscourseTopicPost,
             user::{DiscourseUserProfile, DiscourseUserSummaryResponse},
         },
-        topics::{post::Post, Topic},
+        topics::{
+            link::{LinkedIssue, TopicLink},
+            post::{Post, PostChange},
+            Topic, TopicAccess, VISIBILITY_PUBLIC, VISIBILITY_RESTRICTED,
+        },
     },
+    modules::{search::embeddings, sso::JWTClaims},
     state::AppState,
 };
 use anyhow::{Error, Result};
 use async_std::{
     channel::{Receiver, Sender},
-    sync::Mutex,
+    sync::RwLock,
 };
-use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use chrono::{DateTime, Datelike, TimeDelta, Utc};
 use moka::future::Cache;
-use poem_openapi::types::{ParseFromJSON, ToJSON, Type};
+use poem_openapi::{
+    Object,
+    types::{ParseFromJSON, ToJSON, Type},
+};
 use serde::{Deserialize, Serialize};
 use strip_tags::strip_tags;
 use tracing::{error, info};
+use uuid::Uuid;
 
-pub async fn fetch_latest_topics(discourse_url: &str) -> Result<DiscourseLatestResponse, Error> {
-    let url = format!("{}/latest.json", discourse_url);
-    let response = reqwest::get(url).await?;
-    let body = response.text().await?;
-    let parsed: DiscourseLatestResponse = serde_json::from_str(&body)?;
-    Ok(parsed)
-}
+pub mod backfill;
+pub mod config;
+pub mod http;
+pub mod issues;
+pub mod schedule;
+pub mod webhook;
 
-pub async fn fetch_topic(discourse_url: &str, topic_id: TopicId, page: u32) -> Result<DiscourseTopicResponse, Error> {
-    let url = format!(
-        "{}/t/{}.json?page={}",
-        discourse_url, topic_id, page
-    );
-    let response = reqwest::get(url).await?;
-    let body = response.text().await?;
-    let parsed: DiscourseTopicResponse = serde_json::from_str(&body)?;
-    Ok(parsed)
-}
+pub use config::{DiscourseConfig, DiscourseConfigError, init_discourse_configs};
+use http::{DiscourseClient, Fetched};
+use issues::IssueRules;
+use schedule::ScrapeSchedule;
+use webhook::{WebhookError, WebhookEvent};
 
 pub type TopicId = i32;
 
@@ -57,26 +68,110 @@ pub struct ForumSearchDocument {
     pub slug: Option<String>,
     pub pm_issue: Option<i32>,
     pub cooked: Option<String>,
+    /// `public` or `restricted`, missing on documents indexed before visibility was tracked
+    pub visibility: Option<String>,
+    pub category_id: Option<i32>,
+    pub tags: Option<Vec<String>>,
+    /// Unix timestamp, used for date range filters
+    pub created_at: Option<i64>,
+    /// Facet bucket of `created_at`, documents indexed before it was added need a reindex
+    pub created_year: Option<i32>,
+    /// Unix timestamp of the database rows the document was built from, compared
+    /// when reconciling the index with the database. Posts take the later of their own
+    /// and their topic's, as they copy its visibility, category and tags
+    pub updated_at: Option<i64>,
+    /// `{discourse_id}_topic_{topic_id}` or `{discourse_id}_post_{post_id}`, the ids
+    /// repeat across instances
     pub entity_id: String,
+    /// Embeddings by embedder name, only set when semantic search is enabled
+    #[serde(rename = "_vectors", default, skip_serializing_if = "Option::is_none")]
+    pub vectors: Option<HashMap<String, Vec<f32>>>,
 }
 
-#[derive(Debug)]
-pub struct DiscourseTopicIndexRequest {
-    pub topic_id: TopicId,
-    pub page: u32,
-}
+impl ForumSearchDocument {
+    pub fn topic_entity_id(discourse_id: &str, topic_id: i32) -> String {
+        format!("{}_topic_{}", discourse_id, topic_id)
+    }
 
-#[derive(Debug, Clone, poem_openapi::Union)]
-pub enum LResult<T: Send + Sync + Type + ToJSON + ParseFromJSON> {
-    Failed(String),
-    Success(T),
+    pub fn post_entity_id(discourse_id: &str, post_id: i32) -> String {
+        format!("{}_post_{}", discourse_id, post_id)
+    }
+
+    pub fn from_topic(topic: &Topic) -> Self {
+        Self {
+            entity_type: "topic".to_string(),
+            discourse_id: Some(topic.discourse_id.clone()),
+            topic_id: Some(topic.topic_id),
+            post_id: None,
+            post_number: None,
+            user_id: None,
+            username: None,
+            title: Some(topic.title.clone()),
+            slug: Some(topic.slug.clone()),
+            pm_issue: topic.pm_issue,
+            cooked: None,
+            visibility: Some(topic.visibility.clone()),
+            category_id: topic.category_id,
+            tags: Some(topic.tags.clone()),
+            created_at: Some(topic.created_at.timestamp()),
+            created_year: Some(topic.created_at.year()),
+            updated_at: Some(topic.updated_at.timestamp()),
+            entity_id: Self::topic_entity_id(&topic.discourse_id, topic.topic_id),
+            vectors: None,
+        }
+    }
+
+    /// Posts inherit the visibility, category and tags of their topic, and are public
+    /// when it is unknown
+    pub fn from_post(post: &Post, username: Option<String>, topic: Option<&Topic>) -> Self {
+        Self {
+            entity_type: "post".to_string(),
+            discourse_id: Some(post.discourse_id.clone()),
+            topic_id: Some(post.topic_id),
+            post_id: Some(post.post_id),
+            post_number: Some(post.post_number),
+            user_id: Some(post.user_id),
+            username,
+            title: None,
+            slug: None,
+            pm_issue: None,
+            cooked: post.cooked.as_deref().map(strip_tags),
+            visibility: Some(
+                topic
+                    .map(|topic| topic.visibility.as_str())
+                    .unwrap_or(VISIBILITY_PUBLIC)
+                    .to_string(),
+            ),
+            category_id: topic.and_then(|topic| topic.category_id),
+            tags: topic.map(|topic| topic.tags.clone()),
+            created_at: post.created_at.map(|created_at| created_at.timestamp()),
+            created_year: post.created_at.map(|created_at| created_at.year()),
+            updated_at: post
+                .updated_at
+                .max(topic.map(|topic| topic.updated_at))
+                .map(|updated_at| updated_at.timestamp()),
+            entity_id: Self::post_entity_id(&post.discourse_id, post.post_id),
+            vectors: None,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct DiscourseConfig {
+/// How often an idle indexer checks the job table for work enqueued elsewhere
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct DiscourseIndexerSchedule {
     pub discourse_id: String,
     pub url: String,
     pub scrape_interval: String,
+    pub last_run: Option<DateTime<Utc>>,
+    pub next_run: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, poem_openapi::Union)]
+pub enum LResult<T: Send + Sync + Type + ToJSON + ParseFromJSON> {
+    Failed(String),
+    Success(T),
 }
 
 /// Main service that manages multiple discourse instances
@@ -122,25 +217,141 @@ impl DiscourseService {
         }
     }
 
-    pub async fn enqueue(&self, discourse_id: &str, topic_id: TopicId, page: u32) -> Result<(), Error> {
+    pub async fn enqueue(
+        &self,
+        discourse_id: &str,
+        topic_id: TopicId,
+        page: u32,
+        priority: IndexPriority,
+        state: &AppState,
+    ) -> Result<(), Error> {
         if let Some(indexer) = self.indexers.get(discourse_id) {
-            indexer.enqueue(topic_id, page).await;
+            indexer.enqueue(topic_id, page, priority, state).await?;
             Ok(())
         } else {
             Err(anyhow::anyhow!("Discourse instance '{}' not found", discourse_id))
         }
     }
 
+    /// Verify a webhook delivery against the instance secret and index the event
+    pub async fn handle_webhook(
+        &self,
+        discourse_id: &str,
+        event: &str,
+        signature: Option<&str>,
+        body: &[u8],
+        state: &AppState,
+    ) -> Result<(), WebhookError> {
+        let indexer = self
+            .indexers
+            .get(discourse_id)
+            .ok_or_else(|| WebhookError::UnknownInstance(discourse_id.to_string()))?;
+        let secret = indexer
+            .config
+            .webhook_secret
+            .as_ref()
+            .ok_or_else(|| WebhookError::NotEnabled(discourse_id.to_string()))?;
+
+        if !signature.is_some_and(|signature| webhook::verify_signature(secret.expose(), body, signature)) {
+            return Err(WebhookError::InvalidSignature);
+        }
+
+        let event = WebhookEvent::parse(event, body)?;
+        indexer.handle_webhook(event, state).await?;
+        Ok(())
+    }
+
+    /// Start a backfill crawl in the background, returns false if one is already running
+    pub fn start_backfill(&self, discourse_id: &str, state: AppState, restart: bool) -> Result<bool, Error> {
+        let indexer = self
+            .indexers
+            .get(discourse_id)
+            .ok_or_else(|| anyhow::anyhow!("Discourse instance '{}' not found", discourse_id))?;
+
+        if indexer.is_backfilling() {
+            return Ok(false);
+        }
+
+        let indexer = Arc::clone(indexer);
+        async_std::task::spawn(async move {
+            if let Err(e) = indexer.backfill(&state, restart).await {
+                error!("Backfill for {} failed: {:?}", indexer.config.discourse_id, e);
+            }
+        });
+
+        Ok(true)
+    }
+
+    pub fn is_backfilling(&self, discourse_id: &str) -> Option<bool> {
+        self.indexers.get(discourse_id).map(|indexer| indexer.is_backfilling())
+    }
+
+    /// Last and next scheduled `/latest.json` fetch for every instance
+    pub async fn schedules(&self) -> Vec<DiscourseIndexerSchedule> {
+        let mut schedules = Vec::with_capacity(self.indexers.len());
+        for indexer in self.indexers.values() {
+            schedules.push(indexer.schedule_info().await);
+        }
+        schedules.sort_by(|a, b| a.discourse_id.cmp(&b.discourse_id));
+        schedules
+    }
+
     pub fn get_discourse_url(&self, discourse_id: &str) -> Option<String> {
         self.indexers.get(discourse_id).map(|indexer| indexer.config.url.clone())
     }
 
+    /// Instances whose restricted topics the signed in user may read
+    pub fn access_for(&self, claims: Option<&JWTClaims>) -> TopicAccess {
+        let Some(claims) = claims else {
+            return TopicAccess::public();
+        };
+
+        self.access_for_sign_in(&claims.provider, &claims.email)
+    }
+
+    /// Instances whose restricted topics a stored user may read, for work done without
+    /// their session such as feeds and digests
+    pub fn access_for_user(&self, user: &User) -> TopicAccess {
+        self.access_for_sign_in(&user.sso_provider, user.email.as_deref().unwrap_or_default())
+    }
+
+    /// Instances whose restricted topics a user signed in through `sso_provider` with
+    /// `email` may read
+    pub fn access_for_sign_in(&self, sso_provider: &str, email: &str) -> TopicAccess {
+        let mut discourse_ids: Vec<String> = self
+            .indexers
+            .values()
+            .filter(|indexer| indexer.config.grants_restricted_access(sso_provider, email))
+            .map(|indexer| indexer.config.discourse_id.clone())
+            .collect();
+        discourse_ids.sort();
+
+        TopicAccess::with_restricted(discourse_ids)
+    }
+
+    /// Subscribers the instance currently grants access to its restricted topics, for
+    /// notifications sent without their session
+    pub async fn subscription_readers(
+        &self,
+        discourse_id: &str,
+        state: &AppState,
+    ) -> Result<Vec<Uuid>, sqlx::Error> {
+        match self.indexers.get(discourse_id) {
+            Some(indexer) => indexer.subscription_readers(state).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn get_client(&self, discourse_id: &str) -> Option<Arc<DiscourseClient>> {
+        self.indexers.get(discourse_id).map(|indexer| Arc::clone(&indexer.client))
+    }
+
     pub async fn fetch_discourse_user_cached(
         &self,
         discourse_id: &str,
         username: &str,
     ) -> Result<LResult<DiscourseUserProfile>, Error> {
-        let discourse_url = self.get_discourse_url(discourse_id)
+        let client = self.get_client(discourse_id)
             .ok_or_else(|| anyhow::anyhow!("Discourse instance '{}' not found", discourse_id))?;
         
         let cache_key = format!("{}:{}", discourse_id, username);
@@ -148,7 +359,7 @@ impl DiscourseService {
         
         Ok(self.user_profile_cache
             .get_with(cache_key, async move {
-                match Self::fetch_discourse_user(&discourse_url, &username).await {
+                match client.fetch_user(&username).await {
                     Ok(user) => LResult::Success(user),
                     Err(e) => LResult::Failed(e.to_string()),
                 }
@@ -161,7 +372,7 @@ impl DiscourseService {
         discourse_id: &str,
         username: &str,
     ) -> Result<LResult<DiscourseUserSummaryResponse>, Error> {
-        let discourse_url = self.get_discourse_url(discourse_id)
+        let client = self.get_client(discourse_id)
             .ok_or_else(|| anyhow::anyhow!("Discourse instance '{}' not found", discourse_id))?;
         
         let cache_key = format!("{}:{}", discourse_id, username);
@@ -169,63 +380,58 @@ impl DiscourseService {
         
         Ok(self.user_summary_cache
             .get_with(cache_key, async move {
-                match Self::fetch_discourse_user_summary(&discourse_url, &username).await {
+                match client.fetch_user_summary(&username).await {
                     Ok(user) => LResult::Success(user),
                     Err(e) => LResult::Failed(e.to_string()),
                 }
             })
             .await)
     }
-
-    pub async fn fetch_discourse_user(discourse_url: &str, username: &str) -> anyhow::Result<DiscourseUserProfile> {
-        let url = format!("{}/u/{}.json", discourse_url, username);
-        let response = reqwest::get(url).await?;
-        let body = response.text().await?;
-        let parsed: DiscourseUserProfile = serde_json::from_str(&body)?;
-        Ok(parsed)
-    }
-
-    pub async fn fetch_discourse_user_summary(
-        discourse_url: &str,
-        username: &str,
-    ) -> Result<DiscourseUserSummaryResponse> {
-        let url = format!("{}/u/{}/summary.json", discourse_url, username);
-        let response = reqwest::get(url).await?;
-        
-        // Check if the response is a 404 (profile hidden or user not found)
-        if response.status() == reqwest::StatusCode::NOT_FOUND {
-            // Return an empty summary response for hidden profiles
-            return Ok(DiscourseUserSummaryResponse {
-                topics: None,
-                badges: None,
-                badge_types: None,
-                users: None,
-                user_summary: None,
-            });
-        }
-        
-        let body = response.text().await?;
-        let parsed: DiscourseUserSummaryResponse = serde_json::from_str(&body)?;
-        Ok(parsed)
-    }
 }
 
 /// Individual indexer for a single discourse instance
 pub struct DiscourseIndexer {
     config: DiscourseConfig,
-    topic_tx: Sender<DiscourseTopicIndexRequest>,
-    topic_lock: Arc<Mutex<HashSet<(TopicId, u32)>>>,
-    topic_rx: Receiver<DiscourseTopicIndexRequest>,
+    client: Arc<DiscourseClient>,
+    /// Wakes the worker when a job is enqueued from this process
+    wake_tx: Sender<()>,
+    wake_rx: Receiver<()>,
+    backfill_running: AtomicBool,
+    /// Category id to `read_restricted`, as seen through the configured credentials
+    categories: RwLock<HashMap<i32, bool>>,
+    issue_rules: IssueRules,
+    last_run: RwLock<Option<DateTime<Utc>>>,
+    next_run: RwLock<Option<DateTime<Utc>>>,
 }
 
 impl DiscourseIndexer {
     pub fn new(config: DiscourseConfig) -> Self {
-        let (topic_tx, topic_rx) = async_std::channel::unbounded();
+        let (wake_tx, wake_rx) = async_std::channel::bounded(1);
+        // Rules are validated when the config is loaded
+        let issue_rules = IssueRules::compile(&config.issue_rules).unwrap_or_else(|e| {
+            error!("Invalid issue rules for {}: {}", config.discourse_id, e);
+            IssueRules::default()
+        });
         Self {
+            client: Arc::new(DiscourseClient::new(&config)),
             config,
-            topic_tx,
-            topic_lock: Arc::new(Mutex::new(HashSet::new())),
-            topic_rx,
+            wake_tx,
+            wake_rx,
+            backfill_running: AtomicBool::new(false),
+            categories: RwLock::new(HashMap::new()),
+            issue_rules,
+            last_run: RwLock::new(None),
+            next_run: RwLock::new(None),
+        }
+    }
+
+    pub async fn schedule_info(&self) -> DiscourseIndexerSchedule {
+        DiscourseIndexerSchedule {
+            discourse_id: self.config.discourse_id.clone(),
+            url: self.config.url.clone(),
+            scrape_interval: self.config.scrape_interval.clone(),
+            last_run: *self.last_run.read().await,
+            next_run: *self.next_run.read().await,
         }
     }
 
@@ -237,131 +443,148 @@ impl DiscourseIndexer {
             indexer_clone.fetch_periodically(&state_clone).await;
         });
 
-        info!("Started indexer for {}, awaiting requests", self.config.discourse_id);
+        if self.config.backfill {
+            let state_clone = state.clone();
+            let indexer_clone = Arc::clone(&self);
+            async_std::task::spawn(async move {
+                if let Err(e) = indexer_clone.backfill(&state_clone, false).await {
+                    error!("Backfill for {} failed: {:?}", indexer_clone.config.discourse_id, e);
+                }
+            });
+        }
 
-        // Process topic indexing requests
-        while let Ok(request) = self.topic_rx.recv().await {
-            info!("Processing request for {}: {:?}", self.config.discourse_id, request);
-
-            if let Ok(topic) = fetch_topic(&self.config.url, request.topic_id, request.page).await {
-                let existing_topic = Topic::get_by_topic_id(&self.config.discourse_id, topic.id, &state).await.ok();
-                let existing_messages = if let Some(existing) = &existing_topic {
-                    Post::count_by_topic_id(&self.config.discourse_id, existing.topic_id, &state)
-                        .await
-                        .unwrap_or(0)
-                } else {
-                    0
-                };
+        info!("Started indexer for {}, awaiting requests", self.config.discourse_id);
 
-                let worth_fetching_more = existing_messages != topic.posts_count || {
-                    let existing = existing_topic.unwrap();
-                    let zero = DateTime::<Utc>::MIN_UTC;
-                    let existing_time = existing.last_post_at.unwrap_or(zero);
+        // Process topic indexing jobs, highest priority first
+        loop {
+            let job = match IndexJob::claim(&self.config.discourse_id, &state).await {
+                Ok(Some(job)) => job,
+                Ok(None) => {
+                    // Queue is empty, wait for a local enqueue or poll again later
+                    let _ = async_std::future::timeout(QUEUE_POLL_INTERVAL, self.wake_rx.recv()).await;
+                    continue;
+                }
+                Err(e) => {
+                    error!("Error claiming index job for {}: {:?}", self.config.discourse_id, e);
+                    async_std::task::sleep(QUEUE_POLL_INTERVAL).await;
+                    continue;
+                }
+            };
 
-                    existing.post_count != topic.posts_count
-                        || existing_time < topic.last_posted_at
-                        || existing_messages < topic.posts_count
-                };
+            info!("Processing job for {}: {:?}", self.config.discourse_id, job);
 
-                if !worth_fetching_more {
-                    info!(
-                        "Topic {:?} is up to date ({} -> {}) skipping",
-                        topic.id, existing_messages, topic.posts_count
-                    );
-                    self.topic_lock
-                        .lock()
-                        .await
-                        .remove(&(request.topic_id, request.page));
-                    continue;
-                } else {
-                    info!(
-                        "Topic {:?} ({} -> {}) is worth fetching more, fetching",
-                        topic.id, existing_messages, topic.posts_count
+            let result = match self.index_job(&job, &state).await {
+                Ok(_) => job.complete(&state).await,
+                Err(e) => {
+                    error!(
+                        "Error indexing topic {} page {} for {} (attempt {}): {:?}",
+                        job.topic_id, job.page, self.config.discourse_id, job.attempts, e
                     );
+                    // Don't let a 304 hide the page from the retry
+                    self.client.forget_topic(job.topic_id, job.page as u32).await;
+                    job.fail(&e.to_string(), &state).await
                 }
+            };
 
-                if !topic.post_stream.posts.is_empty() {
-                    self.enqueue(request.topic_id, request.page + 1).await;
-                }
+            if let Err(e) = result {
+                error!("Error updating index job {}: {:?}", job.job_id, e);
+            }
+        }
+    }
 
-                if request.page == 1 {
-                    let topic_model = Topic::from_discourse(&self.config.discourse_id, &topic);
-
-                    match topic_model.upsert(&state).await {
-                        Ok(_) => {
-                            info!("Upserted topic: {:?}", topic_model.topic_id);
-
-                            if let Some(meili) = &state.meili {
-                                let meili_doc = ForumSearchDocument {
-                                    entity_type: "topic".to_string(),
-                                    discourse_id: Some(self.config.discourse_id.clone()),
-                                    topic_id: Some(topic_model.topic_id),
-                                    post_id: None,
-                                    post_number: None,
-                                    user_id: None,
-                                    username: None,
-                                    title: Some(topic_model.title.clone()),
-                                    slug: Some(topic_model.slug.clone()),
-                                    pm_issue: topic_model.pm_issue,
-                                    cooked: None,
-                                    entity_id: format!("topic_{}", topic_model.topic_id),
-                                };
-
-                                let forum = meili.index("forum");
-
-                                if let Err(e) = forum
-                                    .add_documents(&[meili_doc], Some("entity_id"))
-                                    .await
-                                    .map_err(|e| {
-                                        sqlx::Error::Io(std::io::Error::new(
-                                            std::io::ErrorKind::Other,
-                                            e.to_string(),
-                                        ))
-                                    })
-                                {
-                                    error!("Error upserting topic to Meilisearch: {:?}", e);
-                                }
-                            }
-                        }
-                        Err(e) => error!("Error upserting topic: {:?}", e),
-                    }
-                }
+    /// Fetch one page of a topic and upsert the topic and its posts
+    async fn index_job(&self, job: &IndexJob, state: &AppState) -> Result<()> {
+        let page = job.page as u32;
 
-                // Process posts
-                let mut meili_docs = Vec::new();
-                for discourse_post in topic.post_stream.posts {
-                    let username = discourse_post.username.clone();
-                    let post = Post::from_discourse(&self.config.discourse_id, discourse_post);
-                    match post.upsert(&state).await {
-                        Ok(_) => {
-                            info!("Upserted post: {:?}", post.post_id);
-
-                            if state.meili.is_some() {
-                                meili_docs.push(ForumSearchDocument {
-                                    entity_type: "post".to_string(),
-                                    discourse_id: Some(self.config.discourse_id.clone()),
-                                    topic_id: Some(post.topic_id),
-                                    post_id: Some(post.post_id),
-                                    post_number: Some(post.post_number),
-                                    user_id: Some(post.user_id),
-                                    username: Some(username),
-                                    title: None,
-                                    slug: None,
-                                    pm_issue: None,
-                                    cooked: post.cooked.as_deref().map(strip_tags),
-                                    entity_id: format!("post_{}", post.post_id),
-                                });
-                            }
-                        }
-                        Err(e) => error!("Error upserting post: {:?}", e),
+        // A user refresh always refetches, even if the page looks unchanged
+        if job.priority() == IndexPriority::Refresh {
+            self.client.forget_topic(job.topic_id, page).await;
+        }
+
+        let topic = match self.client.fetch_topic(job.topic_id, page).await? {
+            Fetched::Modified(topic) => topic,
+            Fetched::NotModified => {
+                info!("Topic {} page {} is not modified, skipping", job.topic_id, page);
+                return Ok(());
+            }
+        };
+
+        let visibility = self
+            .visibility_for(topic.extra.get("category_id").and_then(|id| id.as_i64()), state)
+            .await;
+
+        let existing_topic = Topic::get_by_topic_id(&self.config.discourse_id, topic.id, state).await.ok();
+        let existing_messages = if let Some(existing) = &existing_topic {
+            Post::count_by_topic_id(&self.config.discourse_id, existing.topic_id, state)
+                .await
+                .unwrap_or(0)
+        } else {
+            0
+        };
+
+        // Edits change neither the post count nor `last_posted_at`, refreshes always go
+        // through and polls compare the versions of the fetched posts
+        let worth_fetching_more = job.priority() == IndexPriority::Refresh
+            || existing_messages != topic.posts_count
+            || existing_topic.as_ref().is_none_or(|existing| {
+                let zero = DateTime::<Utc>::MIN_UTC;
+                let existing_time = existing.last_post_at.unwrap_or(zero);
+
+                existing.post_count != topic.posts_count
+                    || existing_time < topic.last_posted_at
+                    || existing_messages < topic.posts_count
+            })
+            || Post::any_outdated(&self.config.discourse_id, &topic.post_stream.posts, state).await?;
+
+        if !worth_fetching_more {
+            info!(
+                "Topic {:?} is up to date ({} -> {}) skipping",
+                topic.id, existing_messages, topic.posts_count
+            );
+            return Ok(());
+        } else {
+            info!(
+                "Topic {:?} ({} -> {}) is worth fetching more, fetching",
+                topic.id, existing_messages, topic.posts_count
+            );
+        }
+
+        if !topic.post_stream.posts.is_empty() {
+            self.enqueue(job.topic_id, page + 1, job.priority(), state).await?;
+        }
+
+        let mut topic_model = Topic::from_discourse(&self.config.discourse_id, &topic);
+        topic_model.visibility = visibility.to_string();
+
+        if page == 1 {
+            let links = topic
+                .post_stream
+                .posts
+                .iter()
+                .find(|post| post.post_number == 1)
+                .map(|post| self.issue_rules.extract(topic_model.category_id, &post.cooked))
+                .unwrap_or_default();
+            topic_model.pm_issue = LinkedIssue::pm_issue(&links);
+
+            match topic_model.upsert(state).await {
+                Ok(_) => {
+                    info!("Upserted topic: {:?}", topic_model.topic_id);
+
+                    if let Err(e) =
+                        TopicLink::replace_for_topic(&self.config.discourse_id, topic_model.topic_id, &links, state).await
+                    {
+                        error!("Error storing topic links: {:?}", e);
                     }
-                }
 
-                if let Some(meili) = &state.meili {
-                    if !meili_docs.is_empty() {
+                    if let Some(meili) = &state.meili {
+                        let mut meili_doc = ForumSearchDocument::from_topic(&topic_model);
+
+                        embeddings::embed_documents(std::slice::from_mut(&mut meili_doc), state).await;
+
                         let forum = meili.index("forum");
+
                         if let Err(e) = forum
-                            .add_documents(&meili_docs, Some("entity_id"))
+                            .add_documents(&[meili_doc], Some("entity_id"))
                             .await
                             .map_err(|e| {
                                 sqlx::Error::Io(std::io::Error::new(
@@ -370,45 +593,275 @@ impl DiscourseIndexer {
                                 ))
                             })
                         {
-                            error!("Error bulk upserting posts to Meilisearch: {:?}", e);
+                            error!("Error upserting topic to Meilisearch: {:?}", e);
                         }
                     }
                 }
+                // Fails the job, which drops the page's validators so the retry refetches it
+                Err(e) => return Err(e.into()),
             }
+        }
 
-            self.topic_lock
-                .lock()
-                .await
-                .remove(&(request.topic_id, request.page));
+        // The first page lists every post id, anything missing from it was deleted
+        if let Some(stream) = &topic.post_stream.stream {
+            let deleted =
+                Post::soft_delete_missing(&self.config.discourse_id, topic.id, stream, state).await?;
+            if !deleted.is_empty() {
+                info!("Soft-deleted posts {:?} of topic {}", deleted, topic.id);
+                self.remove_posts(&deleted, state).await;
+            }
         }
 
-        error!("Indexer for {} stopped", self.config.discourse_id);
-    }
-
-    pub async fn enqueue(&self, topic_id: TopicId, page: u32) {
-        info!("Enqueuing topic {:?} page {} for {}", topic_id, page, self.config.discourse_id);
-        let mut set = self.topic_lock.lock().await;
-        let key = (topic_id, page);
-        if set.insert(key) {
-            let _ = self
-                .topic_tx
-                .send(DiscourseTopicIndexRequest { 
-                    topic_id, 
-                    page 
-                })
-                .await;
-            info!("Enqueued topic {:?} page {} for {}", topic_id, page, self.config.discourse_id);
-        } else {
-            info!("Topic {:?} page {} is already enqueued for {}, skipping", topic_id, page, self.config.discourse_id);
+        // Later pages don't store the topic, the stored row holds the `updated_at` their
+        // post documents are versioned by
+        let topic_model = match (page, existing_topic) {
+            (1, _) => topic_model,
+            (_, Some(existing)) => existing,
+            (_, None) => anyhow::bail!(
+                "topic {} is not stored yet, its first page stores it",
+                topic.id
+            ),
+        };
+
+        self.upsert_posts(topic.post_stream.posts, &topic_model, state).await
+    }
+
+    /// Upsert posts into the database and the search index, then match them against
+    /// saved searches and notify subscribers. `topic` provides the visibility,
+    /// category and tags of the post documents
+    ///
+    /// Every post is attempted, an error is returned afterwards if any of them failed
+    async fn upsert_posts(&self, posts: Vec<DiscourseTopicPost>, topic: &Topic, state: &AppState) -> Result<()> {
+        let mut failed = 0;
+        let mut meili_docs = Vec::new();
+        let mut upserted = Vec::new();
+        let mut created = Vec::new();
+        let mut edited = Vec::new();
+        for discourse_post in posts {
+            let username = discourse_post.username.clone();
+            let post = Post::from_discourse(&self.config.discourse_id, discourse_post);
+            match post.upsert(state).await {
+                Ok(change) => {
+                    info!("Upserted post: {:?}", post.post_id);
+                    upserted.push(post.post_id);
+                    match change {
+                        PostChange::Created => created.push(post.post_id),
+                        PostChange::Edited => edited.push(post.post_id),
+                        PostChange::Unchanged => {}
+                    }
+
+                    let references = eips::extract_eip_references(post.cooked.as_deref().unwrap_or_default());
+                    if let Err(e) = eips::replace_for_post(&post, &references, state).await {
+                        error!("Error storing EIP references of post {}: {:?}", post.post_id, e);
+                    }
+
+                    if state.meili.is_some() {
+                        meili_docs.push(ForumSearchDocument::from_post(&post, Some(username), Some(topic)));
+                    }
+                }
+                Err(e) => {
+                    error!("Error upserting post: {:?}", e);
+                    failed += 1;
+                }
+            }
+        }
+
+        if !upserted.is_empty() {
+            match self.record_saved_search_matches(&upserted, state).await {
+                Ok(0) => {}
+                Ok(matches) => info!("Recorded {} saved search matches", matches),
+                Err(e) => error!("Error matching saved searches: {:?}", e),
+            }
+        }
+
+        if !created.is_empty() || !edited.is_empty() {
+            match self.record_notifications(&created, &edited, state).await {
+                Ok(0) => {}
+                Ok(notifications) => info!("Recorded {} notifications", notifications),
+                Err(e) => error!("Error notifying subscribers: {:?}", e),
+            }
+        }
+
+        if let Some(meili) = &state.meili {
+            if !meili_docs.is_empty() {
+                embeddings::embed_documents(&mut meili_docs, state).await;
+
+                let forum = meili.index("forum");
+                if let Err(e) = forum
+                    .add_documents(&meili_docs, Some("entity_id"))
+                    .await
+                    .map_err(|e| {
+                        sqlx::Error::Io(std::io::Error::new(
+                            std::io::ErrorKind::Other,
+                            e.to_string(),
+                        ))
+                    })
+                {
+                    error!("Error bulk upserting posts to Meilisearch: {:?}", e);
+                }
+            }
+        }
+
+        if failed > 0 {
+            return Err(anyhow::anyhow!(
+                "failed to upsert {} post(s) of topic {}",
+                failed,
+                topic.topic_id
+            ));
         }
+
+        Ok(())
+    }
+
+    /// Match posts against saved searches, restricted topics only match for owners this
+    /// instance currently grants access
+    async fn record_saved_search_matches(&self, post_ids: &[i32], state: &AppState) -> Result<u64, sqlx::Error> {
+        let owners = SavedSearch::find_owners(&self.config.discourse_id, state).await?;
+        let readers = self.readers(owners);
+
+        SavedSearch::record_matches(&self.config.discourse_id, post_ids, &readers, state).await
+    }
+
+    /// Notify subscribers of created and edited posts, restricted topics only notify
+    /// subscribers this instance currently grants access
+    async fn record_notifications(&self, created: &[i32], edited: &[i32], state: &AppState) -> Result<u64, sqlx::Error> {
+        let readers = self.subscription_readers(state).await?;
+
+        Notification::record_posts(&self.config.discourse_id, created, edited, &readers, state).await
+    }
+
+    async fn subscription_readers(&self, state: &AppState) -> Result<Vec<Uuid>, sqlx::Error> {
+        let subscribers = Subscription::find_subscribers(&self.config.discourse_id, state).await?;
+        Ok(self.readers(subscribers))
+    }
+
+    /// The users this instance grants access to its restricted topics
+    fn readers(&self, users: Vec<User>) -> Vec<Uuid> {
+        users
+            .into_iter()
+            .filter(|user| {
+                self.config
+                    .grants_restricted_access(&user.sso_provider, user.email.as_deref().unwrap_or_default())
+            })
+            .map(|user| user.user_id)
+            .collect()
+    }
+
+    /// Drop deleted posts from the search index
+    async fn remove_posts(&self, post_ids: &[i32], state: &AppState) {
+        let Some(meili) = &state.meili else {
+            return;
+        };
+
+        let entity_ids: Vec<String> = post_ids
+            .iter()
+            .map(|post_id| ForumSearchDocument::post_entity_id(&self.config.discourse_id, *post_id))
+            .collect();
+        if let Err(e) = meili.index("forum").delete_documents(&entity_ids).await {
+            error!("Error deleting posts from Meilisearch: {:?}", e);
+        }
+    }
+
+    /// Store the categories of the instance, `/site.json` is used over `/categories.json`
+    /// as it includes subcategories
+    pub async fn refresh_categories(&self, state: &AppState) -> Result<()> {
+        let site = self.client.fetch_site().await?;
+        let categories = site
+            .categories
+            .iter()
+            .map(|category| (category.id, category.read_restricted.unwrap_or(false)))
+            .collect();
+        *self.categories.write().await = categories;
+
+        Category::sync(&self.config.discourse_id, &site.categories, state).await?;
+        Ok(())
+    }
+
+    /// Topics in `read_restricted` categories are restricted
+    ///
+    /// Topics in categories we haven't seen yet are treated as restricted on
+    /// instances with credentials, so nothing private leaks before the category
+    /// list catches up.
+    async fn visibility_for(&self, category_id: Option<i64>, state: &AppState) -> &'static str {
+        let Some(category_id) = category_id else {
+            return VISIBILITY_PUBLIC;
+        };
+        let category_id = category_id as i32;
+
+        if !self.categories.read().await.contains_key(&category_id)
+            && let Err(e) = self.refresh_categories(state).await
+        {
+            error!("Error fetching categories for {}: {:?}", self.config.discourse_id, e);
+        }
+
+        match self.categories.read().await.get(&category_id) {
+            Some(true) => VISIBILITY_RESTRICTED,
+            Some(false) => VISIBILITY_PUBLIC,
+            None if self.config.has_credentials() => VISIBILITY_RESTRICTED,
+            None => VISIBILITY_PUBLIC,
+        }
+    }
+
+    /// Apply a verified webhook event
+    ///
+    /// Posts are upserted straight from the payload, the topic itself is refreshed
+    /// through the queue at the highest priority. Polling keeps running as a fallback
+    /// for deliveries that never arrive.
+    pub async fn handle_webhook(&self, event: WebhookEvent, state: &AppState) -> Result<()> {
+        info!("Webhook event for {}: {:?}", self.config.discourse_id, event);
+
+        match event {
+            WebhookEvent::TopicCreated { topic_id } => {
+                self.enqueue(topic_id, 1, IndexPriority::Refresh, state).await?;
+            }
+            WebhookEvent::PostCreated(post) | WebhookEvent::PostEdited(post) => {
+                let topic_id = post.topic_id;
+
+                // Posts of topics we haven't indexed yet arrive with the topic itself
+                let upserted = match Topic::get_by_topic_id(&self.config.discourse_id, topic_id, state).await {
+                    Ok(topic) => self.upsert_posts(vec![post], &topic, state).await,
+                    Err(_) => Ok(()),
+                };
+                // Picks up the new post count and last activity of the topic
+                self.enqueue(topic_id, 1, IndexPriority::Refresh, state).await?;
+                upserted?;
+            }
+            WebhookEvent::PostDestroyed(post) => {
+                if Post::soft_delete(&self.config.discourse_id, post.id, state).await? {
+                    self.remove_posts(&[post.id], state).await;
+                }
+            }
+            WebhookEvent::Ignored(event) => {
+                info!("Ignoring webhook event {} for {}", event, self.config.discourse_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Persist a job for the page and wake the worker
+    ///
+    /// Re-enqueueing a pending page only raises its priority, so a user refresh
+    /// overtakes the same topic sitting in the backfill.
+    pub async fn enqueue(&self, topic_id: TopicId, page: u32, priority: IndexPriority, state: &AppState) -> Result<()> {
+        info!("Enqueuing topic {:?} page {} for {} ({:?})", topic_id, page, self.config.discourse_id, priority);
+        IndexJob::enqueue(&self.config.discourse_id, topic_id, page as i32, priority, state).await?;
+        let _ = self.wake_tx.try_send(());
+        Ok(())
     }
 
     pub async fn fetch_latest(&self, state: &AppState) -> anyhow::Result<()> {
-        let topics = fetch_latest_topics(&self.config.url).await?;
+        let topics = match self.client.fetch_latest_topics().await? {
+            Fetched::Modified(topics) => topics,
+            Fetched::NotModified => {
+                info!("Latest topics for {} are not modified", self.config.discourse_id);
+                return Ok(());
+            }
+        };
 
         for topic in topics.topic_list.topics {
             info!("Topic ({}) for {}: {:?}", topic.id, self.config.discourse_id, topic.title);
-            self.enqueue(topic.id, 1).await;
+            self.enqueue(topic.id, 1, IndexPriority::Latest, state).await?;
             info!("Queued for {}", self.config.discourse_id);
         }
 
@@ -416,7 +869,21 @@ impl DiscourseIndexer {
     }
 
     pub async fn fetch_periodically(&self, state: &AppState) {
+        // Config is validated at startup, fall back to the old 30 minute cadence just in case
+        let schedule = self.config.schedule().unwrap_or_else(|e| {
+            error!("Invalid schedule for {}: {}, using 30m", self.config.discourse_id, e);
+            ScrapeSchedule::Interval(Duration::from_secs(30 * 60))
+        });
+        let jitter = self.config.jitter().unwrap_or_default();
+
         loop {
+            *self.last_run.write().await = Some(Utc::now());
+
+            // Keep the category list fresh, it decides the visibility of indexed topics
+            if let Err(e) = self.refresh_categories(state).await {
+                error!("Error fetching categories for {}: {:?}", self.config.discourse_id, e);
+            }
+
             match self.fetch_latest(state).await {
                 Ok(_) => {
                     info!("Fetched latest topics for {}", self.config.discourse_id);
@@ -427,39 +894,38 @@ impl DiscourseIndexer {
             }
 
             let now = Utc::now();
-            let next = now.duration_round_up(TimeDelta::minutes(30)).unwrap();
+            let next = schedule
+                .next_with_jitter(now, jitter)
+                .unwrap_or_else(|| now + TimeDelta::minutes(30));
+            *self.next_run.write().await = Some(next);
 
             info!("Next fetch for {} at: {:?}", self.config.discourse_id, next);
 
             let duration = next.signed_duration_since(now);
-            async_std::task::sleep(Duration::from_secs(duration.num_seconds() as u64)).await;
+            async_std::task::sleep(duration.to_std().unwrap_or_default()).await;
         }
     }
 }
 
-/// Helper function to create discourse configs from TOML-like structure
-pub fn create_discourse_configs() -> Vec<DiscourseConfig> {
-    vec![
-        DiscourseConfig {
-            discourse_id: "magicians".to_string(),
-            url: "https://ethereum-magicians.org".to_string(),
-            scrape_interval: "30m".to_string(),
-        },
-        DiscourseConfig {
-            discourse_id: "research".to_string(),
-            url: "https://ethresear.ch".to_string(),
-            scrape_interval: "30m".to_string(),
-        },
-    ]
-}
-
 #[cfg(test)]
 mod tests {
+    use figment::{
+        Figment,
+        providers::{Format, Toml},
+    };
+
     use super::*;
 
     #[async_std::test]
     async fn test_fetch_latest_topics() {
-        let result = fetch_latest_topics("https://ethereum-magicians.org").await.unwrap();
+        let figment = Figment::new().merge(Toml::string(
+            "[discourse.magicians]\nurl = \"https://ethereum-magicians.org\"",
+        ));
+        let configs = init_discourse_configs(figment).unwrap();
+        let client = DiscourseClient::new(&configs[0]);
+        let Fetched::Modified(result) = client.fetch_latest_topics().await.unwrap() else {
+            panic!("first request can't be conditional");
+        };
         // assert!(result.topic_list.topics.len() > 0);
 
         println!("Active Users: {:?}", result.users.len());
@@ -0,0 +1,194 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use rand::Rng;
+
+/// Upper bound for the default jitter when none is configured
+const MAX_DEFAULT_JITTER: Duration = Duration::from_secs(5 * 60);
+
+/// When to poll `/latest.json` for a discourse instance
+///
+/// Accepts either a human duration such as `"30m"`, `"6h"` or `"1h30m"`,
+/// or a cron expression such as `"*/5 * * * *"` (five or six fields).
+#[derive(Debug, Clone)]
+pub enum ScrapeSchedule {
+    /// Runs on multiples of the interval since the unix epoch
+    Interval(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl FromStr for ScrapeSchedule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+
+        // Cron expressions always contain whitespace separated fields
+        if value.split_whitespace().count() >= 5 {
+            // The cron crate expects a leading seconds field
+            let expression = if value.split_whitespace().count() == 5 {
+                format!("0 {}", value)
+            } else {
+                value.to_string()
+            };
+
+            return cron::Schedule::from_str(&expression)
+                .map(|schedule| Self::Cron(Box::new(schedule)))
+                .map_err(|e| format!("invalid cron expression '{}': {}", value, e));
+        }
+
+        let interval = parse_duration(value)?;
+        if interval < Duration::from_secs(60) {
+            return Err(format!("interval '{}' must be at least one minute", value));
+        }
+
+        Ok(Self::Interval(interval))
+    }
+}
+
+impl ScrapeSchedule {
+    /// The next run strictly after `now`, without jitter
+    pub fn next_after(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Interval(interval) => {
+                let interval = TimeDelta::from_std(*interval).ok()?;
+                let next = now.duration_round_up(interval).ok()?;
+                if next <= now {
+                    Some(next + interval)
+                } else {
+                    Some(next)
+                }
+            }
+            Self::Cron(schedule) => schedule.after(&now).next(),
+        }
+    }
+
+    /// The next run after `now` with a random delay of up to `jitter` added
+    ///
+    /// Without an explicit jitter, up to 10% of the gap until the next run is
+    /// used (capped at five minutes) so instances sharing a schedule don't
+    /// all hit the network at the same instant.
+    pub fn next_with_jitter(
+        &self,
+        now: DateTime<Utc>,
+        jitter: Option<Duration>,
+    ) -> Option<DateTime<Utc>> {
+        let next = self.next_after(now)?;
+
+        let jitter = jitter.unwrap_or_else(|| {
+            let gap = (next - now).to_std().unwrap_or_default();
+            (gap / 10).min(MAX_DEFAULT_JITTER)
+        });
+
+        if jitter.is_zero() {
+            return Some(next);
+        }
+
+        let offset = rand::rng().random_range(0..=jitter.as_millis() as u64);
+        Some(next + TimeDelta::milliseconds(offset as i64))
+    }
+}
+
+/// Parse human durations such as `"90s"`, `"5m"`, `"6h"`, `"1d"` or `"1h 30m"`
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err("duration is empty".to_string());
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(format!("invalid duration '{}': expected a number", value));
+        }
+        let amount: u64 = rest[..digits]
+            .parse()
+            .map_err(|e| format!("invalid duration '{}': {}", value, e))?;
+        rest = rest[digits..].trim_start();
+
+        let unit_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit_len] {
+            "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 60 * 60,
+            "d" | "day" | "days" => 24 * 60 * 60,
+            "" => return Err(format!("invalid duration '{}': missing unit", value)),
+            unit => {
+                return Err(format!(
+                    "invalid duration '{}': unknown unit '{}'",
+                    value, unit
+                ));
+            }
+        };
+        rest = rest[unit_len..].trim_start();
+
+        total = amount
+            .checked_mul(seconds)
+            .and_then(|seconds| total.checked_add(Duration::from_secs(seconds)))
+            .ok_or_else(|| format!("invalid duration '{}': too large", value))?;
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("6h").unwrap(), Duration::from_secs(6 * 3600));
+        assert_eq!(parse_duration("1h 30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(
+            parse_duration("2 days").unwrap(),
+            Duration::from_secs(2 * 86400)
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("5 fortnights").is_err());
+        assert!(parse_duration("18446744073709551615d").is_err());
+        assert!(parse_duration("18446744073709551615s 1s").is_err());
+    }
+
+    #[test]
+    fn test_interval_schedule() {
+        let schedule: ScrapeSchedule = "30m".parse().unwrap();
+        let now = DateTime::parse_from_rfc3339("2025-06-01T10:12:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let next = schedule.next_after(now).unwrap();
+        assert_eq!(next.to_rfc3339(), "2025-06-01T10:30:00+00:00");
+
+        // Exactly on a boundary schedules the following one
+        let next = schedule.next_after(next).unwrap();
+        assert_eq!(next.to_rfc3339(), "2025-06-01T11:00:00+00:00");
+
+        assert!("10s".parse::<ScrapeSchedule>().is_err());
+    }
+
+    #[test]
+    fn test_cron_schedule() {
+        let schedule: ScrapeSchedule = "*/5 * * * *".parse().unwrap();
+        let now = DateTime::parse_from_rfc3339("2025-06-01T10:12:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let next = schedule.next_after(now).unwrap();
+        assert_eq!(next.to_rfc3339(), "2025-06-01T10:15:00+00:00");
+
+        let jittered = schedule
+            .next_with_jitter(now, Some(Duration::from_secs(30)))
+            .unwrap();
+        assert!(jittered >= next && jittered <= next + TimeDelta::seconds(30));
+
+        assert!("* * * * * * * *".parse::<ScrapeSchedule>().is_err());
+    }
+}
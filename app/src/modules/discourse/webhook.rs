@@ -0,0 +1,123 @@
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::models::discourse::topic::DiscourseTopicPost;
+
+use super::TopicId;
+
+pub const EVENT_HEADER: &str = "X-Discourse-Event";
+pub const SIGNATURE_HEADER: &str = "X-Discourse-Event-Signature";
+
+/// A webhook delivery we act on, see `Admin > API > Webhooks` on the Discourse side
+#[derive(Debug)]
+pub enum WebhookEvent {
+    TopicCreated { topic_id: TopicId },
+    PostCreated(DiscourseTopicPost),
+    PostEdited(DiscourseTopicPost),
+    PostDestroyed(DiscourseTopicPost),
+    /// `ping` and any event we don't subscribe to
+    Ignored(String),
+}
+
+#[derive(Deserialize)]
+struct TopicPayload {
+    topic: WebhookTopic,
+}
+
+#[derive(Deserialize)]
+struct WebhookTopic {
+    id: TopicId,
+}
+
+#[derive(Deserialize)]
+struct PostPayload {
+    post: DiscourseTopicPost,
+}
+
+impl WebhookEvent {
+    /// Parse the body of a delivery, `event` is the `X-Discourse-Event` header
+    pub fn parse(event: &str, body: &[u8]) -> Result<Self, serde_json::Error> {
+        let post = || serde_json::from_slice::<PostPayload>(body).map(|payload| payload.post);
+
+        Ok(match event {
+            "topic_created" => Self::TopicCreated {
+                topic_id: serde_json::from_slice::<TopicPayload>(body)?.topic.id,
+            },
+            "post_created" => Self::PostCreated(post()?),
+            "post_edited" => Self::PostEdited(post()?),
+            "post_destroyed" => Self::PostDestroyed(post()?),
+            other => Self::Ignored(other.to_string()),
+        })
+    }
+}
+
+/// Check `X-Discourse-Event-Signature`, `sha256=` followed by the hex HMAC of the raw body
+pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(digest) = signature.trim().strip_prefix("sha256=") else {
+        return false;
+    };
+    let Ok(digest) = hex::decode(digest) else {
+        return false;
+    };
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(body);
+    mac.verify_slice(&digest).is_ok()
+}
+
+#[derive(Debug, Error)]
+pub enum WebhookError {
+    #[error("discourse instance '{0}' not found")]
+    UnknownInstance(String),
+    #[error("webhooks are not enabled for '{0}', set webhook_secret to accept them")]
+    NotEnabled(String),
+    #[error("missing or invalid webhook signature")]
+    InvalidSignature,
+    #[error("invalid webhook payload: {0}")]
+    InvalidPayload(#[from] serde_json::Error),
+    #[error("failed to index webhook event: {0}")]
+    Index(#[from] anyhow::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_signature() {
+        let body = br#"{"post":{"id":1}}"#;
+        let signature = "sha256=10849a3e65c522d33a3be1ffd82c88d09e020a350beb5d06d1ec2b9555266425";
+
+        assert!(verify_signature("webhook-secret", body, signature));
+        assert!(!verify_signature("other-secret", body, signature));
+        assert!(!verify_signature("webhook-secret", br#"{"post":{"id":2}}"#, signature));
+        assert!(!verify_signature("webhook-secret", body, &signature[7..]));
+        assert!(!verify_signature("webhook-secret", body, "sha256=not-hex"));
+    }
+
+    #[test]
+    fn test_parse_webhook_event() {
+        let topic = br#"{"topic":{"id":42,"title":"EIP-7702"}}"#;
+        assert!(matches!(
+            WebhookEvent::parse("topic_created", topic),
+            Ok(WebhookEvent::TopicCreated { topic_id: 42 })
+        ));
+
+        let post = br#"{"post":{"id":7,"username":"alice","created_at":"2025-06-01T10:00:00Z","updated_at":"2025-06-01T10:05:00Z","cooked":"<p>hi</p>","user_id":3,"topic_id":42,"post_number":2,"category_id":5}}"#;
+        match WebhookEvent::parse("post_edited", post) {
+            Ok(WebhookEvent::PostEdited(post)) => {
+                assert_eq!((post.id, post.topic_id, post.post_number), (7, 42, 2));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        assert!(matches!(
+            WebhookEvent::parse("ping", br#"{"ping":"OK"}"#),
+            Ok(WebhookEvent::Ignored(event)) if event == "ping"
+        ));
+        assert!(WebhookEvent::parse("post_created", topic).is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use std::time::Duration;
+
+use figment::{Figment, providers::Env};
+use lettre::{
+    AsyncSmtpTransport, AsyncStd1Executor, AsyncTransport, Message,
+    address::AddressError,
+    message::{
+        Mailbox, MultiPart,
+        header::{HeaderName, HeaderValue},
+    },
+    transport::smtp::{self, authentication::Credentials, extension::ClientId},
+};
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::{error, info};
+
+const DEFAULT_PORT: u16 = 25;
+const TIMEOUT: Duration = Duration::from_secs(30);
+const CLIENT_NAME: &str = "ethereum-forum";
+
+/// How the connection to the SMTP server is secured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain text, for local sinks like Mailpit
+    #[default]
+    None,
+    /// Upgrade with STARTTLS, usually port 587
+    Starttls,
+    /// TLS from the start, usually port 465
+    Tls,
+}
+
+/// SMTP relay read from `SMTP_*` variables, email is disabled without `SMTP_HOST`
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// `From` header, e.g. `Ethereum Forum <digest@ethereum.forum>`
+    pub from: String,
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+#[derive(Debug, Error)]
+pub enum EmailError {
+    #[error("invalid address: {0}")]
+    Address(#[from] AddressError),
+    #[error("invalid header name: {0}")]
+    HeaderName(String),
+    #[error("invalid email: {0}")]
+    Message(#[from] lettre::error::Error),
+    #[error("SMTP failed: {0}")]
+    Smtp(#[from] smtp::Error),
+}
+
+/// A multipart email with plain text and HTML bodies
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+    /// Extra headers, e.g. `List-Unsubscribe`
+    pub headers: Vec<(String, String)>,
+}
+
+pub fn init_email(figment: Figment) -> Option<Mailer> {
+    let config = match figment
+        .merge(Env::prefixed("SMTP_"))
+        .extract::<EmailConfig>()
+    {
+        Ok(config) => config,
+        Err(e) => {
+            info!("No SMTP config found, email is disabled: {}", e);
+            return None;
+        }
+    };
+
+    match Mailer::new(config) {
+        Ok(mailer) => {
+            info!(
+                "Sending email through {}:{} ({:?})",
+                mailer.config.host, mailer.config.port, mailer.config.security
+            );
+            Some(mailer)
+        }
+        Err(e) => {
+            error!("Invalid SMTP config, email is disabled: {}", e);
+            None
+        }
+    }
+}
+
+/// Delivers emails to a single SMTP relay, one connection per email
+pub struct Mailer {
+    config: EmailConfig,
+    from: Mailbox,
+    transport: AsyncSmtpTransport<AsyncStd1Executor>,
+}
+
+impl Mailer {
+    pub fn new(config: EmailConfig) -> Result<Self, EmailError> {
+        let from = config.from.parse()?;
+
+        let builder = match config.security {
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<AsyncStd1Executor>::builder_dangerous(&config.host)
+            }
+            SmtpSecurity::Starttls => {
+                AsyncSmtpTransport::<AsyncStd1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<AsyncStd1Executor>::relay(&config.host)?,
+        };
+        let mut builder = builder
+            .port(config.port)
+            .timeout(Some(TIMEOUT))
+            .hello_name(ClientId::Domain(CLIENT_NAME.to_string()));
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from,
+            config,
+        })
+    }
+
+    pub async fn send(&self, email: &Email) -> Result<(), EmailError> {
+        self.transport.send(email.to_message(&self.from)?).await?;
+        Ok(())
+    }
+}
+
+impl Email {
+    /// Recipients are parsed as a mailbox, so they can't smuggle in SMTP commands or
+    /// headers, and header values are encoded
+    fn to_message(&self, from: &Mailbox) -> Result<Message, EmailError> {
+        let mut builder = Message::builder()
+            .from(from.clone())
+            .to(self.to.parse()?)
+            .subject(&self.subject);
+        for (name, value) in &self.headers {
+            let name = HeaderName::new_from_ascii(name.clone())
+                .map_err(|_| EmailError::HeaderName(name.clone()))?;
+            builder = builder.raw_header(HeaderValue::new(name, value.clone()));
+        }
+
+        Ok(builder.multipart(MultiPart::alternative_plain_html(
+            self.text.clone(),
+            self.html.clone(),
+        ))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    /// Accepts a single session like a local SMTP sink advertising `extensions`, returns
+    /// the commands and data it received
+    fn sink(listener: TcpListener, extensions: &'static str) -> String {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer: TcpStream = stream;
+        let mut transcript = String::new();
+        let mut in_data = false;
+
+        writer.write_all(b"220 sink ready\r\n").unwrap();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            transcript.push_str(&line);
+
+            if in_data {
+                if line == ".\r\n" {
+                    in_data = false;
+                    writer.write_all(b"250 queued\r\n").unwrap();
+                }
+                continue;
+            }
+
+            let reply = match line.trim_end() {
+                command if command.starts_with("EHLO") => {
+                    format!("250-sink\r\n{}250 8BITMIME\r\n", extensions)
+                }
+                command if command.starts_with("AUTH") => "235 authenticated\r\n".to_string(),
+                "DATA" => {
+                    in_data = true;
+                    "354 go ahead\r\n".to_string()
+                }
+                "QUIT" => {
+                    writer.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                }
+                _ => "250 ok\r\n".to_string(),
+            };
+            writer.write_all(reply.as_bytes()).unwrap();
+        }
+
+        transcript
+    }
+
+    fn mailer(port: u16, security: SmtpSecurity, credentials: bool) -> Mailer {
+        Mailer::new(EmailConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            security,
+            username: credentials.then(|| "forum".to_string()),
+            password: credentials.then(|| "secret".to_string()),
+            from: "Ethereum Forum <digest@ethereum.forum>".to_string(),
+        })
+        .unwrap()
+    }
+
+    fn email(to: &str) -> Email {
+        Email {
+            to: to.to_string(),
+            subject: "Your digest – 3 topics".to_string(),
+            text: "Hello\n.hidden dot".to_string(),
+            html: "<p>Hello</p>".to_string(),
+            headers: vec![(
+                "List-Unsubscribe".to_string(),
+                "<https://ethereum.forum/unsubscribe>\r\nBcc: injected".to_string(),
+            )],
+        }
+    }
+
+    #[async_std::test]
+    async fn test_send_to_sink() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = std::thread::spawn(move || sink(listener, "250-AUTH PLAIN LOGIN\r\n"));
+
+        mailer(port, SmtpSecurity::None, true)
+            .send(&email("alice@example.com"))
+            .await
+            .unwrap();
+
+        let transcript = sink.join().unwrap();
+        assert!(transcript.contains("EHLO ethereum-forum\r\n"));
+        // Base64 of "\0forum\0secret"
+        assert!(transcript.contains("AUTH PLAIN AGZvcnVtAHNlY3JldA==\r\n"));
+        assert!(transcript.contains("MAIL FROM:<digest@ethereum.forum>"));
+        assert!(transcript.contains("RCPT TO:<alice@example.com>"));
+        assert!(transcript.contains("To: alice@example.com\r\n"));
+        assert!(transcript.contains("Subject: Your digest =?utf-8?b?4oCT?= 3 topics\r\n"));
+        assert!(transcript.contains("List-Unsubscribe: "));
+        assert!(!transcript.contains("\r\nBcc: injected"));
+        assert!(transcript.contains("\r\n..hidden dot\r\n"));
+        assert!(transcript.contains("<p>Hello</p>"));
+    }
+
+    #[async_std::test]
+    async fn test_require_starttls() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = std::thread::spawn(move || sink(listener, ""));
+
+        // Credentials are never sent over a connection the server doesn't upgrade
+        let result = mailer(port, SmtpSecurity::Starttls, true)
+            .send(&email("alice@example.com"))
+            .await;
+        assert!(result.is_err());
+
+        let transcript = sink.join().unwrap();
+        assert!(!transcript.contains("AUTH"));
+        assert!(!transcript.contains("MAIL FROM"));
+    }
+
+    #[test]
+    fn test_reject_invalid_recipients() {
+        let from: Mailbox = "Ethereum Forum <digest@ethereum.forum>".parse().unwrap();
+
+        for to in [
+            "alice@example.com>\r\nRCPT TO:<mallory@example.com",
+            "alice@example.com\nBcc: mallory@example.com",
+            "not an address",
+        ] {
+            assert!(
+                matches!(email(to).to_message(&from), Err(EmailError::Address(_))),
+                "{:?}",
+                to
+            );
+        }
+    }
+}
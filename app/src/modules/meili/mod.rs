@@ -1,4 +1,7 @@
 pub use meilisearch_sdk::client::Client;
+use serde_json::json;
+
+use crate::modules::search::embeddings::EMBEDDER;
 
 pub async fn init_meili() -> Option<Client> {
     match (std::env::var("MEILI_HOST"), std::env::var("MEILI_KEY")) {
@@ -10,7 +13,7 @@ pub async fn init_meili() -> Option<Client> {
                     tracing::info!("Connected to MeiliSearch: version {}", version.commit_sha);
                     
                     // Configure the forum index
-                    if let Err(e) = configure_forum_index(&client).await {
+                    if let Err(e) = configure_index(&client, "forum").await {
                         tracing::error!("Failed to configure forum index: {}", e);
                         return None;
                     }
@@ -27,17 +30,24 @@ pub async fn init_meili() -> Option<Client> {
     }
 }
 
-async fn configure_forum_index(client: &Client) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut forum_index = client.index("forum");
+/// Apply the forum index settings to `uid`, creating the index if it doesn't exist
+pub async fn configure_index(client: &Client, uid: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut forum_index = client.index(uid);
     
-    // Set filterable attributes for the forum index
+    // Set filterable attributes for the forum index, they also back the search facets
     let filterable_attributes = vec![
         "entity_type".to_string(),
+        "discourse_id".to_string(),
         "topic_id".to_string(), 
         "user_id".to_string(),
         "username".to_string(),
         "pm_issue".to_string(),
         "post_id".to_string(),
+        "visibility".to_string(),
+        "category_id".to_string(),
+        "tags".to_string(),
+        "created_at".to_string(),
+        "created_year".to_string(),
     ];
     
     // Set searchable attributes for better search experience
@@ -51,7 +61,7 @@ async fn configure_forum_index(client: &Client) -> Result<(), Box<dyn std::error
     // Configure the index settings
     match forum_index.set_filterable_attributes(&filterable_attributes).await {
         Ok(_) => {
-            tracing::info!("Successfully configured filterable attributes for forum index");
+            tracing::info!("Successfully configured filterable attributes for {} index", uid);
         }
         Err(e) => {
             tracing::error!("Failed to set filterable attributes: {}", e);
@@ -61,7 +71,7 @@ async fn configure_forum_index(client: &Client) -> Result<(), Box<dyn std::error
     
     match forum_index.set_searchable_attributes(&searchable_attributes).await {
         Ok(_) => {
-            tracing::info!("Successfully configured searchable attributes for forum index");
+            tracing::info!("Successfully configured searchable attributes for {} index", uid);
         }
         Err(e) => {
             tracing::error!("Failed to set searchable attributes: {}", e);
@@ -72,7 +82,7 @@ async fn configure_forum_index(client: &Client) -> Result<(), Box<dyn std::error
     // Set the primary key for the index
     match forum_index.set_primary_key("entity_id").await {
         Ok(_) => {
-            tracing::info!("Successfully configured primary key for forum index");
+            tracing::info!("Successfully configured primary key for {} index", uid);
         }
         Err(e) => {
             tracing::error!("Failed to set primary key: {}", e);
@@ -82,3 +92,26 @@ async fn configure_forum_index(client: &Client) -> Result<(), Box<dyn std::error
     
     Ok(())
 }
+
+/// Register the user-provided embedder the `_vectors` of the forum documents are stored under
+/// in index `uid`
+///
+/// The SDK doesn't cover embedder settings yet, so this talks to the settings route directly.
+pub async fn configure_embedder(client: &Client, uid: &str, dimensions: usize) -> Result<(), reqwest::Error> {
+    let mut request = reqwest::Client::new()
+        .patch(format!("{}/indexes/{}/settings/embedders", client.get_host(), uid))
+        .json(&json!({
+            EMBEDDER: {
+                "source": "userProvided",
+                "dimensions": dimensions,
+            }
+        }));
+    if let Some(api_key) = client.get_api_key() {
+        request = request.bearer_auth(api_key);
+    }
+
+    request.send().await?.error_for_status()?;
+    tracing::info!("Configured the '{}' embedder for {} index", EMBEDDER, uid);
+
+    Ok(())
+}
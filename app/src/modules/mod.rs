@@ -1,6 +1,9 @@
+pub mod digest;
 pub mod discourse;
+pub mod email;
 pub mod ical;
 pub mod meili;
 pub mod pm;
+pub mod search;
 pub mod sso;
 pub mod workshop;
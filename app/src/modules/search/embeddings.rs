@@ -0,0 +1,316 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, query, query_as};
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+use crate::{
+    modules::{
+        discourse::ForumSearchDocument,
+        workshop::provider::{LlmProvider, ProviderError},
+    },
+    state::AppState,
+};
+
+/// Name of the user-provided embedder in the `forum` index, and the key in `_vectors`
+pub const EMBEDDER: &str = "forum";
+/// Share of semantic ranking in hybrid search when the client doesn't ask for one
+pub const DEFAULT_SEMANTIC_RATIO: f32 = 0.5;
+
+const DEFAULT_DIMENSIONS: usize = 1536;
+/// Inputs sent per embedding request
+const BATCH_SIZE: usize = 64;
+/// Roughly 2k tokens, longer posts are embedded by their beginning
+const MAX_INPUT_CHARS: usize = 8000;
+
+#[derive(Debug, Error)]
+pub enum EmbeddingError {
+    #[error("embedding request failed: {0}")]
+    Request(#[from] ProviderError),
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Turns text into vectors for semantic search
+#[async_trait]
+pub trait EmbeddingProvider: Send + Sync {
+    /// Stored along the cached embeddings, changing it embeds everything again
+    fn model(&self) -> &str;
+
+    fn dimensions(&self) -> usize;
+
+    /// One embedding per input, in order
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, EmbeddingError>;
+}
+
+/// Embeddings from the model provider the workshop talks to
+pub struct ProviderEmbeddings {
+    provider: Arc<dyn LlmProvider>,
+    model: String,
+    dimensions: usize,
+}
+
+impl ProviderEmbeddings {
+    pub fn new(provider: Arc<dyn LlmProvider>, model: String, dimensions: usize) -> Self {
+        Self {
+            provider,
+            model,
+            dimensions,
+        }
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for ProviderEmbeddings {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        Ok(self
+            .provider
+            .embed(&self.model, inputs, self.dimensions)
+            .await?)
+    }
+}
+
+/// Local embeddings hashing words into buckets, for tests and development without an API
+///
+/// Only texts sharing words end up close to each other, paraphrases don't.
+pub struct StubEmbeddings {
+    dimensions: usize,
+}
+
+impl StubEmbeddings {
+    pub fn new(dimensions: usize) -> Self {
+        Self { dimensions }
+    }
+}
+
+/// Normalized counts of the lowercased words of the input, hashed into `dimensions` buckets
+pub fn hashed_embedding(input: &str, dimensions: usize) -> Vec<f32> {
+    let mut vector = vec![0.0f32; dimensions];
+    for word in input
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let digest = Sha256::digest(word.to_lowercase().as_bytes());
+        let bucket = u64::from_le_bytes(digest[..8].try_into().unwrap());
+        vector[(bucket % dimensions as u64) as usize] += 1.0;
+    }
+
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= norm);
+    }
+    vector
+}
+
+#[async_trait]
+impl EmbeddingProvider for StubEmbeddings {
+    fn model(&self) -> &str {
+        "stub"
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        Ok(inputs
+            .iter()
+            .map(|input| hashed_embedding(input, self.dimensions))
+            .collect())
+    }
+}
+
+/// Semantic search is enabled by setting `WORKSHOP_EMBEDDING_MODEL`, `stub` selects the
+/// local provider. `WORKSHOP_EMBEDDING_DIMENSIONS` defaults to 1536.
+/// Other models need a workshop model provider.
+pub fn init_embeddings(
+    provider: Option<&Arc<dyn LlmProvider>>,
+) -> Option<Arc<dyn EmbeddingProvider>> {
+    let model = std::env::var("WORKSHOP_EMBEDDING_MODEL").ok()?;
+    let dimensions = std::env::var("WORKSHOP_EMBEDDING_DIMENSIONS")
+        .ok()
+        .and_then(|dimensions| dimensions.parse().ok())
+        .unwrap_or(DEFAULT_DIMENSIONS);
+
+    if model == "stub" {
+        info!("Embedding search documents with stub ({} dimensions)", dimensions);
+        return Some(Arc::new(StubEmbeddings::new(dimensions)));
+    }
+
+    let Some(provider) = provider else {
+        warn!(
+            "WORKSHOP_EMBEDDING_MODEL is {} but no model provider is configured, semantic search is disabled",
+            model
+        );
+        return None;
+    };
+
+    info!(
+        "Embedding search documents with {} on {} ({} dimensions)",
+        model,
+        provider.name(),
+        dimensions
+    );
+    Some(Arc::new(ProviderEmbeddings::new(
+        provider.clone(),
+        model,
+        dimensions,
+    )))
+}
+
+/// The text a document is embedded by, the title for topics and the content for posts
+fn embedding_input(document: &ForumSearchDocument) -> Option<String> {
+    let text = match document.entity_type.as_str() {
+        "topic" => document.title.as_deref()?,
+        _ => document.cooked.as_deref()?,
+    }
+    .trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    Some(match text.char_indices().nth(MAX_INPUT_CHARS) {
+        Some((end, _)) => text[..end].to_string(),
+        None => text.to_string(),
+    })
+}
+
+fn content_hash(input: &str) -> String {
+    hex::encode(Sha256::digest(input.as_bytes()))
+}
+
+/// Attach embeddings to the documents before they are sent to Meilisearch
+///
+/// Does nothing when semantic search is disabled. Failures are logged, documents whose
+/// embedding couldn't be refreshed keep their previous vectors and new documents only show
+/// up in keyword search until they are embedded.
+pub async fn embed_documents(documents: &mut [ForumSearchDocument], state: &AppState) {
+    let Some(provider) = &state.embeddings else {
+        return;
+    };
+
+    if let Err(e) = embed_with(provider.as_ref(), documents, &state.database.pool).await {
+        error!("Error embedding search documents: {:?}", e);
+    }
+}
+
+async fn embed_with(
+    provider: &dyn EmbeddingProvider,
+    documents: &mut [ForumSearchDocument],
+    pool: &PgPool,
+) -> Result<(), EmbeddingError> {
+    let inputs: Vec<Option<(String, String)>> = documents
+        .iter()
+        .map(|document| embedding_input(document).map(|input| (content_hash(&input), input)))
+        .collect();
+
+    let discourse_ids: Vec<String> = documents
+        .iter()
+        .map(|document| document.discourse_id.clone().unwrap_or_default())
+        .collect();
+    let entity_ids: Vec<String> = documents
+        .iter()
+        .map(|document| document.entity_id.clone())
+        .collect();
+
+    let cached: Vec<(String, String, String, Vec<f32>)> = query_as(
+        "SELECT discourse_id, entity_id, content_hash, embedding FROM search_embeddings WHERE model = $1 AND (discourse_id, entity_id) IN (SELECT * FROM unnest($2::TEXT[], $3::TEXT[]))",
+    )
+    .bind(provider.model())
+    .bind(&discourse_ids)
+    .bind(&entity_ids)
+    .fetch_all(pool)
+    .await?;
+    let mut cached: HashMap<(String, String), (String, Vec<f32>)> = cached
+        .into_iter()
+        .map(|(discourse_id, entity_id, hash, embedding)| {
+            ((discourse_id, entity_id), (hash, embedding))
+        })
+        .collect();
+
+    // (index, content hash, input) of the documents without an up to date embedding
+    let mut missing = Vec::new();
+    for (index, input) in inputs.iter().enumerate() {
+        let Some((hash, input)) = input else {
+            continue;
+        };
+        let key = (discourse_ids[index].clone(), entity_ids[index].clone());
+        let cached = cached
+            .remove(&key)
+            .filter(|(_, embedding)| embedding.len() == provider.dimensions());
+        let is_current = matches!(&cached, Some((cached_hash, _)) if cached_hash == hash);
+
+        // Outdated vectors are kept until the new ones arrive, a document re-added without
+        // `_vectors` would lose them if embedding fails
+        if let Some((_, embedding)) = cached {
+            documents[index].vectors = Some(HashMap::from([(EMBEDDER.to_string(), embedding)]));
+        }
+        if !is_current {
+            missing.push((index, hash, input));
+        }
+    }
+
+    for batch in missing.chunks(BATCH_SIZE) {
+        let batch_inputs: Vec<String> = batch
+            .iter()
+            .map(|(_, _, input)| input.to_string())
+            .collect();
+        let embeddings = provider.embed(&batch_inputs).await?;
+
+        let mut tx = pool.begin().await?;
+        for ((index, hash, _), embedding) in batch.iter().zip(embeddings) {
+            query("INSERT INTO search_embeddings (discourse_id, entity_id, model, content_hash, embedding) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (discourse_id, entity_id, model) DO UPDATE SET content_hash = $4, embedding = $5, updated_at = NOW()")
+                .bind(&discourse_ids[*index])
+                .bind(&entity_ids[*index])
+                .bind(provider.model())
+                .bind(hash)
+                .bind(&embedding)
+                .execute(&mut *tx)
+                .await?;
+
+            documents[*index].vectors = Some(HashMap::from([(EMBEDDER.to_string(), embedding)]));
+        }
+        tx.commit().await?;
+    }
+
+    if !missing.is_empty() {
+        info!("Embedded {} search documents", missing.len());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cosine(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| a * b).sum()
+    }
+
+    #[async_std::test]
+    async fn test_stub_embeddings() {
+        let provider = StubEmbeddings::new(64);
+        let inputs = [
+            "Blob fee market".to_string(),
+            "the blob FEE market".to_string(),
+            "Account abstraction".to_string(),
+        ];
+        let embeddings = provider.embed(&inputs).await.unwrap();
+
+        assert_eq!(embeddings.len(), 3);
+        assert!(embeddings.iter().all(|embedding| embedding.len() == 64));
+        assert!(cosine(&embeddings[0], &embeddings[1]) > cosine(&embeddings[0], &embeddings[2]));
+        assert_eq!(embeddings, provider.embed(&inputs).await.unwrap());
+    }
+}
@@ -0,0 +1,227 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use meilisearch_sdk::{
+    request::HttpClient,
+    search::{SearchResults, Selectors},
+};
+use serde_json::{Map, Value, json};
+
+use crate::{
+    models::topics::TopicAccess,
+    modules::{discourse::ForumSearchDocument, meili::Client},
+};
+
+use super::{
+    CROP_LENGTH, FACETS, HIGHLIGHT_POST_TAG, HIGHLIGHT_PRE_TAG, SearchBackend, SearchDocument,
+    SearchError, SearchFacets, SearchFormatted, SearchPage, SearchQuery,
+    embeddings::{DEFAULT_SEMANTIC_RATIO, EMBEDDER, EmbeddingProvider},
+};
+
+/// Searches the `forum` index, ranking by keywords and embeddings when `embeddings` is set
+pub struct MeiliSearch {
+    client: Client,
+    embeddings: Option<Arc<dyn EmbeddingProvider>>,
+    http: reqwest::Client,
+}
+
+impl MeiliSearch {
+    pub fn new(client: Client, embeddings: Option<Arc<dyn EmbeddingProvider>>) -> Self {
+        Self {
+            client,
+            embeddings,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// The semantic ratio and query embedding for hybrid search, `None` for keyword search
+    ///
+    /// Falls back to keyword search when the query can't be embedded.
+    async fn semantic(&self, query: &SearchQuery) -> Option<(f32, Vec<f32>)> {
+        let embeddings = self.embeddings.as_ref()?;
+        let ratio = query
+            .semantic_ratio
+            .unwrap_or(DEFAULT_SEMANTIC_RATIO)
+            .clamp(0.0, 1.0);
+        if ratio == 0.0 || query.query.trim().is_empty() {
+            return None;
+        }
+
+        match embeddings.embed(std::slice::from_ref(&query.query)).await {
+            Ok(mut vectors) => Some((ratio, vectors.pop().unwrap_or_default())),
+            Err(e) => {
+                tracing::warn!("Embedding the query failed, using keyword search: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SearchBackend for MeiliSearch {
+    fn name(&self) -> &'static str {
+        "meilisearch"
+    }
+
+    async fn search(
+        &self,
+        query: &SearchQuery,
+        access: &TopicAccess,
+    ) -> Result<SearchPage, SearchError> {
+        let forum = self.client.index("forum");
+        let filter = meili_filter(query, access);
+
+        let mut search = forum.search();
+        search
+            .with_query(&query.query)
+            .with_filter(&filter)
+            .with_offset(query.offset)
+            .with_limit(query.limit)
+            .with_attributes_to_highlight(Selectors::Some(&["title", "cooked"]))
+            .with_attributes_to_crop(Selectors::Some(&[("cooked", Some(CROP_LENGTH))]))
+            .with_highlight_pre_tag(HIGHLIGHT_PRE_TAG)
+            .with_highlight_post_tag(HIGHLIGHT_POST_TAG);
+        if query.facets {
+            search.with_facets(Selectors::Some(&FACETS));
+        }
+
+        let results: SearchResults<ForumSearchDocument> = match self.semantic(query).await {
+            // The SDK doesn't support hybrid search yet, extend its request body instead
+            Some((ratio, vector)) => {
+                let body = hybrid_body(&search, ratio, vector);
+                let mut request = self
+                    .http
+                    .post(format!("{}/indexes/forum/search", self.client.get_host()))
+                    .json(&body);
+                if let Some(api_key) = self.client.get_api_key() {
+                    request = request.bearer_auth(api_key);
+                }
+                request.send().await?.error_for_status()?.json().await?
+            }
+            None => search.execute().await?,
+        };
+
+        Ok(SearchPage {
+            total_hits: results
+                .estimated_total_hits
+                .unwrap_or(results.hits.len().saturating_add(query.offset)),
+            processing_time_ms: results.processing_time_ms,
+            facets: results
+                .facet_distribution
+                .map(SearchFacets::from_distribution),
+            documents: results
+                .hits
+                .into_iter()
+                .map(|hit| SearchDocument {
+                    formatted: hit.formatted_result.as_ref().map(formatted_fields),
+                    document: hit.result,
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Body of a search request ranking `ratio` by similarity to `vector`
+fn hybrid_body(
+    search: &meilisearch_sdk::search::SearchQuery<'_, impl HttpClient>,
+    ratio: f32,
+    vector: Vec<f32>,
+) -> Value {
+    let mut body = serde_json::to_value(search).unwrap_or_default();
+    body["hybrid"] = json!({ "embedder": EMBEDDER, "semanticRatio": ratio });
+    body["vector"] = json!(vector);
+    body
+}
+
+/// Meilisearch filter for the query, restricted to what `access` may read
+fn meili_filter(query: &SearchQuery, access: &TopicAccess) -> String {
+    let mut filters = vec![access.meili_filter()];
+
+    if let Some(discourse_id) = &query.discourse_id {
+        filters.push(format!("discourse_id = {}", quote(discourse_id)));
+    }
+    if let Some(entity_type) = query.entity_type {
+        filters.push(format!("entity_type = {}", entity_type.as_str()));
+    }
+    if let Some(username) = &query.username {
+        filters.push(format!("username = {}", quote(username)));
+    }
+    if let Some(user_id) = query.user_id {
+        filters.push(format!("user_id = {}", user_id));
+    }
+    if let Some(topic_id) = query.topic_id {
+        filters.push(format!("topic_id = {}", topic_id));
+    }
+    if let Some(pm_issue) = query.pm_issue {
+        filters.push(format!("pm_issue = {}", pm_issue));
+    }
+    if let Some(from) = query.from {
+        filters.push(format!("created_at >= {}", from.timestamp()));
+    }
+    if let Some(to) = query.to {
+        filters.push(format!("created_at <= {}", to.timestamp()));
+    }
+
+    filters.join(" AND ")
+}
+
+fn formatted_fields(formatted: &Map<String, Value>) -> SearchFormatted {
+    let field = |name: &str| {
+        formatted
+            .get(name)
+            .and_then(|value| value.as_str())
+            .map(str::to_string)
+    };
+
+    SearchFormatted {
+        title: field("title"),
+        cooked: field("cooked"),
+    }
+}
+
+/// Quote a value for a Meilisearch filter expression
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::modules::search::SearchEntityType;
+
+    #[test]
+    fn test_meili_filter() {
+        let query = SearchQuery {
+            query: "blobs".to_string(),
+            discourse_id: Some("magicians".to_string()),
+            entity_type: Some(SearchEntityType::Post),
+            username: Some("al\"ice".to_string()),
+            pm_issue: Some(1518),
+            from: DateTime::from_timestamp(1_700_000_000, 0),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            meili_filter(&query, &TopicAccess::public()),
+            "visibility != restricted AND discourse_id = \"magicians\" AND entity_type = post \
+             AND username = \"al\\\"ice\" AND pm_issue = 1518 AND created_at >= 1700000000"
+        );
+    }
+
+    #[test]
+    fn test_hybrid_body() {
+        let client = Client::new("http://localhost:7700", None::<String>).unwrap();
+        let forum = client.index("forum");
+        let mut search = forum.search();
+        search.with_query("blob pricing").with_limit(5);
+
+        let body = hybrid_body(&search, 0.7, vec![0.5, 0.5]);
+        assert_eq!(body["q"], "blob pricing");
+        assert_eq!(body["limit"], 5);
+        assert_eq!(body["hybrid"]["embedder"], EMBEDDER);
+        assert_eq!(body["vector"], json!([0.5, 0.5]));
+        assert!((body["hybrid"]["semanticRatio"].as_f64().unwrap() - 0.7).abs() < 1e-6);
+    }
+}
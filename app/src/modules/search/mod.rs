@@ -0,0 +1,330 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object, Union};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    database::Database,
+    models::topics::TopicAccess,
+    modules::{discourse::ForumSearchDocument, meili},
+};
+use embeddings::EmbeddingProvider;
+
+pub mod embeddings;
+pub mod meilisearch;
+pub mod postgres;
+pub mod reconcile;
+pub mod reindex;
+
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+/// Words kept around the match in `_formatted.cooked`
+const CROP_LENGTH: usize = 40;
+const HIGHLIGHT_PRE_TAG: &str = "<mark>";
+const HIGHLIGHT_POST_TAG: &str = "</mark>";
+/// Attributes matches are counted by, all filterable in the forum index
+pub const FACETS: [&str; 5] = [
+    "discourse_id",
+    "entity_type",
+    "username",
+    "pm_issue",
+    "created_year",
+];
+/// Values kept per facet, most frequent first
+const MAX_FACET_VALUES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum SearchEntityType {
+    Topic,
+    Post,
+}
+
+impl SearchEntityType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Topic => "topic",
+            Self::Post => "post",
+        }
+    }
+}
+
+/// A search request, everything but the query is optional
+///
+/// An empty query matches every document, newest first on Postgres.
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    pub query: String,
+    pub discourse_id: Option<String>,
+    pub entity_type: Option<SearchEntityType>,
+    pub username: Option<String>,
+    pub user_id: Option<i32>,
+    pub topic_id: Option<i32>,
+    pub pm_issue: Option<i32>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Share of semantic ranking from 0.0 (keyword only) to 1.0, defaults to
+    /// `DEFAULT_SEMANTIC_RATIO` when semantic search is enabled
+    pub semantic_ratio: Option<f32>,
+    /// Count all matches by `FACETS` into `SearchPage::facets`
+    pub facets: bool,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// `<mark>`-highlighted copies of the matched fields, cropped around the match
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct SearchFormatted {
+    pub title: Option<String>,
+    pub cooked: Option<String>,
+}
+
+/// A matching document as returned by a backend
+#[derive(Debug)]
+pub struct SearchDocument {
+    pub document: ForumSearchDocument,
+    pub formatted: Option<SearchFormatted>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: usize,
+}
+
+/// Number of matches per value of each facet, most frequent first
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Object)]
+pub struct SearchFacets {
+    pub discourse_id: Vec<FacetCount>,
+    pub entity_type: Vec<FacetCount>,
+    pub username: Vec<FacetCount>,
+    pub pm_issue: Vec<FacetCount>,
+    /// Year the topic or post was created in
+    pub created_year: Vec<FacetCount>,
+}
+
+impl SearchFacets {
+    /// From counts by facet and value, the shape Meilisearch returns them in
+    pub fn from_distribution(distribution: HashMap<String, HashMap<String, usize>>) -> Self {
+        let mut facets = Self::default();
+        for (facet, values) in distribution {
+            let counts = match facet.as_str() {
+                "discourse_id" => &mut facets.discourse_id,
+                "entity_type" => &mut facets.entity_type,
+                "username" => &mut facets.username,
+                "pm_issue" => &mut facets.pm_issue,
+                "created_year" => &mut facets.created_year,
+                _ => continue,
+            };
+            counts.extend(
+                values
+                    .into_iter()
+                    .map(|(value, count)| FacetCount { value, count }),
+            );
+            counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+            counts.truncate(MAX_FACET_VALUES);
+        }
+
+        facets
+    }
+}
+
+#[derive(Debug)]
+pub struct SearchPage {
+    pub documents: Vec<SearchDocument>,
+    /// Exact on Postgres, estimated by Meilisearch
+    pub total_hits: usize,
+    pub processing_time_ms: usize,
+    /// Set when the query asked for facets
+    pub facets: Option<SearchFacets>,
+}
+
+#[derive(Debug, Error)]
+pub enum SearchError {
+    #[error("meilisearch error: {0}")]
+    Meilisearch(#[from] meilisearch_sdk::errors::Error),
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("hybrid search request failed: {0}")]
+    Hybrid(#[from] reqwest::Error),
+}
+
+/// Full-text search over the indexed topics and posts
+#[async_trait]
+pub trait SearchBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Search the documents `access` may read
+    async fn search(
+        &self,
+        query: &SearchQuery,
+        access: &TopicAccess,
+    ) -> Result<SearchPage, SearchError>;
+}
+
+/// Meilisearch when it is configured, Postgres full-text search otherwise
+///
+/// Semantic ranking needs Meilisearch's vector store, Postgres search is keyword only.
+pub fn init_search(
+    meili: Option<&meili::Client>,
+    embeddings: Option<Arc<dyn EmbeddingProvider>>,
+    database: &Database,
+) -> Box<dyn SearchBackend> {
+    let backend: Box<dyn SearchBackend> = match meili {
+        Some(client) => Box::new(meilisearch::MeiliSearch::new(client.clone(), embeddings)),
+        None => Box::new(postgres::PostgresSearch::new(database.pool.clone())),
+    };
+    tracing::info!("Using {} for search", backend.name());
+
+    backend
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct TopicHit {
+    pub discourse_id: String,
+    pub topic_id: i32,
+    pub title: Option<String>,
+    pub slug: Option<String>,
+    pub pm_issue: Option<i32>,
+    pub category_id: Option<i32>,
+    pub tags: Vec<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    #[oai(rename = "_formatted")]
+    #[serde(rename = "_formatted")]
+    pub formatted: Option<SearchFormatted>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct PostHit {
+    pub discourse_id: String,
+    pub topic_id: i32,
+    pub post_id: i32,
+    pub post_number: i32,
+    pub user_id: Option<i32>,
+    pub username: Option<String>,
+    /// Post content with html tags stripped
+    pub cooked: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    #[oai(rename = "_formatted")]
+    #[serde(rename = "_formatted")]
+    pub formatted: Option<SearchFormatted>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Union)]
+#[oai(discriminator_name = "entity_type")]
+#[serde(tag = "entity_type", rename_all = "snake_case")]
+pub enum SearchHit {
+    #[oai(mapping = "topic")]
+    Topic(TopicHit),
+    #[oai(mapping = "post")]
+    Post(PostHit),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct SearchResponse {
+    pub query: String,
+    pub hits: Vec<SearchHit>,
+    pub page: usize,
+    pub size: usize,
+    pub total_hits: usize,
+    pub total_pages: usize,
+    pub processing_time_ms: usize,
+    pub facets: Option<SearchFacets>,
+}
+
+impl SearchResponse {
+    /// `page` is 1-based, `size` the page size used for `results`
+    pub fn from_page(query: String, page: usize, size: usize, results: SearchPage) -> Self {
+        Self {
+            query,
+            hits: results
+                .documents
+                .into_iter()
+                .filter_map(SearchHit::from_document)
+                .collect(),
+            page,
+            size,
+            total_hits: results.total_hits,
+            total_pages: results.total_hits.div_ceil(size),
+            processing_time_ms: results.processing_time_ms,
+            facets: results.facets,
+        }
+    }
+}
+
+impl SearchHit {
+    /// Documents that are neither a topic nor a post, or miss their ids, are skipped
+    pub fn from_document(hit: SearchDocument) -> Option<Self> {
+        let SearchDocument {
+            document,
+            formatted,
+        } = hit;
+        let created_at = document
+            .created_at
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0));
+
+        match document.entity_type.as_str() {
+            "topic" => Some(Self::Topic(TopicHit {
+                discourse_id: document.discourse_id?,
+                topic_id: document.topic_id?,
+                title: document.title,
+                slug: document.slug,
+                pm_issue: document.pm_issue,
+                category_id: document.category_id,
+                tags: document.tags.unwrap_or_default(),
+                created_at,
+                formatted,
+            })),
+            "post" => Some(Self::Post(PostHit {
+                discourse_id: document.discourse_id?,
+                topic_id: document.topic_id?,
+                post_id: document.post_id?,
+                post_number: document.post_number?,
+                user_id: document.user_id,
+                username: document.username,
+                cooked: document.cooked,
+                created_at,
+                formatted,
+            })),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_facets_from_distribution() {
+        let distribution = HashMap::from([
+            (
+                "username".to_string(),
+                HashMap::from([
+                    ("vbuterin".to_string(), 3),
+                    ("alice".to_string(), 5),
+                    ("bob".to_string(), 3),
+                ]),
+            ),
+            (
+                "created_year".to_string(),
+                HashMap::from([("2024".to_string(), 7)]),
+            ),
+            ("tags".to_string(), HashMap::from([("eip".to_string(), 1)])),
+        ]);
+
+        let facets = SearchFacets::from_distribution(distribution);
+        let usernames: Vec<(&str, usize)> = facets
+            .username
+            .iter()
+            .map(|facet| (facet.value.as_str(), facet.count))
+            .collect();
+        assert_eq!(usernames, [("alice", 5), ("bob", 3), ("vbuterin", 3)]);
+        assert_eq!(facets.created_year.len(), 1);
+        assert!(facets.discourse_id.is_empty());
+    }
+}
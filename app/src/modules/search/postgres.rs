@@ -0,0 +1,322 @@
+use std::{collections::HashMap, time::Instant};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Utc};
+use sqlx::{PgPool, Postgres, postgres::PgArguments, prelude::FromRow, query::QueryAs, query_as};
+
+use crate::{models::topics::TopicAccess, modules::discourse::ForumSearchDocument};
+
+use super::{
+    CROP_LENGTH, HIGHLIGHT_POST_TAG, HIGHLIGHT_PRE_TAG, SearchBackend, SearchDocument, SearchError,
+    SearchFacets, SearchFormatted, SearchPage, SearchQuery,
+};
+
+/// Topics match on their title and posts on their content without html tags, same as
+/// the Meilisearch documents. Shared by the page and facet queries, binds $1 to $10.
+const HITS: &str = r#"
+WITH q AS (
+    SELECT websearch_to_tsquery('english', $1) AS query
+),
+hits AS (
+    SELECT 'topic' AS entity_type, t.discourse_id, t.topic_id, NULL::INT AS post_id,
+        NULL::INT AS post_number, NULL::INT AS user_id, NULL::TEXT AS username,
+        t.title, t.slug, t.pm_issue, NULL::TEXT AS cooked, t.visibility, t.category_id,
+        t.tags, t.created_at, ts_rank(t.search_vector, q.query) AS rank
+    FROM topics t, q
+    WHERE ($1 = '' OR t.search_vector @@ q.query)
+        AND ($3::TEXT IS NULL OR $3 = 'topic')
+        AND $4::TEXT IS NULL
+        AND $5::INT IS NULL
+        AND ($6::INT IS NULL OR t.topic_id = $6)
+        AND ($7::INT IS NULL OR t.pm_issue = $7)
+        AND ($8::TIMESTAMPTZ IS NULL OR t.created_at >= $8)
+        AND ($9::TIMESTAMPTZ IS NULL OR t.created_at <= $9)
+        AND ($2::TEXT IS NULL OR t.discourse_id = $2)
+        AND (t.visibility <> 'restricted' OR t.discourse_id = ANY($10))
+    UNION ALL
+    SELECT 'post', p.discourse_id, p.topic_id, p.post_id, p.post_number, p.user_id,
+        p.extra->>'username', NULL, NULL, NULL, p.cooked, t.visibility, t.category_id,
+        t.tags, p.created_at, ts_rank(p.search_vector, q.query)
+    FROM posts p
+    JOIN topics t ON t.discourse_id = p.discourse_id AND t.topic_id = p.topic_id, q
+    WHERE ($1 = '' OR p.search_vector @@ q.query)
+        AND p.deleted_at IS NULL
+        AND ($3::TEXT IS NULL OR $3 = 'post')
+        AND ($4::TEXT IS NULL OR p.extra->>'username' = $4)
+        AND ($5::INT IS NULL OR p.user_id = $5)
+        AND ($6::INT IS NULL OR p.topic_id = $6)
+        AND $7::INT IS NULL
+        AND ($8::TIMESTAMPTZ IS NULL OR p.created_at >= $8)
+        AND ($9::TIMESTAMPTZ IS NULL OR p.created_at <= $9)
+        AND ($2::TEXT IS NULL OR p.discourse_id = $2)
+        AND (t.visibility <> 'restricted' OR t.discourse_id = ANY($10))
+)
+"#;
+
+/// Follows `HITS`, highlights are computed for the returned page only
+const PAGE_QUERY: &str = r#"
+, page AS (
+    SELECT *, COUNT(*) OVER () AS total_hits
+    FROM hits
+    ORDER BY rank DESC, created_at DESC NULLS LAST
+    LIMIT $11 OFFSET $12
+),
+stripped AS (
+    SELECT page.*,
+        btrim(regexp_replace(regexp_replace(page.cooked, '<[^>]*>', ' ', 'g'), '\s+', ' ', 'g')) AS text
+    FROM page
+)
+SELECT stripped.entity_type, stripped.discourse_id, stripped.topic_id, stripped.post_id,
+    stripped.post_number, stripped.user_id, stripped.username, stripped.title, stripped.slug,
+    stripped.pm_issue, stripped.text AS cooked, stripped.visibility, stripped.category_id,
+    stripped.tags, stripped.created_at, stripped.total_hits,
+    ts_headline('english', stripped.title, q.query, $13) AS formatted_title,
+    ts_headline('english', stripped.text, q.query, $14) AS formatted_cooked
+FROM stripped, q
+ORDER BY stripped.rank DESC, stripped.created_at DESC NULLS LAST
+"#;
+
+/// Follows `HITS`, counts every match by `FACETS`
+const FACETS_QUERY: &str = r#"
+SELECT 'discourse_id' AS facet, discourse_id AS value, COUNT(*) AS count
+FROM hits GROUP BY discourse_id
+UNION ALL
+SELECT 'entity_type', entity_type, COUNT(*) FROM hits GROUP BY entity_type
+UNION ALL
+SELECT 'username', username, COUNT(*) FROM hits WHERE username IS NOT NULL GROUP BY username
+UNION ALL
+SELECT 'pm_issue', pm_issue::TEXT, COUNT(*) FROM hits WHERE pm_issue IS NOT NULL GROUP BY pm_issue
+UNION ALL
+SELECT 'created_year', EXTRACT(YEAR FROM created_at AT TIME ZONE 'UTC')::INT::TEXT, COUNT(*)
+FROM hits WHERE created_at IS NOT NULL GROUP BY 2
+"#;
+
+/// Follows `HITS`, for pages that have no row to read `total_hits` from
+const COUNT_QUERY: &str = "SELECT COUNT(*) FROM hits";
+
+#[derive(FromRow)]
+struct SearchRow {
+    entity_type: String,
+    discourse_id: String,
+    topic_id: i32,
+    post_id: Option<i32>,
+    post_number: Option<i32>,
+    user_id: Option<i32>,
+    username: Option<String>,
+    title: Option<String>,
+    slug: Option<String>,
+    pm_issue: Option<i32>,
+    cooked: Option<String>,
+    visibility: String,
+    category_id: Option<i32>,
+    tags: Vec<String>,
+    created_at: Option<DateTime<Utc>>,
+    total_hits: i64,
+    formatted_title: Option<String>,
+    formatted_cooked: Option<String>,
+}
+
+impl From<SearchRow> for SearchDocument {
+    fn from(row: SearchRow) -> Self {
+        let entity_id = match row.post_id {
+            Some(post_id) => ForumSearchDocument::post_entity_id(&row.discourse_id, post_id),
+            None => ForumSearchDocument::topic_entity_id(&row.discourse_id, row.topic_id),
+        };
+
+        Self {
+            document: ForumSearchDocument {
+                entity_type: row.entity_type,
+                discourse_id: Some(row.discourse_id),
+                topic_id: Some(row.topic_id),
+                post_id: row.post_id,
+                post_number: row.post_number,
+                user_id: row.user_id,
+                username: row.username,
+                title: row.title,
+                slug: row.slug,
+                pm_issue: row.pm_issue,
+                cooked: row.cooked,
+                visibility: Some(row.visibility),
+                category_id: row.category_id,
+                tags: Some(row.tags),
+                created_at: row.created_at.map(|created_at| created_at.timestamp()),
+                created_year: row.created_at.map(|created_at| created_at.year()),
+                updated_at: None,
+                entity_id,
+                vectors: None,
+            },
+            formatted: Some(SearchFormatted {
+                title: row.formatted_title,
+                cooked: row.formatted_cooked,
+            }),
+        }
+    }
+}
+
+/// Searches the `search_vector` columns of `topics` and `posts`
+pub struct PostgresSearch {
+    pool: PgPool,
+}
+
+impl PostgresSearch {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl SearchBackend for PostgresSearch {
+    fn name(&self) -> &'static str {
+        "postgres full-text search"
+    }
+
+    async fn search(
+        &self,
+        query: &SearchQuery,
+        access: &TopicAccess,
+    ) -> Result<SearchPage, SearchError> {
+        let started = Instant::now();
+
+        let title_options = format!(
+            "StartSel={}, StopSel={}, HighlightAll=true",
+            HIGHLIGHT_PRE_TAG, HIGHLIGHT_POST_TAG
+        );
+        let cooked_options = format!(
+            "StartSel={}, StopSel={}, MaxWords={}, MinWords={}",
+            HIGHLIGHT_PRE_TAG,
+            HIGHLIGHT_POST_TAG,
+            CROP_LENGTH,
+            CROP_LENGTH / 2
+        );
+
+        let page_query = format!("{}{}", HITS, PAGE_QUERY);
+        let rows: Vec<SearchRow> = bind_hits(query_as(&page_query), query, access)
+            .bind(query.limit as i64)
+            .bind(i64::try_from(query.offset).unwrap_or(i64::MAX))
+            .bind(title_options)
+            .bind(cooked_options)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let facets = if query.facets {
+            let facets_query = format!("{}{}", HITS, FACETS_QUERY);
+            let counts: Vec<(String, String, i64)> =
+                bind_hits(query_as(&facets_query), query, access)
+                    .fetch_all(&self.pool)
+                    .await?;
+
+            let mut distribution: HashMap<String, HashMap<String, usize>> = HashMap::new();
+            for (facet, value, count) in counts {
+                distribution
+                    .entry(facet)
+                    .or_default()
+                    .insert(value, count as usize);
+            }
+            Some(SearchFacets::from_distribution(distribution))
+        } else {
+            None
+        };
+
+        // Past the last hit, or with a limit of 0, the page is empty but the query isn't
+        let total_hits = match (rows.first(), &facets) {
+            (Some(row), _) => row.total_hits as usize,
+            (None, Some(facets)) => facets.entity_type.iter().map(|facet| facet.count).sum(),
+            (None, None) if query.offset == 0 && query.limit > 0 => 0,
+            (None, None) => {
+                let count_query = format!("{}{}", HITS, COUNT_QUERY);
+                let (count,): (i64,) = bind_hits(query_as(&count_query), query, access)
+                    .fetch_one(&self.pool)
+                    .await?;
+                count as usize
+            }
+        };
+
+        Ok(SearchPage {
+            total_hits,
+            documents: rows.into_iter().map(SearchDocument::from).collect(),
+            processing_time_ms: started.elapsed().as_millis() as usize,
+            facets,
+        })
+    }
+}
+
+/// Bind the filters of `HITS`
+fn bind_hits<'q, O>(
+    statement: QueryAs<'q, Postgres, O, PgArguments>,
+    query: &'q SearchQuery,
+    access: &'q TopicAccess,
+) -> QueryAs<'q, Postgres, O, PgArguments> {
+    statement
+        .bind(query.query.trim())
+        .bind(&query.discourse_id)
+        .bind(query.entity_type.map(|entity_type| entity_type.as_str()))
+        .bind(&query.username)
+        .bind(query.user_id)
+        .bind(query.topic_id)
+        .bind(query.pm_issue)
+        .bind(query.from)
+        .bind(query.to)
+        .bind(access.restricted())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placeholders(sql: &str) -> Vec<usize> {
+        let mut numbers: Vec<usize> = sql
+            .split('$')
+            .skip(1)
+            .filter_map(|rest| {
+                let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+                digits.parse().ok()
+            })
+            .collect();
+        numbers.sort();
+        numbers.dedup();
+        numbers
+    }
+
+    #[test]
+    fn test_query_placeholders() {
+        // `bind_hits` binds $1 to $10, the page query binds the rest in order
+        assert_eq!(placeholders(HITS), (1..=10).collect::<Vec<_>>());
+        assert_eq!(placeholders(PAGE_QUERY), (11..=14).collect::<Vec<_>>());
+        assert!(placeholders(FACETS_QUERY).is_empty());
+        assert!(placeholders(COUNT_QUERY).is_empty());
+    }
+
+    #[test]
+    fn test_row_to_document() {
+        let row = SearchRow {
+            entity_type: "post".to_string(),
+            discourse_id: "magicians".to_string(),
+            topic_id: 12,
+            post_id: Some(345),
+            post_number: Some(2),
+            user_id: Some(7),
+            username: Some("alice".to_string()),
+            title: None,
+            slug: None,
+            pm_issue: None,
+            cooked: Some("Blob fees".to_string()),
+            visibility: "public".to_string(),
+            category_id: Some(3),
+            tags: vec!["eip".to_string()],
+            created_at: DateTime::from_timestamp(1_700_000_000, 0),
+            total_hits: 1,
+            formatted_title: None,
+            formatted_cooked: Some("<em>Blob</em> fees".to_string()),
+        };
+
+        let hit = SearchDocument::from(row);
+        assert_eq!(hit.document.entity_id, "magicians_post_345");
+        assert_eq!(hit.document.discourse_id.as_deref(), Some("magicians"));
+        assert_eq!(hit.document.created_at, Some(1_700_000_000));
+        assert_eq!(hit.document.created_year, Some(2023));
+        assert_eq!(
+            hit.formatted.unwrap().cooked.as_deref(),
+            Some("<em>Blob</em> fees")
+        );
+    }
+}
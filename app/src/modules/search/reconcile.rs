@@ -0,0 +1,373 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{Context, Result, bail};
+use async_std::sync::RwLock;
+use chrono::{DateTime, TimeDelta, Utc};
+use meilisearch_sdk::documents::{DocumentsQuery, DocumentsResults};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query_as};
+use tracing::{error, info};
+
+use crate::{
+    models::{
+        search::reindex::ReindexJob,
+        topics::{Topic, post::Post},
+    },
+    modules::{
+        discourse::{ForumSearchDocument, schedule::ScrapeSchedule},
+        meili,
+    },
+    state::AppState,
+};
+
+use super::reindex::{add_documents, post_documents, wait_for};
+
+const DEFAULT_SCHEDULE: &str = "6h";
+/// Documents listed per request when reading the index
+const PAGE_SIZE: usize = 1000;
+/// Documents re-pushed or deleted per Meilisearch task
+const BATCH_SIZE: usize = 500;
+
+/// Drift found between the database and the forum index by one reconciliation
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct ReconcileReport {
+    /// Only count the drift, without fixing it
+    pub dry_run: bool,
+    pub started_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub database_documents: usize,
+    pub index_documents: usize,
+    /// In the database but not in the index
+    pub missing: usize,
+    /// In both, with a different `updated_at`
+    pub stale: usize,
+    /// In the index but not in the database, including deleted posts
+    pub orphaned: usize,
+    pub error: Option<String>,
+}
+
+/// `updated_at` of every document by entity id
+type Versions = HashMap<String, Option<i64>>;
+
+/// Entity ids of the documents to re-push or delete
+#[derive(Debug, Default, PartialEq)]
+struct Drift {
+    missing: Vec<String>,
+    stale: Vec<String>,
+    orphaned: Vec<String>,
+}
+
+#[derive(FromRow)]
+struct VersionRow {
+    discourse_id: String,
+    id: i32,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+impl VersionRow {
+    fn topic_entity_id(&self) -> String {
+        ForumSearchDocument::topic_entity_id(&self.discourse_id, self.id)
+    }
+
+    fn post_entity_id(&self) -> String {
+        ForumSearchDocument::post_entity_id(&self.discourse_id, self.id)
+    }
+
+    fn timestamp(&self) -> Option<i64> {
+        self.updated_at.map(|updated_at| updated_at.timestamp())
+    }
+}
+
+#[derive(Deserialize)]
+struct IndexedVersion {
+    entity_id: String,
+    #[serde(default)]
+    updated_at: Option<i64>,
+}
+
+/// Keeps the forum index in line with the database
+///
+/// Indexing writes to both independently and only logs Meilisearch errors, so
+/// documents can go missing, fall behind their row or outlive it. Reconciling compares
+/// the entity id and `updated_at` of every document, re-pushes missing and
+/// stale ones from the database and deletes orphans. Documents indexed before
+/// `updated_at` was stored count as stale once.
+#[derive(Default)]
+pub struct Reconciler {
+    running: AtomicBool,
+    last_report: RwLock<Option<ReconcileReport>>,
+}
+
+impl Reconciler {
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    pub async fn last_report(&self) -> Option<ReconcileReport> {
+        self.last_report.read().await.clone()
+    }
+
+    /// Reconcile in the background, `false` when a run is already in progress
+    pub fn start(&self, dry_run: bool, state: &AppState) -> bool {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+
+        let state = state.clone();
+        async_std::task::spawn(async move {
+            state.reconcile.run(dry_run, &state).await;
+            state.reconcile.running.store(false, Ordering::SeqCst);
+        });
+
+        true
+    }
+
+    /// Reconcile on `SEARCH_RECONCILE_SCHEDULE`, a duration or cron expression
+    /// defaulting to every 6 hours
+    pub async fn reconcile_periodically(&self, state: &AppState) {
+        if state.meili.is_none() {
+            return;
+        }
+
+        let schedule = std::env::var("SEARCH_RECONCILE_SCHEDULE")
+            .unwrap_or_else(|_| DEFAULT_SCHEDULE.to_string());
+        let schedule: ScrapeSchedule = match schedule.parse() {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                error!("Invalid SEARCH_RECONCILE_SCHEDULE, not reconciling: {}", e);
+                return;
+            }
+        };
+
+        loop {
+            let now = Utc::now();
+            let next = schedule
+                .next_after(now)
+                .unwrap_or_else(|| now + TimeDelta::hours(6));
+            info!("Next search reconciliation at: {:?}", next);
+            async_std::task::sleep(next.signed_duration_since(now).to_std().unwrap_or_default())
+                .await;
+
+            if self.running.swap(true, Ordering::SeqCst) {
+                continue;
+            }
+            self.run(false, state).await;
+            self.running.store(false, Ordering::SeqCst);
+        }
+    }
+
+    async fn run(&self, dry_run: bool, state: &AppState) {
+        let mut report = ReconcileReport {
+            dry_run,
+            started_at: Utc::now(),
+            completed_at: None,
+            database_documents: 0,
+            index_documents: 0,
+            missing: 0,
+            stale: 0,
+            orphaned: 0,
+            error: None,
+        };
+
+        if let Err(e) = reconcile(&mut report, state).await {
+            error!("Error reconciling the search index: {:?}", e);
+            report.error = Some(e.to_string());
+        }
+        report.completed_at = Some(Utc::now());
+
+        info!(
+            "Reconciled search index: {} missing, {} stale and {} orphaned documents{}",
+            report.missing,
+            report.stale,
+            report.orphaned,
+            if dry_run { " (dry run)" } else { "" }
+        );
+        *self.last_report.write().await = Some(report);
+    }
+}
+
+async fn reconcile(report: &mut ReconcileReport, state: &AppState) -> Result<()> {
+    let meili = state
+        .meili
+        .as_ref()
+        .context("Meilisearch is not configured")?;
+    // A swap job replaces the index anyway, an in place one would race the fixes
+    if let Some(job) = ReindexJob::find_running(state).await? {
+        bail!("reindex job {} is running", job.job_id);
+    }
+
+    // The index is read first, anything written to both in between is only re-pushed
+    let index = index_versions(meili).await?;
+
+    let topics: Vec<VersionRow> =
+        query_as("SELECT discourse_id, topic_id AS id, updated_at FROM topics")
+            .fetch_all(&state.database.pool)
+            .await?;
+    // Post documents copy fields of their topic, see `ForumSearchDocument::updated_at`
+    let posts: Vec<VersionRow> = query_as(
+        "SELECT p.discourse_id, p.post_id AS id, GREATEST(p.updated_at, t.updated_at) AS updated_at FROM posts p LEFT JOIN topics t ON t.discourse_id = p.discourse_id AND t.topic_id = p.topic_id WHERE p.deleted_at IS NULL",
+    )
+    .fetch_all(&state.database.pool)
+    .await?;
+
+    let database: Versions = topics
+        .iter()
+        .map(|row| (row.topic_entity_id(), row.timestamp()))
+        .chain(
+            posts
+                .iter()
+                .map(|row| (row.post_entity_id(), row.timestamp())),
+        )
+        .collect();
+
+    report.database_documents = database.len();
+    report.index_documents = index.len();
+
+    let drift = diff(&database, index);
+    report.missing = drift.missing.len();
+    report.stale = drift.stale.len();
+    report.orphaned = drift.orphaned.len();
+
+    if report.dry_run {
+        return Ok(());
+    }
+
+    let outdated: HashSet<&String> = drift.missing.iter().chain(&drift.stale).collect();
+    let outdated_topics: Vec<&VersionRow> = topics
+        .iter()
+        .filter(|row| outdated.contains(&row.topic_entity_id()))
+        .collect();
+    let outdated_posts: Vec<&VersionRow> = posts
+        .iter()
+        .filter(|row| outdated.contains(&row.post_entity_id()))
+        .collect();
+
+    for batch in outdated_topics.chunks(BATCH_SIZE) {
+        let (discourse_ids, topic_ids) = keys(batch);
+        let topics: Vec<Topic> = query_as(
+            "SELECT * FROM topics WHERE (discourse_id, topic_id) IN (SELECT * FROM unnest($1::TEXT[], $2::INT[]))",
+        )
+        .bind(&discourse_ids)
+        .bind(&topic_ids)
+        .fetch_all(&state.database.pool)
+        .await?;
+
+        let mut documents: Vec<ForumSearchDocument> =
+            topics.iter().map(ForumSearchDocument::from_topic).collect();
+        add_documents(&mut documents, "forum", meili, state).await?;
+    }
+
+    for batch in outdated_posts.chunks(BATCH_SIZE) {
+        let (discourse_ids, post_ids) = keys(batch);
+        let posts: Vec<Post> = query_as(
+            "SELECT * FROM posts WHERE deleted_at IS NULL AND (discourse_id, post_id) IN (SELECT * FROM unnest($1::TEXT[], $2::INT[]))",
+        )
+        .bind(&discourse_ids)
+        .bind(&post_ids)
+        .fetch_all(&state.database.pool)
+        .await?;
+
+        let mut documents = post_documents(&posts, state).await?;
+        add_documents(&mut documents, "forum", meili, state).await?;
+    }
+
+    let forum = meili.index("forum");
+    for batch in drift.orphaned.chunks(BATCH_SIZE) {
+        let task = forum.delete_documents(batch).await?;
+        wait_for(task, meili).await?;
+    }
+
+    Ok(())
+}
+
+/// Key and `updated_at` of every document in the forum index
+async fn index_versions(meili: &meili::Client) -> Result<Versions> {
+    let forum = meili.index("forum");
+    let mut versions = Versions::new();
+    let mut offset = 0;
+
+    loop {
+        let mut query = DocumentsQuery::new(&forum);
+        query
+            .with_offset(offset)
+            .with_limit(PAGE_SIZE)
+            .with_fields(["entity_id", "updated_at"]);
+        let page: DocumentsResults<IndexedVersion> = forum.get_documents_with(&query).await?;
+
+        let count = page.results.len();
+        offset += count;
+        versions.extend(
+            page.results
+                .into_iter()
+                .map(|document| (document.entity_id, document.updated_at)),
+        );
+        if count < PAGE_SIZE {
+            return Ok(versions);
+        }
+    }
+}
+
+fn keys(rows: &[&VersionRow]) -> (Vec<String>, Vec<i32>) {
+    rows.iter()
+        .map(|row| (row.discourse_id.clone(), row.id))
+        .unzip()
+}
+
+fn diff(database: &Versions, mut index: Versions) -> Drift {
+    let mut drift = Drift::default();
+    for (key, updated_at) in database {
+        match index.remove(key) {
+            None => drift.missing.push(key.clone()),
+            Some(indexed) if indexed != *updated_at => drift.stale.push(key.clone()),
+            Some(_) => {}
+        }
+    }
+    drift.orphaned = index.into_keys().collect();
+
+    drift.missing.sort();
+    drift.stale.sort();
+    drift.orphaned.sort();
+    drift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let database = Versions::from([
+            ("magicians_topic_1".to_string(), Some(100)),
+            ("magicians_topic_2".to_string(), Some(200)),
+            ("magicians_post_10".to_string(), None),
+            ("magicians_post_11".to_string(), Some(300)),
+            ("research_post_11".to_string(), Some(300)),
+        ]);
+        let index = Versions::from([
+            ("magicians_topic_1".to_string(), Some(100)),
+            ("magicians_topic_2".to_string(), Some(150)),
+            ("magicians_post_10".to_string(), None),
+            ("magicians_post_12".to_string(), Some(300)),
+            ("research_post_11".to_string(), Some(300)),
+            // Keyed without the instance before it was part of the entity id
+            ("post_11".to_string(), Some(300)),
+        ]);
+
+        assert_eq!(
+            diff(&database, index),
+            Drift {
+                missing: ids(&["magicians_post_11"]),
+                stale: ids(&["magicians_topic_2"]),
+                orphaned: ids(&["magicians_post_12", "post_11"]),
+            }
+        );
+    }
+}
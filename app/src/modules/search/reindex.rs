@@ -0,0 +1,443 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use meilisearch_sdk::{
+    client::SwapIndexes,
+    documents::{DocumentsQuery, DocumentsResults},
+    task_info::TaskInfo,
+    tasks::{Task, TasksSearchQuery},
+};
+use serde::Deserialize;
+use sqlx::query_as;
+use tracing::{error, info};
+
+use crate::{
+    models::{
+        search::reindex::{PHASE_CLEANUP, PHASE_POSTS, PHASE_SWAP, PHASE_TOPICS, ReindexJob},
+        topics::{Topic, post::Post},
+    },
+    modules::{discourse::ForumSearchDocument, meili},
+    state::AppState,
+};
+
+use super::embeddings;
+
+/// Topics or posts read from Postgres and sent to Meilisearch at once
+const BATCH_SIZE: i64 = 500;
+const TASK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// A batch may wait behind the indexer's own updates in the Meilisearch task queue
+const TASK_TIMEOUT: Duration = Duration::from_secs(600);
+/// Documents checked for entity ids without their instance on startup
+const ENTITY_ID_SAMPLE: usize = 100;
+
+#[derive(Deserialize)]
+struct IndexedId {
+    #[serde(default)]
+    discourse_id: Option<String>,
+    entity_id: String,
+}
+
+/// Rebuilds the forum index from Postgres in the background
+///
+/// Topics and then posts are streamed in keyset order, each batch is only recorded
+/// in `search_reindex_jobs` once Meilisearch applied it, so an interrupted job
+/// resumes after its last indexed batch. Swap jobs build a separate index and swap
+/// it with `forum` at the end, search keeps using the old index until then.
+/// Documents the indexer updates during a swap job only reach the old index, they
+/// are refreshed in the new one on their next crawl.
+#[derive(Default)]
+pub struct Reindexer {
+    running: AtomicBool,
+}
+
+impl Reindexer {
+    /// Start a job, or return the one already running
+    pub async fn start(&self, swap: bool, state: &AppState) -> Result<ReindexJob, sqlx::Error> {
+        let job = match ReindexJob::find_running(state).await? {
+            Some(job) => job,
+            None => match ReindexJob::create(swap, state).await {
+                Ok(job) => job,
+                // Another request created one in the meantime
+                Err(e) if is_unique_violation(&e) => {
+                    ReindexJob::find_running(state).await?.ok_or(e)?
+                }
+                Err(e) => return Err(e),
+            },
+        };
+
+        self.spawn(job.clone(), state);
+        Ok(job)
+    }
+
+    /// Continue a failed or interrupted job from its cursor, `None` when there is no
+    /// such job
+    ///
+    /// Fails with a unique violation while another job is running.
+    pub async fn resume(
+        &self,
+        job_id: i64,
+        state: &AppState,
+    ) -> Result<Option<ReindexJob>, sqlx::Error> {
+        let Some(mut job) = ReindexJob::find_by_id(job_id, state).await? else {
+            return Ok(None);
+        };
+
+        if job.status == "failed" {
+            job.resume(state).await?;
+        }
+        if job.status == "running" {
+            self.spawn(job.clone(), state);
+        }
+
+        Ok(Some(job))
+    }
+
+    /// Pick up the job a previous process was running when it stopped
+    pub async fn resume_interrupted(&self, state: &AppState) {
+        match ReindexJob::find_running(state).await {
+            Ok(Some(job)) => {
+                info!("Resuming interrupted reindex job {}", job.job_id);
+                self.spawn(job, state);
+            }
+            Ok(None) => {}
+            Err(e) => error!("Error looking up interrupted reindex jobs: {:?}", e),
+        }
+    }
+
+    /// Rebuild the forum index with a swap job when it still has documents keyed without
+    /// their instance
+    ///
+    /// Entity ids used to be `topic_{id}` and `post_{id}`, so instances sharing an id
+    /// overwrote each other's documents.
+    pub async fn migrate_entity_ids(&self, state: &AppState) {
+        let Some(meili) = &state.meili else {
+            return;
+        };
+
+        match has_legacy_entity_ids(meili).await {
+            Ok(false) => {}
+            Ok(true) => {
+                info!("Forum index has entity ids without their instance, reindexing");
+                if let Err(e) = self.start(true, state).await {
+                    error!("Error starting the entity id reindex: {:?}", e);
+                }
+            }
+            Err(e) => error!("Error checking the entity ids of the forum index: {:?}", e),
+        }
+    }
+
+    /// Run `job` in the background unless this process is already running one
+    fn spawn(&self, job: ReindexJob, state: &AppState) {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let state = state.clone();
+        async_std::task::spawn(async move {
+            run(job, &state).await;
+            state.reindex.running.store(false, Ordering::SeqCst);
+        });
+    }
+}
+
+async fn has_legacy_entity_ids(meili: &meili::Client) -> Result<bool> {
+    let forum = meili.index("forum");
+    let mut query = DocumentsQuery::new(&forum);
+    query
+        .with_limit(ENTITY_ID_SAMPLE)
+        .with_fields(["discourse_id", "entity_id"]);
+    let page: DocumentsResults<IndexedId> = forum.get_documents_with(&query).await?;
+
+    Ok(page.results.iter().any(is_legacy_entity_id))
+}
+
+fn is_legacy_entity_id(document: &IndexedId) -> bool {
+    document.discourse_id.as_ref().is_none_or(|discourse_id| {
+        !document
+            .entity_id
+            .starts_with(&format!("{}_", discourse_id))
+    })
+}
+
+fn is_unique_violation(error: &sqlx::Error) -> bool {
+    error
+        .as_database_error()
+        .is_some_and(|error| error.is_unique_violation())
+}
+
+async fn run(mut job: ReindexJob, state: &AppState) {
+    info!(
+        "Running reindex job {} into {} from phase {}",
+        job.job_id, job.target_index, job.phase
+    );
+
+    match run_phases(&mut job, state).await {
+        Ok(()) => info!(
+            "Reindex job {} completed with {} topics and {} posts",
+            job.job_id, job.topics_indexed, job.posts_indexed
+        ),
+        Err(e) => {
+            error!("Reindex job {} failed: {:?}", job.job_id, e);
+            if let Err(e) = job.fail(&e.to_string(), state).await {
+                error!(
+                    "Error recording failure of reindex job {}: {:?}",
+                    job.job_id, e
+                );
+            }
+        }
+    }
+}
+
+async fn run_phases(job: &mut ReindexJob, state: &AppState) -> Result<()> {
+    let meili = state
+        .meili
+        .as_ref()
+        .context("Meilisearch is not configured")?;
+
+    if job.swap && (job.phase == PHASE_TOPICS || job.phase == PHASE_POSTS) {
+        meili::configure_index(meili, &job.target_index)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        if let Some(embeddings) = &state.embeddings {
+            meili::configure_embedder(meili, &job.target_index, embeddings.dimensions()).await?;
+        }
+    }
+
+    loop {
+        match job.phase.as_str() {
+            PHASE_TOPICS => {
+                if !index_topics(job, meili, state).await? {
+                    job.set_phase(PHASE_POSTS, state).await?;
+                }
+            }
+            PHASE_POSTS => {
+                if !index_posts(job, meili, state).await? {
+                    if !job.swap {
+                        break;
+                    }
+                    job.set_phase(PHASE_SWAP, state).await?;
+                }
+            }
+            PHASE_SWAP => {
+                // A process stopped between the swap and recording the next phase must not
+                // swap again, the old index would be back in `forum` and then deleted
+                match latest_swap(&job.target_index, meili).await? {
+                    Some(Task::Succeeded { .. }) => {}
+                    Some(task @ (Task::Enqueued { .. } | Task::Processing { .. })) => {
+                        let task = task
+                            .wait_for_completion(
+                                meili,
+                                Some(TASK_POLL_INTERVAL),
+                                Some(TASK_TIMEOUT),
+                            )
+                            .await?;
+                        if let Task::Failed { content } = task {
+                            bail!(
+                                "Swapping {} into forum failed: {}",
+                                job.target_index,
+                                content.error
+                            );
+                        }
+                    }
+                    Some(Task::Failed { .. }) | None => {
+                        let task = meili
+                            .swap_indexes([&SwapIndexes {
+                                indexes: ("forum".to_string(), job.target_index.clone()),
+                            }])
+                            .await?;
+                        wait_for(task, meili).await?;
+                    }
+                }
+                info!("Swapped {} into forum", job.target_index);
+                job.set_phase(PHASE_CLEANUP, state).await?;
+            }
+            PHASE_CLEANUP => {
+                // After the swap the previous forum index lives under the target name
+                let task = meili.index(&job.target_index).delete().await?;
+                wait_for(task, meili).await?;
+                break;
+            }
+            phase => bail!("Unknown reindex phase '{}'", phase),
+        }
+    }
+
+    job.complete(state).await?;
+    Ok(())
+}
+
+/// The most recent swap of `index`, target indexes are unique to their job
+async fn latest_swap(index: &str, meili: &meili::Client) -> Result<Option<Task>> {
+    let mut query = TasksSearchQuery::new(meili);
+    query
+        .with_types(["indexSwap"])
+        .with_index_uids([index])
+        .with_limit(1);
+
+    Ok(query.execute().await?.results.into_iter().next())
+}
+
+/// Index the next batch of topics, `false` once all topics are indexed
+async fn index_topics(
+    job: &mut ReindexJob,
+    meili: &meili::Client,
+    state: &AppState,
+) -> Result<bool> {
+    let topics: Vec<Topic> = query_as(
+        "SELECT * FROM topics WHERE ($1::TEXT IS NULL OR (discourse_id, topic_id) > ($1, $2)) ORDER BY discourse_id, topic_id LIMIT $3",
+    )
+    .bind(&job.cursor_discourse_id)
+    .bind(job.cursor_id)
+    .bind(BATCH_SIZE)
+    .fetch_all(&state.database.pool)
+    .await?;
+    let Some(last) = topics.last() else {
+        return Ok(false);
+    };
+
+    let mut documents: Vec<ForumSearchDocument> =
+        topics.iter().map(ForumSearchDocument::from_topic).collect();
+    let task_uid = add_documents(&mut documents, &job.target_index, meili, state).await?;
+
+    job.advance(
+        (&last.discourse_id, last.topic_id),
+        topics.len() as i64,
+        0,
+        task_uid,
+        state,
+    )
+    .await?;
+    Ok(true)
+}
+
+/// Index the next batch of posts, `false` once all posts are indexed
+async fn index_posts(
+    job: &mut ReindexJob,
+    meili: &meili::Client,
+    state: &AppState,
+) -> Result<bool> {
+    let posts: Vec<Post> = query_as(
+        "SELECT * FROM posts WHERE deleted_at IS NULL AND ($1::TEXT IS NULL OR (discourse_id, post_id) > ($1, $2)) ORDER BY discourse_id, post_id LIMIT $3",
+    )
+    .bind(&job.cursor_discourse_id)
+    .bind(job.cursor_id)
+    .bind(BATCH_SIZE)
+    .fetch_all(&state.database.pool)
+    .await?;
+    let Some(last) = posts.last() else {
+        return Ok(false);
+    };
+
+    let mut documents = post_documents(&posts, state).await?;
+    let task_uid = add_documents(&mut documents, &job.target_index, meili, state).await?;
+
+    job.advance(
+        (&last.discourse_id, last.post_id),
+        0,
+        posts.len() as i64,
+        task_uid,
+        state,
+    )
+    .await?;
+    Ok(true)
+}
+
+/// Search documents for `posts`, with the visibility, category and tags of their topics
+pub async fn post_documents(
+    posts: &[Post],
+    state: &AppState,
+) -> Result<Vec<ForumSearchDocument>, sqlx::Error> {
+    let (discourse_ids, topic_ids): (Vec<String>, Vec<i32>) = posts
+        .iter()
+        .map(|post| (post.discourse_id.clone(), post.topic_id))
+        .unzip();
+    let topics: Vec<Topic> = query_as(
+        "SELECT * FROM topics WHERE (discourse_id, topic_id) IN (SELECT * FROM unnest($1::TEXT[], $2::INT[]))",
+    )
+    .bind(&discourse_ids)
+    .bind(&topic_ids)
+    .fetch_all(&state.database.pool)
+    .await?;
+    let topics_by_id: HashMap<(&str, i32), &Topic> = topics
+        .iter()
+        .map(|topic| ((topic.discourse_id.as_str(), topic.topic_id), topic))
+        .collect();
+
+    Ok(posts
+        .iter()
+        .map(|post| {
+            let username = post
+                .extra
+                .as_ref()
+                .and_then(|extra| extra.get("username"))
+                .and_then(|username| username.as_str())
+                .map(str::to_string);
+            let topic = topics_by_id
+                .get(&(post.discourse_id.as_str(), post.topic_id))
+                .copied();
+            ForumSearchDocument::from_post(post, username, topic)
+        })
+        .collect())
+}
+
+/// Send a batch to `index` and wait until Meilisearch applied it, returns the task uid
+pub async fn add_documents(
+    documents: &mut [ForumSearchDocument],
+    index: &str,
+    meili: &meili::Client,
+    state: &AppState,
+) -> Result<u32> {
+    embeddings::embed_documents(documents, state).await;
+
+    let task = meili
+        .index(index)
+        .add_documents(documents, Some("entity_id"))
+        .await?;
+    wait_for(task, meili).await
+}
+
+/// Poll a Meilisearch task until it is processed, failed tasks are errors
+pub async fn wait_for(task: TaskInfo, meili: &meili::Client) -> Result<u32> {
+    let task_uid = task.get_task_uid();
+    let task = task
+        .wait_for_completion(meili, Some(TASK_POLL_INTERVAL), Some(TASK_TIMEOUT))
+        .await?;
+    if task.is_failure() {
+        bail!(
+            "Meilisearch task {} failed: {}",
+            task_uid,
+            task.unwrap_failure()
+        );
+    }
+
+    Ok(task_uid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indexed(discourse_id: Option<&str>, entity_id: &str) -> IndexedId {
+        IndexedId {
+            discourse_id: discourse_id.map(str::to_string),
+            entity_id: entity_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_legacy_entity_ids() {
+        assert!(!is_legacy_entity_id(&indexed(
+            Some("magicians"),
+            "magicians_post_11"
+        )));
+        assert!(is_legacy_entity_id(&indexed(Some("magicians"), "post_11")));
+        assert!(is_legacy_entity_id(&indexed(
+            Some("research"),
+            "magicians_post_11"
+        )));
+        assert!(is_legacy_entity_id(&indexed(None, "topic_3")));
+    }
+}
@@ -1,48 +1,61 @@
 use async_openai::{
-    Client,
     types::{
         ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
         ChatCompletionRequestUserMessage, CreateChatCompletionRequest,
     },
 };
-use async_std::sync::RwLock;
+use async_std::sync::{Mutex, RwLock};
 use async_std::task;
+use figment::Figment;
 use opentelemetry_http::HttpError;
-use serde_json::json;
+use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::info;
 use uuid::Uuid;
 
 use crate::{
     models::{
-        topics::{
-            Topic,
-            post::{Post, WorkshopPost},
-        },
+        topics::Topic,
         workshop::{chat::WorkshopChat, message::WorkshopMessage},
     },
-    modules::workshop::prompts::{
-        OngoingPrompt, OngoingPromptManager, SHORTSUM_MODEL, SUMMARY_MODEL,
-        truncate_messages_to_token_limit,
+    modules::workshop::{
+        prompts::{OngoingPrompt, OngoingPromptManager, truncate_messages_to_token_limit},
+        provider::{LlmProvider, ProviderError},
+        registry::{ModelConfig, ModelRegistry},
     },
     state::AppState,
 };
 
 pub mod mcp_client;
 pub mod prompts;
+pub mod provider;
+pub mod registry;
+pub mod summary;
+pub mod tokenizer;
+
+/// Output limits of summaries and chat titles
+pub const SUMMARY_MAX_OUTPUT_TOKENS: u32 = 2000;
+const SHORTSUM_MAX_OUTPUT_TOKENS: u32 = 40;
 
 pub struct WorkshopService {
-    pub client: Client<async_openai::config::OpenAIConfig>,
+    /// `None` when no model provider is configured, workshop features are disabled
+    pub provider: Option<Arc<dyn LlmProvider>>,
+    /// `None` without a `[workshop]` table in `config.toml`, also disabling workshop features
+    pub models: Option<ModelRegistry>,
     pub prompts: WorkshopPrompts,
     // Manager for request coalescing of streaming responses
     pub ongoing_prompts: OngoingPromptManager,
     // MCP client manager for AI tool calling
     pub mcp_client: Arc<RwLock<mcp_client::McpClientManager>>,
+    /// Held while the chunks of a topic are summarized, so concurrent requests wait and
+    /// join the summary stream instead of summarizing every chunk again
+    summary_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
 }
 
 pub struct WorkshopPrompts {
     pub summerize: ChatCompletionRequestMessage,
     pub shortsum: ChatCompletionRequestMessage,
+    pub chunk_summary: ChatCompletionRequestMessage,
 }
 
 impl Default for WorkshopPrompts {
@@ -56,83 +69,82 @@ impl Default for WorkshopPrompts {
                 content: prompts::SHORTSUM_PROMPT.to_string().into(),
                 name: None,
             }),
+            chunk_summary: ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+                content: prompts::CHUNK_SUMMARY_PROMPT.to_string().into(),
+                name: None,
+            }),
         }
     }
 }
 
 impl WorkshopService {
-    pub async fn init() -> Self {
-        let api_key =
-            std::env::var("WORKSHOP_INTELLIGENCE_KEY").expect("WORKSHOP_INTELLIGENCE_KEY not set");
-
-        let base_url = std::env::var("WORKSHOP_INTELLIGENCE_BASE_URL")
-            .unwrap_or_else(|_| "https://openrouter.ai/api/v1".to_string());
-
-        tracing::info!("🔧 Workshop Service Init:");
-        tracing::info!("  API Key present: {}", !api_key.is_empty());
-        tracing::info!("  API Key length: {}", api_key.len());
-        tracing::info!("  Base URL: {}", base_url);
-
-        let config = async_openai::config::OpenAIConfig::new()
-            .with_api_key(api_key)
-            .with_api_base(base_url);
-
-        let client = Client::with_config(config);
-        tracing::info!("  OpenAI client configured successfully");
+    pub async fn init(models: Option<ModelRegistry>) -> Self {
+        let provider = match &models {
+            Some(_) => provider::init_provider(Figment::new()),
+            None => {
+                tracing::warn!("No models configured in [workshop], workshop is disabled");
+                None
+            }
+        };
 
-        // Initialize MCP client manager
+        // Initialize MCP client manager, its tools are only offered to a model
         let mut mcp_client = mcp_client::McpClientManager::new();
-        let mcp_base_url = std::env::var("MCP_BASE_URL")
-            .unwrap_or_else(|_| "https://ethereum.forum/mcp".to_string());
+        if provider.is_some() {
+            let mcp_base_url = std::env::var("MCP_BASE_URL")
+                .unwrap_or_else(|_| "https://ethereum.forum/mcp".to_string());
 
-        if let Err(e) = mcp_client.init_default_client(mcp_base_url).await {
-            tracing::warn!("Failed to initialize MCP client: {}", e);
+            if let Err(e) = mcp_client.init_default_client(mcp_base_url).await {
+                tracing::warn!("Failed to initialize MCP client: {}", e);
+            }
         }
 
         Self {
-            client,
+            provider,
+            models,
             prompts: WorkshopPrompts::default(),
             ongoing_prompts: OngoingPromptManager::new(),
             mcp_client: Arc::new(RwLock::new(mcp_client)),
+            summary_locks: Mutex::new(HashMap::new()),
         }
     }
 
+    pub fn is_enabled(&self) -> bool {
+        self.provider.is_some() && self.models.is_some()
+    }
+
+    /// The model provider, [`ProviderError::Disabled`] when none is configured
+    pub fn provider(&self) -> Result<&Arc<dyn LlmProvider>, ProviderError> {
+        self.provider.as_ref().ok_or(ProviderError::Disabled)
+    }
+
+    /// The configured models, [`ProviderError::Disabled`] when there are none
+    pub fn models(&self) -> Result<&ModelRegistry, ProviderError> {
+        self.models.as_ref().ok_or(ProviderError::Disabled)
+    }
+
     pub async fn create_workshop_summary(
         topic: &Topic,
         state: &AppState,
     ) -> Result<String, HttpError> {
-        let posts =
-            Post::find_by_topic_id(&topic.discourse_id, topic.topic_id, 1, Some(512), state).await;
-
-        let (posts, _) = posts.unwrap_or_default();
-        let posts: Vec<WorkshopPost> = posts.into_iter().map(|x| {
-            x.into()
-        }).collect();
-
-        let messages = vec![
-            state.workshop.prompts.summerize.clone(),
-            ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
-                content: serde_json::to_string(&json!({
-                    "topic_info": topic,
-                    "posts": posts,
-                }))
-                .unwrap()
-                .into(),
-                name: None,
-            }),
-        ];
+        let messages = summary::summary_messages(topic, state).await?;
 
-        // Apply token limits to prevent excessive costs
-        let truncated_messages = truncate_messages_to_token_limit(messages, &None);
+        // Apply token limits to fit the model's context window
+        let model = state.workshop.models()?.summary_model();
+        let truncated_messages = summary::fit_context(
+            messages,
+            model,
+            SUMMARY_MAX_OUTPUT_TOKENS,
+            &format!("topic {}", topic.topic_id),
+        )?;
 
         let request = CreateChatCompletionRequest {
-            model: SUMMARY_MODEL.to_string(),
+            model: model.id.clone(),
             messages: truncated_messages,
-            max_completion_tokens: Some(2000), // Limit output to 2k tokens for summaries
+            max_completion_tokens: Some(SUMMARY_MAX_OUTPUT_TOKENS),
             ..Default::default()
         };
 
-        let chat_completion = state.workshop.client.chat().create(request).await?;
+        let chat_completion = state.workshop.provider()?.chat(request).await?;
 
         let response = chat_completion.choices.first().unwrap().message.clone();
 
@@ -158,17 +170,19 @@ impl WorkshopService {
         message_id: Uuid,
         state: &AppState,
     ) -> Result<(OngoingPrompt, WorkshopMessage), Box<dyn std::error::Error + Send + Sync>> {
-        Self::process_next_message_with_model(chat_id, message_id, None, state).await
+        let model = state.workshop.models()?.default_model().clone();
+        Self::process_next_message_with_model(chat_id, message_id, model, state).await
     }
 
     /// Process next message with specified model
     ///
     /// Fetches the entire chat history from chat_id upwards and processes it with the LLM
     /// Returns the next message from the LLM using request coalescing
+    /// The model is offered the MCP tools if it supports tool calling
     pub async fn process_next_message_with_model(
         chat_id: Uuid,
         message_id: Uuid,
-        model: Option<String>,
+        model: ModelConfig,
         state: &AppState,
     ) -> Result<(OngoingPrompt, WorkshopMessage), Box<dyn std::error::Error + Send + Sync>> {
         tracing::info!(
@@ -222,28 +236,35 @@ impl WorkshopService {
         messages.insert(0, system_message);
 
         // Get available MCP tools for the chat completion
-        tracing::info!("🔧 Getting MCP tools...");
-        let mut mcp_client_lock_result = state.workshop.mcp_client.write().await;
-        tracing::info!("🔓 MCP client lock acquired successfully");
+        let tools = if model.tools {
+            tracing::info!("🔧 Getting MCP tools...");
+            let mut mcp_client_lock_result = state.workshop.mcp_client.write().await;
+            tracing::info!("🔓 MCP client lock acquired successfully");
 
-        let tools = match mcp_client_lock_result.get_openai_tools().await {
-            Ok(mut tools) if !tools.is_empty() => {
-                tracing::info!("✅ Got {} MCP tools", tools.len());
+            let available_tools = match mcp_client_lock_result.get_openai_tools().await {
+                Ok(mut tools) if !tools.is_empty() => {
+                    tracing::info!("✅ Got {} MCP tools", tools.len());
 
-                Some(tools)
-            }
-            Ok(_) => {
-                tracing::info!("ℹ️ No MCP tools available");
-                None
-            }
-            Err(e) => {
-                tracing::warn!("⚠️ Failed to get MCP tools: {}", e);
-                None
-            }
-        };
+                    Some(tools)
+                }
+                Ok(_) => {
+                    tracing::info!("ℹ️ No MCP tools available");
+                    None
+                }
+                Err(e) => {
+                    tracing::warn!("⚠️ Failed to get MCP tools: {}", e);
+                    None
+                }
+            };
+
+            drop(mcp_client_lock_result); // Explicitly drop the lock
+            tracing::info!("🔒 MCP client lock released");
 
-        drop(mcp_client_lock_result); // Explicitly drop the lock
-        tracing::info!("🔒 MCP client lock released");
+            available_tools
+        } else {
+            tracing::info!("ℹ️ {} does not support tool calling", model.id);
+            None
+        };
 
         // Use chat_id + message_id as the coalescing key
         let key = format!("{}-{}", chat_id, message_id);
@@ -429,52 +450,49 @@ impl WorkshopService {
 
     /// Create workshop summary using streaming with request coalescing
     /// Returns an OngoingPrompt that can be used for streaming the summary generation
+    /// Chunks of long topics are summarized before, only the reduce step is streamed
     pub async fn create_workshop_summary_streaming(
         topic: &Topic,
         state: &AppState,
     ) -> Result<OngoingPrompt, Box<dyn std::error::Error + Send + Sync>> {
-        let posts =
-            Post::find_by_topic_id(&topic.discourse_id, topic.topic_id, 1, Some(512), state).await;
-        let (posts, _) = posts.unwrap_or_default();
-        let posts: Vec<WorkshopPost> = posts.into_iter().map(|x| {
-            x.into()
-        }).collect();
-
-        let messages = vec![
-            state.workshop.prompts.summerize.clone(),
-            ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
-                content: serde_json::to_string(&json!({
-                    "topic_info": topic,
-                    "posts": posts,
-                }))
-                .unwrap()
-                .into(),
-                name: None,
-            }),
-        ];
-
-        // Apply token limits to prevent excessive costs
-        let truncated_messages = truncate_messages_to_token_limit(messages, &None);
-
         // Use topic_id as the coalescing key for summaries
         let key = Self::summary_key(&topic.discourse_id, topic.topic_id);
 
+        let lock = state.workshop.summary_lock(&key).await;
+        let _guard = lock.lock().await;
+        if let Some(ongoing_prompt) = state.workshop.ongoing_prompts.get(&key).await {
+            return Ok(ongoing_prompt);
+        }
+
+        let messages = summary::summary_messages(topic, state).await?;
+
+        // Apply token limits to fit the model's context window
+        let model = state.workshop.models()?.summary_model().clone();
+        let truncated_messages = summary::fit_context(
+            messages,
+            &model,
+            SUMMARY_MAX_OUTPUT_TOKENS,
+            &format!("topic {}", topic.topic_id),
+        )?;
+
         // Get or create the ongoing prompt (no tools needed for summaries)
         let ongoing_prompt = state
             .workshop
             .ongoing_prompts
-            .get_or_create(
-                key,
-                state,
-                truncated_messages,
-                None,
-                Some(SUMMARY_MODEL.to_string()),
-            )
+            .get_or_create(key, state, truncated_messages, None, model)
             .await?;
 
         Ok(ongoing_prompt)
     }
 
+    async fn summary_lock(&self, key: &str) -> Arc<Mutex<()>> {
+        let mut locks = self.summary_locks.lock().await;
+        // Locks only referenced by the map are neither held nor waited on
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+
+        locks.entry(key.to_string()).or_default().clone()
+    }
+
     fn summary_key(discourse_id: &str, topic_id: i32) -> String {
         format!("summary-{}-{}", discourse_id, topic_id)
     }
@@ -582,18 +600,24 @@ impl WorkshopService {
             }),
         ];
 
-        // Apply token limits to prevent excessive costs
-        let truncated_summary_messages = truncate_messages_to_token_limit(summary_messages, &None);
+        // Apply token limits to fit the model's context window
+        let model = state.workshop.models()?.shortsum_model();
+        let truncated_summary_messages = truncate_messages_to_token_limit(
+            summary_messages,
+            &None,
+            model,
+            SHORTSUM_MAX_OUTPUT_TOKENS,
+        );
 
-        // Generate the summary using async-openai
+        // Generate the summary through the model provider
         let request = CreateChatCompletionRequest {
-            model: SHORTSUM_MODEL.to_string(),
+            model: model.id.clone(),
             messages: truncated_summary_messages,
-            max_completion_tokens: Some(40),
+            max_completion_tokens: Some(SHORTSUM_MAX_OUTPUT_TOKENS),
             ..Default::default()
         };
 
-        let chat_completion = state.workshop.client.chat().create(request).await?;
+        let chat_completion = state.workshop.provider()?.chat(request).await?;
 
         let summary = chat_completion
             .choices
@@ -14,6 +14,7 @@ use tracing;
 use serde::{Serialize, Deserialize};
 use serde_json::Value;
 
+use crate::modules::workshop::registry::ModelConfig;
 use crate::state::AppState;
 
 /// Helper function to normalize tool arguments by converting string numbers to actual numbers
@@ -74,105 +75,23 @@ fn normalize_tool_arguments(tool_name: &str, args: Value) -> Value {
 }
 
 pub const SUMMARY_PROMPT: &str = include_str!("./summary.md");
-pub const SUMMARY_MODEL: &str = "mistralai/ministral-3b";
-
 pub const WORKSHOP_PROMPT: &str = include_str!("./workshop.md");
-pub const WORKSHOP_MODEL: &str = "google/gemini-2.5-flash-preview-05-20";
-// pub const WORKSHOP_MODEL: &str = "google/gemini-2.0-flash-001";
-// pub const WORKSHOP_MODEL: &str = "google/gemini-2.5-pro-preview";
-
-// TODO: for consideration when we implementing reasoning decoding
-// pub const WORKSHOP_MODEL: &str = "google/gemini-2.5-flash-preview-05-20:thinking";
-
 pub const SHORTSUM_PROMPT: &str = include_str!("./shortsum.md");
-pub const SHORTSUM_MODEL: &str = "mistralai/mistral-7b-instruct:free";
-
-/// Constants for token limits
-const MAX_INPUT_TOKENS: usize = 180000; // Limit input to 32k tokens to prevent excessive costs
-const TOKENS_PER_MESSAGE_OVERHEAD: usize = 4; // Overhead tokens per message (role, formatting, etc.)
-const TOKENS_PER_NAME: usize = 1; // Additional tokens if name is present
-
-/// Simple token estimation function
-/// This is a rough estimate - for exact counts you'd need the actual tokenizer
-/// But this is good enough for preventing runaway costs
-fn estimate_tokens_in_text(text: &str) -> usize {
-    // Rough estimate: ~4 characters per token for English text
-    // This errs on the side of overestimating to be safe
-    (text.len() as f64 / 3.5).ceil() as usize
-}
+pub const CHUNK_SUMMARY_PROMPT: &str = include_str!("./chunk_summary.md");
 
-fn estimate_tokens_in_message(message: &ChatCompletionRequestMessage) -> usize {
-    let mut token_count = TOKENS_PER_MESSAGE_OVERHEAD;
-    
-    match message {
-        ChatCompletionRequestMessage::User(user_msg) => {
-            let content = match &user_msg.content {
-                async_openai::types::ChatCompletionRequestUserMessageContent::Text(text) => text,
-                async_openai::types::ChatCompletionRequestUserMessageContent::Array(_) => "[Complex content]",
-            };
-            token_count += estimate_tokens_in_text(content);
-            if user_msg.name.is_some() {
-                token_count += TOKENS_PER_NAME;
-            }
-        },
-        ChatCompletionRequestMessage::Assistant(assistant_msg) => {
-            if let Some(content) = &assistant_msg.content {
-                let text = match content {
-                    async_openai::types::ChatCompletionRequestAssistantMessageContent::Text(text) => text,
-                    async_openai::types::ChatCompletionRequestAssistantMessageContent::Array(_) => "[Complex content]",
-                };
-                token_count += estimate_tokens_in_text(text);
-            }
-            if assistant_msg.name.is_some() {
-                token_count += TOKENS_PER_NAME;
-            }
-            // Add tokens for tool calls if present
-            if let Some(tool_calls) = &assistant_msg.tool_calls {
-                for tool_call in tool_calls {
-                    token_count += estimate_tokens_in_text(&tool_call.function.name);
-                    token_count += estimate_tokens_in_text(&tool_call.function.arguments);
-                    token_count += 4; // Overhead for tool call structure
-                }
-            }
-        },
-        ChatCompletionRequestMessage::System(system_msg) => {
-            let content = match &system_msg.content {
-                async_openai::types::ChatCompletionRequestSystemMessageContent::Text(text) => text,
-                async_openai::types::ChatCompletionRequestSystemMessageContent::Array(_) => "[Complex content]",
-            };
-            token_count += estimate_tokens_in_text(content);
-            if system_msg.name.is_some() {
-                token_count += TOKENS_PER_NAME;
-            }
-        },
-        ChatCompletionRequestMessage::Tool(tool_msg) => {
-            let content_text = match &tool_msg.content {
-                async_openai::types::ChatCompletionRequestToolMessageContent::Text(text) => text,
-                async_openai::types::ChatCompletionRequestToolMessageContent::Array(_) => "[Complex content]",
-            };
-            token_count += estimate_tokens_in_text(content_text);
-            token_count += estimate_tokens_in_text(&tool_msg.tool_call_id);
-        },
-        _ => {
-            // For any other message types, add a conservative estimate
-            token_count += 50;
-        }
-    }
-    
-    token_count
-}
+/// Limit output tokens to 4k to prevent excessive generation costs
+pub const WORKSHOP_MAX_OUTPUT_TOKENS: u32 = 4000;
 
-pub fn truncate_messages_to_token_limit(mut messages: Vec<ChatCompletionRequestMessage>, tools: &Option<Vec<ChatCompletionTool>>) -> Vec<ChatCompletionRequestMessage> {
-    // First, estimate tokens for tools if present
-    let mut tool_tokens = 0;
-    if let Some(tools_vec) = tools {
-        for _tool in tools_vec {
-            // Rough estimate for tool definitions
-            tool_tokens += 100; // Conservative estimate per tool
-        }
-    }
+/// Keep the system message and the most recent messages that fit in the context window of
+/// `model` once `max_output` tokens are reserved, counted with the model's tokenizer
+pub fn truncate_messages_to_token_limit(mut messages: Vec<ChatCompletionRequestMessage>, tools: &Option<Vec<ChatCompletionTool>>, model: &ModelConfig, max_output: u32) -> Vec<ChatCompletionRequestMessage> {
+    let counter = model.token_counter();
+    let max_input_tokens = model.input_limit(max_output);
+
+    // Tool definitions are sent with every request, their schemas count towards the limit
+    let tool_tokens = tools.as_deref().map(|tools| counter.count_tools(tools)).unwrap_or_default();
     
-    let mut total_tokens = tool_tokens;
+    let mut total_tokens = tool_tokens + counter.count_reply_priming();
     let mut kept_messages = Vec::new();
     let mut truncated_count = 0;
     
@@ -180,19 +99,20 @@ pub fn truncate_messages_to_token_limit(mut messages: Vec<ChatCompletionRequestM
     if let Some(first_message) = messages.first() {
         if matches!(first_message, ChatCompletionRequestMessage::System(_)) {
             let system_message = messages.remove(0);
-            total_tokens += estimate_tokens_in_message(&system_message);
+            total_tokens += counter.count_message(&system_message);
             kept_messages.push(system_message);
         }
     }
+    let has_system_message = !kept_messages.is_empty();
     
     // Keep messages from the end (most recent) while staying under limit
     // Work backwards to keep the most recent conversation
     for message in messages.into_iter().rev() {
-        let message_tokens = estimate_tokens_in_message(&message);
+        let message_tokens = counter.count_message(&message);
         
-        if total_tokens + message_tokens <= MAX_INPUT_TOKENS {
+        if truncated_count == 0 && total_tokens + message_tokens <= max_input_tokens {
             total_tokens += message_tokens;
-            kept_messages.insert(if kept_messages.is_empty() { 0 } else { 1 }, message); // Insert after system message if present
+            kept_messages.insert(usize::from(has_system_message), message); // Insert after system message if present
         } else {
             truncated_count += 1;
         }
@@ -200,13 +120,15 @@ pub fn truncate_messages_to_token_limit(mut messages: Vec<ChatCompletionRequestM
     
     if truncated_count > 0 {
         tracing::warn!(
-            "🔪 Truncated {} message(s) to stay under {}-token limit. Current estimate: {} tokens",
+            "🔪 Truncated {} message(s) to stay under {}-token limit of {}. Prompt: {} tokens ({} for tools)",
             truncated_count,
-            MAX_INPUT_TOKENS,
-            total_tokens
+            max_input_tokens,
+            model.id,
+            total_tokens,
+            tool_tokens
         );
     } else {
-        tracing::info!("✅ Messages within token limit. Estimated tokens: {}", total_tokens);
+        tracing::info!("✅ Messages within token limit of {}. Prompt: {} tokens ({} for tools)", model.id, total_tokens, tool_tokens);
     }
     
     kept_messages
@@ -269,14 +191,14 @@ pub struct OngoingPrompt {
 }
 
 impl OngoingPrompt {
-    pub async fn new(state: &AppState, messages: Vec<ChatCompletionRequestMessage>, tools: Option<Vec<ChatCompletionTool>>, model: Option<String>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+    pub async fn new(state: &AppState, messages: Vec<ChatCompletionRequestMessage>, tools: Option<Vec<ChatCompletionTool>>, model: ModelConfig) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         tracing::info!("🚀 Creating new OngoingPrompt with {} messages and {} tools", 
             messages.len(), tools.as_ref().map(|t| t.len()).unwrap_or(0));
         
-        let model = model.unwrap_or_else(|| WORKSHOP_MODEL.to_string());
+        let provider = state.workshop.provider()?.clone();
         
         tracing::info!("📡 API Request Details:");
-        tracing::info!("  Model: {}", model);
+        tracing::info!("  Model: {}", model.id);
         tracing::info!("  Messages count: {}", messages.len());
         tracing::info!("  Tools count: {}", tools.as_ref().map(|t| t.len()).unwrap_or(0));
         tracing::info!("  Stream: true");
@@ -340,7 +262,7 @@ impl OngoingPrompt {
         let conversation_history = Arc::new(RwLock::new(messages.clone()));
         let tools_arc = Arc::new(RwLock::new(tools.clone()));
         let usage_data = Arc::new(RwLock::new(None));
-        let model_used = Arc::new(RwLock::new(Some(model.clone())));
+        let model_used = Arc::new(RwLock::new(Some(model.id.clone())));
         
         let ongoing_state = OngoingPromptState {
             buffer: buffer.clone(),
@@ -385,25 +307,26 @@ impl OngoingPrompt {
                 };
 
                 // Apply token limits to prevent excessive costs
-                let truncated_messages = truncate_messages_to_token_limit(current_messages, &current_tools);
+                let truncated_messages = truncate_messages_to_token_limit(
+                    current_messages,
+                    &current_tools,
+                    &model,
+                    WORKSHOP_MAX_OUTPUT_TOKENS,
+                );
 
                 // Create request for this iteration
                 let request = CreateChatCompletionRequest {
-                    model: model.clone(),
+                    model: model.id.clone(),
                     messages: truncated_messages,
                     tools: current_tools,
                     tool_choice: None,
                     stream: Some(true),
-                    max_completion_tokens: Some(4000), // Limit output tokens to 4k to prevent excessive generation costs
+                    max_completion_tokens: Some(WORKSHOP_MAX_OUTPUT_TOKENS),
                     ..Default::default()
                 };
 
                 tracing::info!("📞 Making API call for conversation turn...");
-                let mut stream = match state_clone.workshop.client
-                    .chat()
-                    .create_stream(request)
-                    .await
-                {
+                let mut stream = match provider.chat_stream(request).await {
                     Ok(stream) => stream,
                     Err(e) => {
                         tracing::error!("❌ Failed to create chat completion stream: {:?}", e);
@@ -941,7 +864,7 @@ impl OngoingPromptManager {
         state: &AppState,
         messages: Vec<ChatCompletionRequestMessage>,
         tools: Option<Vec<ChatCompletionTool>>,
-        model: Option<String>,
+        model: ModelConfig,
     ) -> Result<OngoingPrompt, Box<dyn std::error::Error + Send + Sync>> {
         // First check if we already have this prompt
         {
@@ -992,3 +915,52 @@ impl OngoingPromptManager {
         prompts.insert(key, prompt);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::{ChatCompletionRequestSystemMessage, ChatCompletionRequestUserMessage};
+
+    use super::*;
+
+    #[test]
+    fn test_truncate_messages_to_token_limit() {
+        let model: ModelConfig = serde_json::from_value(serde_json::json!({
+            "id": "openai/gpt-4o",
+            "name": "GPT-4o",
+            "provider": "OpenAI",
+            "context_window": 1100,
+        }))
+        .unwrap();
+        let user = |text: String| {
+            ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                content: text.into(),
+                name: None,
+            })
+        };
+
+        let mut messages = vec![ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+            content: "You are helpful".to_string().into(),
+            name: None,
+        })];
+        // " word" is a single token, so each message is 3 + 1 + 200 tokens
+        messages.extend((0..5).map(|i| user(format!("{i}{}", " word".repeat(199)))));
+
+        let kept = truncate_messages_to_token_limit(messages.clone(), &None, &model, 100);
+        assert_eq!(kept.len(), 5);
+        assert!(matches!(kept[0], ChatCompletionRequestMessage::System(_)));
+        assert_eq!(kept[1..], messages[2..]);
+
+        let tools = Some(vec![ChatCompletionTool {
+            r#type: ChatCompletionToolType::Function,
+            function: async_openai::types::FunctionObject {
+                name: "search_forum".to_string(),
+                description: Some("Search the forum".repeat(100)),
+                parameters: None,
+                strict: None,
+            },
+        }]);
+        let kept = truncate_messages_to_token_limit(messages.clone(), &tools, &model, 100);
+        assert_eq!(kept.len(), 4);
+        assert_eq!(kept[1..], messages[3..]);
+    }
+}
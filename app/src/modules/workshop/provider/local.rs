@@ -0,0 +1,102 @@
+use async_openai::types::{CreateChatCompletionRequest, CreateChatCompletionResponse};
+use async_trait::async_trait;
+
+use super::{ChatStream, LlmProvider, OpenAIProvider, ProviderError};
+
+/// An Ollama or llama.cpp server, both serve the OpenAI routes under `/v1`
+///
+/// They ignore the API key and know nothing of OpenRouter model ids, so requests are sent
+/// to the configured model instead. Without one the requested model is kept, llama.cpp
+/// answers with whatever model it loaded.
+pub struct LocalProvider {
+    inner: OpenAIProvider,
+    model: Option<String>,
+}
+
+impl LocalProvider {
+    pub fn new(url: &str, model: Option<String>) -> Self {
+        Self {
+            inner: OpenAIProvider::named("local", "", url),
+            model,
+        }
+    }
+
+    /// Older servers only read the deprecated `max_tokens` and fail on `stream_options`
+    #[allow(deprecated)]
+    fn prepare(&self, mut request: CreateChatCompletionRequest) -> CreateChatCompletionRequest {
+        if let Some(model) = &self.model {
+            request.model = model.clone();
+        }
+        if let Some(max_tokens) = request.max_completion_tokens.take() {
+            request.max_tokens = Some(max_tokens);
+        }
+        request.stream_options = None;
+        request
+    }
+}
+
+#[async_trait]
+impl LlmProvider for LocalProvider {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse, ProviderError> {
+        self.inner.chat(self.prepare(request)).await
+    }
+
+    async fn chat_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<ChatStream, ProviderError> {
+        self.inner.chat_stream(self.prepare(request)).await
+    }
+
+    /// Local embedding models have a fixed size, `dimensions` is not sent
+    async fn embed(
+        &self,
+        model: &str,
+        inputs: &[String],
+        _dimensions: usize,
+    ) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed_with(model, inputs, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::ChatCompletionStreamOptions;
+
+    use super::*;
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_prepare() {
+        let request = CreateChatCompletionRequest {
+            model: "google/gemini-2.5-flash-preview-05-20".to_string(),
+            max_completion_tokens: Some(4000),
+            stream: Some(true),
+            stream_options: Some(ChatCompletionStreamOptions {
+                include_usage: true,
+            }),
+            ..Default::default()
+        };
+
+        let local = LocalProvider::new("http://localhost:11434/v1", Some("qwen3:8b".to_string()));
+        let prepared = local.prepare(request.clone());
+        assert_eq!(prepared.model, "qwen3:8b");
+        assert_eq!(prepared.max_tokens, Some(4000));
+        assert_eq!(prepared.max_completion_tokens, None);
+        assert_eq!(prepared.stream_options, None);
+        assert_eq!(prepared.stream, Some(true));
+
+        let unnamed = LocalProvider::new("http://localhost:8080/v1", None);
+        assert_eq!(
+            unnamed.prepare(request).model,
+            "google/gemini-2.5-flash-preview-05-20"
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use async_openai::types::{
+    ChatChoice, ChatChoiceStream, ChatCompletionRequestMessage,
+    ChatCompletionRequestUserMessageContent, ChatCompletionResponseMessage,
+    ChatCompletionStreamResponseDelta, CompletionUsage, CreateChatCompletionRequest,
+    CreateChatCompletionResponse, CreateChatCompletionStreamResponse, FinishReason, Role,
+};
+use async_trait::async_trait;
+use futures::{StreamExt, stream};
+
+use super::{ChatStream, LlmProvider, ProviderError};
+use crate::modules::search::embeddings::hashed_embedding;
+
+const MOCK_ID: &str = "chatcmpl-mock";
+/// Characters of the last user message echoed in the reply
+const ECHO_LENGTH: usize = 80;
+
+/// Answers every request with an echo of the last user message, no model involved
+///
+/// Responses only depend on the request, so tests and local development behave the same
+/// on every run. Embeddings hash words like the `stub` embedder.
+pub struct MockProvider;
+
+impl MockProvider {
+    fn reply(request: &CreateChatCompletionRequest) -> String {
+        let last_user_message = request
+            .messages
+            .iter()
+            .rev()
+            .find_map(|message| match message {
+                ChatCompletionRequestMessage::User(user_msg) => match &user_msg.content {
+                    ChatCompletionRequestUserMessageContent::Text(text) => Some(text.as_str()),
+                    ChatCompletionRequestUserMessageContent::Array(_) => Some("[Complex content]"),
+                },
+                _ => None,
+            });
+
+        match last_user_message {
+            Some(text) => format!(
+                "Mock reply to \"{}\"",
+                text.chars().take(ECHO_LENGTH).collect::<String>()
+            ),
+            None => "Mock reply".to_string(),
+        }
+    }
+
+    /// Roughly 4 characters per token of the serialized messages, 1 token per word of the reply
+    fn usage(request: &CreateChatCompletionRequest, reply: &str) -> CompletionUsage {
+        let prompt_tokens = serde_json::to_string(&request.messages)
+            .map(|json| json.len().div_ceil(4) as u32)
+            .unwrap_or_default();
+        let completion_tokens = reply.split_whitespace().count() as u32;
+
+        CompletionUsage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            prompt_tokens_details: None,
+            completion_tokens_details: None,
+        }
+    }
+
+    fn chunk(
+        model: &str,
+        content: Option<String>,
+        finish_reason: Option<FinishReason>,
+        usage: Option<CompletionUsage>,
+    ) -> CreateChatCompletionStreamResponse {
+        #[allow(deprecated)]
+        let delta = ChatCompletionStreamResponseDelta {
+            content,
+            function_call: None,
+            tool_calls: None,
+            role: Some(Role::Assistant),
+            refusal: None,
+        };
+
+        CreateChatCompletionStreamResponse {
+            id: MOCK_ID.to_string(),
+            choices: vec![ChatChoiceStream {
+                index: 0,
+                delta,
+                finish_reason,
+                logprobs: None,
+            }],
+            created: 0,
+            model: model.to_string(),
+            service_tier: None,
+            system_fingerprint: None,
+            object: "chat.completion.chunk".to_string(),
+            usage,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse, ProviderError> {
+        let reply = Self::reply(&request);
+        let usage = Self::usage(&request, &reply);
+
+        #[allow(deprecated)]
+        let message = ChatCompletionResponseMessage {
+            content: Some(reply),
+            refusal: None,
+            tool_calls: None,
+            role: Role::Assistant,
+            function_call: None,
+            audio: None,
+        };
+
+        Ok(CreateChatCompletionResponse {
+            id: MOCK_ID.to_string(),
+            choices: vec![ChatChoice {
+                index: 0,
+                message,
+                finish_reason: Some(FinishReason::Stop),
+                logprobs: None,
+            }],
+            created: 0,
+            model: request.model,
+            service_tier: None,
+            system_fingerprint: None,
+            object: "chat.completion".to_string(),
+            usage: Some(usage),
+        })
+    }
+
+    /// The reply word by word, the last chunk carries the finish reason and usage
+    async fn chat_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<ChatStream, ProviderError> {
+        let reply = Self::reply(&request);
+        let usage = Self::usage(&request, &reply);
+
+        let mut chunks: Vec<_> = reply
+            .split_inclusive(' ')
+            .map(|word| Self::chunk(&request.model, Some(word.to_string()), None, None))
+            .collect();
+        chunks.push(Self::chunk(
+            &request.model,
+            None,
+            Some(FinishReason::Stop),
+            Some(usage),
+        ));
+
+        Ok(stream::iter(chunks.into_iter().map(Ok)).boxed())
+    }
+
+    async fn embed(
+        &self,
+        _model: &str,
+        inputs: &[String],
+        dimensions: usize,
+    ) -> Result<Vec<Vec<f32>>, ProviderError> {
+        Ok(inputs
+            .iter()
+            .map(|input| hashed_embedding(input, dimensions))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::{
+        ChatCompletionRequestSystemMessage, ChatCompletionRequestUserMessage,
+    };
+
+    use super::*;
+
+    #[async_std::test]
+    async fn test_mock_provider() {
+        let request = CreateChatCompletionRequest {
+            model: "mock-model".to_string(),
+            messages: vec![
+                ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+                    content: "You are helpful".to_string().into(),
+                    name: None,
+                }),
+                ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                    content: "What is EOF?".to_string().into(),
+                    name: None,
+                }),
+            ],
+            ..Default::default()
+        };
+
+        let response = MockProvider.chat(request.clone()).await.unwrap();
+        let content = response.choices[0].message.content.clone().unwrap();
+        assert_eq!(content, "Mock reply to \"What is EOF?\"");
+
+        let chunks: Vec<_> = MockProvider
+            .chat_stream(request)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        let chunks: Vec<_> = chunks.into_iter().map(Result::unwrap).collect();
+        let streamed: String = chunks
+            .iter()
+            .filter_map(|chunk| chunk.choices[0].delta.content.clone())
+            .collect();
+        assert_eq!(streamed, content);
+
+        let last = chunks.last().unwrap();
+        assert_eq!(last.choices[0].finish_reason, Some(FinishReason::Stop));
+        assert_eq!(last.usage, response.usage);
+
+        let inputs = vec!["Inclusion lists".to_string()];
+        let embeddings = MockProvider.embed("any", &inputs, 16).await.unwrap();
+        assert_eq!(embeddings, vec![hashed_embedding("Inclusion lists", 16)]);
+    }
+}
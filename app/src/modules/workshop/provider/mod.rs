@@ -0,0 +1,183 @@
+use std::{pin::Pin, sync::Arc};
+
+use async_openai::{
+    error::OpenAIError,
+    types::{
+        CreateChatCompletionRequest, CreateChatCompletionResponse,
+        CreateChatCompletionStreamResponse,
+    },
+};
+use async_trait::async_trait;
+use figment::{Figment, providers::Env};
+use futures::Stream;
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::{info, warn};
+
+pub mod local;
+pub mod mock;
+pub mod openai;
+
+pub use local::LocalProvider;
+pub use mock::MockProvider;
+pub use openai::OpenAIProvider;
+
+const DEFAULT_BASE_URL: &str = "https://openrouter.ai/api/v1";
+/// Ollama, llama.cpp's `llama-server` listens on 8080 with the same `/v1` routes
+const DEFAULT_LOCAL_URL: &str = "http://localhost:11434/v1";
+
+#[derive(Debug, Error)]
+pub enum ProviderError {
+    #[error(transparent)]
+    Request(#[from] OpenAIError),
+    #[error("expected {expected} embeddings, got {got}")]
+    Mismatch { expected: usize, got: usize },
+    #[error("workshop features are disabled, no model provider is configured")]
+    Disabled,
+}
+
+/// Streamed chat completion chunks, in the OpenAI wire format whatever the provider
+pub type ChatStream =
+    Pin<Box<dyn Stream<Item = Result<CreateChatCompletionStreamResponse, ProviderError>> + Send>>;
+
+/// A language model backend of the workshop, requests and responses use the OpenAI types
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Shown in logs
+    fn name(&self) -> &str;
+
+    async fn chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse, ProviderError>;
+
+    async fn chat_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<ChatStream, ProviderError>;
+
+    /// One embedding per input, in order
+    async fn embed(
+        &self,
+        model: &str,
+        inputs: &[String],
+        dimensions: usize,
+    ) -> Result<Vec<Vec<f32>>, ProviderError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    /// Any OpenAI-compatible API, OpenRouter by default
+    Openai,
+    /// An Ollama or llama.cpp server on the local network
+    Local,
+    /// Canned responses, for tests and development without a model
+    Mock,
+}
+
+/// Read from `WORKSHOP_*` variables
+///
+/// Without `WORKSHOP_PROVIDER` the OpenAI-compatible API is used when
+/// `WORKSHOP_INTELLIGENCE_KEY` is set, otherwise workshop features are disabled.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ProviderConfig {
+    pub provider: Option<ProviderKind>,
+    pub intelligence_key: Option<String>,
+    pub intelligence_base_url: Option<String>,
+    pub local_url: Option<String>,
+    /// Model every request is sent to, local servers usually serve a single one
+    pub local_model: Option<String>,
+}
+
+pub fn init_provider(figment: Figment) -> Option<Arc<dyn LlmProvider>> {
+    let config = match figment
+        .merge(Env::prefixed("WORKSHOP_"))
+        .extract::<ProviderConfig>()
+    {
+        Ok(config) => config,
+        Err(e) => {
+            warn!(
+                "Invalid workshop provider config, workshop is disabled: {}",
+                e
+            );
+            return None;
+        }
+    };
+
+    let provider = from_config(config)?;
+    info!("Workshop models are served by {}", provider.name());
+    Some(provider)
+}
+
+fn from_config(config: ProviderConfig) -> Option<Arc<dyn LlmProvider>> {
+    let kind = config.provider.or_else(|| {
+        config
+            .intelligence_key
+            .as_ref()
+            .map(|_| ProviderKind::Openai)
+    });
+
+    Some(match kind {
+        Some(ProviderKind::Openai) => {
+            let Some(api_key) = config.intelligence_key.filter(|key| !key.is_empty()) else {
+                warn!("WORKSHOP_INTELLIGENCE_KEY is not set, workshop is disabled");
+                return None;
+            };
+            let base_url = config
+                .intelligence_base_url
+                .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+            Arc::new(OpenAIProvider::new(&api_key, &base_url))
+        }
+        Some(ProviderKind::Local) => {
+            let url = config
+                .local_url
+                .unwrap_or_else(|| DEFAULT_LOCAL_URL.to_string());
+            Arc::new(LocalProvider::new(&url, config.local_model))
+        }
+        Some(ProviderKind::Mock) => Arc::new(MockProvider),
+        None => {
+            info!("No workshop provider configured, workshop is disabled");
+            return None;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(config: ProviderConfig) -> Option<String> {
+        from_config(config).map(|provider| provider.name().to_string())
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert_eq!(name(ProviderConfig::default()), None);
+
+        let openai = ProviderConfig {
+            intelligence_key: Some("sk-test".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(name(openai.clone()).as_deref(), Some("openai"));
+
+        let mock = ProviderConfig {
+            provider: Some(ProviderKind::Mock),
+            ..openai.clone()
+        };
+        assert_eq!(name(mock).as_deref(), Some("mock"));
+
+        let local = ProviderConfig {
+            provider: Some(ProviderKind::Local),
+            ..Default::default()
+        };
+        assert_eq!(name(local).as_deref(), Some("local"));
+
+        let without_key = ProviderConfig {
+            provider: Some(ProviderKind::Openai),
+            intelligence_key: Some(String::new()),
+            ..Default::default()
+        };
+        assert_eq!(name(without_key), None);
+    }
+}
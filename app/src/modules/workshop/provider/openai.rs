@@ -0,0 +1,102 @@
+use async_openai::{
+    Client,
+    config::OpenAIConfig,
+    types::{
+        CreateChatCompletionRequest, CreateChatCompletionResponse, CreateEmbeddingRequest,
+        EmbeddingInput,
+    },
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+
+use super::{ChatStream, LlmProvider, ProviderError};
+
+/// Any API speaking the OpenAI protocol, e.g. OpenRouter
+pub struct OpenAIProvider {
+    client: Client<OpenAIConfig>,
+    name: &'static str,
+}
+
+impl OpenAIProvider {
+    pub fn new(api_key: &str, base_url: &str) -> Self {
+        Self::named("openai", api_key, base_url)
+    }
+
+    pub fn named(name: &'static str, api_key: &str, base_url: &str) -> Self {
+        let config = OpenAIConfig::new()
+            .with_api_key(api_key)
+            .with_api_base(base_url);
+
+        Self {
+            client: Client::with_config(config),
+            name,
+        }
+    }
+
+    /// Embeddings, `dimensions` can be left out for servers that reject it
+    pub async fn embed_with(
+        &self,
+        model: &str,
+        inputs: &[String],
+        dimensions: Option<usize>,
+    ) -> Result<Vec<Vec<f32>>, ProviderError> {
+        let response = self
+            .client
+            .embeddings()
+            .create(CreateEmbeddingRequest {
+                model: model.to_string(),
+                input: EmbeddingInput::StringArray(inputs.to_vec()),
+                dimensions: dimensions.map(|dimensions| dimensions as u32),
+                ..Default::default()
+            })
+            .await?;
+
+        let mut data = response.data;
+        if data.len() != inputs.len() {
+            return Err(ProviderError::Mismatch {
+                expected: inputs.len(),
+                got: data.len(),
+            });
+        }
+        data.sort_by_key(|embedding| embedding.index);
+
+        Ok(data
+            .into_iter()
+            .map(|embedding| embedding.embedding)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAIProvider {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    async fn chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse, ProviderError> {
+        Ok(self.client.chat().create(request).await?)
+    }
+
+    async fn chat_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<ChatStream, ProviderError> {
+        let stream = self.client.chat().create_stream(request).await?;
+
+        Ok(stream
+            .map(|chunk| chunk.map_err(ProviderError::from))
+            .boxed())
+    }
+
+    async fn embed(
+        &self,
+        model: &str,
+        inputs: &[String],
+        dimensions: usize,
+    ) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.embed_with(model, inputs, Some(dimensions)).await
+    }
+}
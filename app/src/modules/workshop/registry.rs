@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, HashSet};
+
+use figment::Figment;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::tokenizer::{TokenCounter, Tokenizer};
+
+/// Tier of every signed in user, models listing it are open to everyone
+pub const DEFAULT_TIER: &str = "free";
+
+/// `[[workshop.models]]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelConfig {
+    /// Sent to the provider, e.g. `google/gemini-2.5-flash-preview-05-20`
+    pub id: String,
+    /// Display name
+    pub name: String,
+    /// Who makes the model, e.g. `Google`
+    pub provider: String,
+    /// Tokens of input and output the model accepts, prompts are truncated to fit
+    pub context_window: usize,
+    /// USD per prompt token
+    #[serde(default)]
+    pub prompt_price: f64,
+    /// USD per completion token
+    #[serde(default)]
+    pub completion_price: f64,
+    /// Whether the model is offered the MCP tools
+    #[serde(default)]
+    pub tools: bool,
+    /// Tiers whose users may chat with the model, models without tiers are only used
+    /// for summaries
+    #[serde(default)]
+    pub tiers: Vec<String>,
+    /// BPE vocabulary prompts are counted with, guessed from the id when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<Tokenizer>,
+}
+
+impl ModelConfig {
+    /// Tokens left for the prompt once `max_output` tokens are reserved for the response
+    pub fn input_limit(&self, max_output: u32) -> usize {
+        self.context_window.saturating_sub(max_output as usize)
+    }
+
+    pub fn token_counter(&self) -> TokenCounter {
+        TokenCounter::new(&self.id, self.tokenizer)
+    }
+
+    pub fn allows(&self, tiers: &[String]) -> bool {
+        self.tiers.iter().any(|tier| tiers.contains(tier))
+    }
+}
+
+/// `[workshop.tiers.<name>]`, users match a tier by SSO provider or email domain
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TierConfig {
+    #[serde(default)]
+    pub sso_providers: Vec<String>,
+    #[serde(default)]
+    pub email_domains: Vec<String>,
+}
+
+impl TierConfig {
+    pub fn matches(&self, sso_provider: &str, email: &str) -> bool {
+        if self
+            .sso_providers
+            .iter()
+            .any(|provider| provider == sso_provider)
+        {
+            return true;
+        }
+
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return false;
+        };
+        self.email_domains
+            .iter()
+            .any(|allowed| allowed.trim_start_matches('@').eq_ignore_ascii_case(domain))
+    }
+}
+
+/// The `[workshop]` table of `config.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegistryConfig {
+    /// Model of new chat messages when the client doesn't pick one
+    pub default_model: String,
+    /// Model writing topic summaries
+    pub summary_model: String,
+    /// Model writing the short titles of chats
+    pub shortsum_model: String,
+    #[serde(default)]
+    pub tiers: BTreeMap<String, TierConfig>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+}
+
+#[derive(Debug, Error)]
+pub enum RegistryError {
+    #[error("failed to read workshop configuration: {0}")]
+    Figment(Box<figment::Error>),
+    #[error("model '{0}' is configured more than once")]
+    DuplicateModel(String),
+    #[error("model '{0}' has no context window")]
+    NoContextWindow(String),
+    #[error("model '{model}' is open to tier '{tier}', which is not configured")]
+    UnknownTier { model: String, tier: String },
+    #[error("{field} '{model}' is not one of the configured [[workshop.models]]")]
+    MissingModel { field: &'static str, model: String },
+    #[error("default_model '{0}' is not open to any tier")]
+    DefaultWithoutTiers(String),
+}
+
+/// Why a user can't chat with a model
+#[derive(Debug, Error, PartialEq)]
+pub enum ModelError {
+    #[error("unknown model '{0}'")]
+    Unknown(String),
+    #[error("model '{0}' is not available on your plan")]
+    NotAllowed(String),
+}
+
+/// The models the workshop may use, read from the `[workshop]` table of `config.toml`
+#[derive(Debug, Clone)]
+pub struct ModelRegistry {
+    default: ModelConfig,
+    summary: ModelConfig,
+    shortsum: ModelConfig,
+    tiers: BTreeMap<String, TierConfig>,
+    models: Vec<ModelConfig>,
+}
+
+/// Load the registry, `None` when the figment has no `[workshop]` table
+pub fn init_registry(figment: Figment) -> Option<Result<ModelRegistry, RegistryError>> {
+    if !figment.contains("workshop") {
+        return None;
+    }
+
+    Some(
+        figment
+            .focus("workshop")
+            .extract::<RegistryConfig>()
+            .map_err(|e| RegistryError::Figment(Box::new(e)))
+            .and_then(ModelRegistry::new),
+    )
+}
+
+impl ModelRegistry {
+    pub fn new(config: RegistryConfig) -> Result<Self, RegistryError> {
+        let mut seen = HashSet::new();
+        for model in &config.models {
+            if !seen.insert(model.id.as_str()) {
+                return Err(RegistryError::DuplicateModel(model.id.clone()));
+            }
+            if model.context_window == 0 {
+                return Err(RegistryError::NoContextWindow(model.id.clone()));
+            }
+            if let Some(tier) = model
+                .tiers
+                .iter()
+                .find(|tier| *tier != DEFAULT_TIER && !config.tiers.contains_key(*tier))
+            {
+                return Err(RegistryError::UnknownTier {
+                    model: model.id.clone(),
+                    tier: tier.clone(),
+                });
+            }
+        }
+
+        let find = |field: &'static str, id: &str| {
+            config
+                .models
+                .iter()
+                .find(|model| model.id == id)
+                .cloned()
+                .ok_or_else(|| RegistryError::MissingModel {
+                    field,
+                    model: id.to_string(),
+                })
+        };
+        let default = find("default_model", &config.default_model)?;
+        let summary = find("summary_model", &config.summary_model)?;
+        let shortsum = find("shortsum_model", &config.shortsum_model)?;
+        if default.tiers.is_empty() {
+            return Err(RegistryError::DefaultWithoutTiers(default.id));
+        }
+
+        Ok(Self {
+            default,
+            summary,
+            shortsum,
+            tiers: config.tiers,
+            models: config.models,
+        })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ModelConfig> {
+        self.models.iter().find(|model| model.id == id)
+    }
+
+    pub fn default_model(&self) -> &ModelConfig {
+        &self.default
+    }
+
+    pub fn summary_model(&self) -> &ModelConfig {
+        &self.summary
+    }
+
+    pub fn shortsum_model(&self) -> &ModelConfig {
+        &self.shortsum
+    }
+
+    /// Tiers of a signed in user, always including [`DEFAULT_TIER`]
+    pub fn tiers_for(&self, sso_provider: &str, email: &str) -> Vec<String> {
+        std::iter::once(DEFAULT_TIER.to_string())
+            .chain(
+                self.tiers
+                    .iter()
+                    .filter(|(_, tier)| tier.matches(sso_provider, email))
+                    .map(|(name, _)| name.clone()),
+            )
+            .collect()
+    }
+
+    /// Models users with these tiers may chat with, in configuration order
+    pub fn available(&self, tiers: &[String]) -> Vec<&ModelConfig> {
+        self.models
+            .iter()
+            .filter(|model| model.allows(tiers))
+            .collect()
+    }
+
+    /// The requested model, or the default one, if users with these tiers may use it
+    pub fn resolve(&self, id: Option<&str>, tiers: &[String]) -> Result<&ModelConfig, ModelError> {
+        let model = match id {
+            Some(id) => self
+                .get(id)
+                .ok_or_else(|| ModelError::Unknown(id.to_string()))?,
+            None => &self.default,
+        };
+
+        if !model.allows(tiers) {
+            return Err(ModelError::NotAllowed(model.id.clone()));
+        }
+
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::providers::{Format, Toml};
+
+    use super::*;
+
+    fn load(toml: &str) -> Result<ModelRegistry, RegistryError> {
+        init_registry(Figment::new().merge(Toml::string(toml))).unwrap()
+    }
+
+    const CONFIG: &str = r#"
+        [workshop]
+        default_model = "google/gemini-2.5-flash"
+        summary_model = "mistralai/ministral-3b"
+        shortsum_model = "mistralai/ministral-3b"
+
+        [workshop.tiers.core]
+        sso_providers = ["internal"]
+        email_domains = ["ethereum.org"]
+
+        [[workshop.models]]
+        id = "google/gemini-2.5-flash"
+        name = "Gemini 2.5 Flash"
+        provider = "Google"
+        context_window = 1048576
+        prompt_price = 0.00000015
+        completion_price = 0.0000006
+        tools = true
+        tiers = ["free", "core"]
+
+        [[workshop.models]]
+        id = "anthropic/claude-sonnet-4"
+        name = "Claude Sonnet 4"
+        provider = "Anthropic"
+        context_window = 200000
+        tools = true
+        tiers = ["core"]
+
+        [[workshop.models]]
+        id = "mistralai/ministral-3b"
+        name = "Ministral 3B"
+        provider = "Mistral AI"
+        context_window = 131072
+    "#;
+
+    #[test]
+    fn test_load_registry() {
+        assert!(init_registry(Figment::new()).is_none());
+
+        let registry = load(CONFIG).unwrap();
+        assert_eq!(registry.default_model().id, "google/gemini-2.5-flash");
+        assert_eq!(registry.summary_model().input_limit(2000), 129072);
+
+        let free = registry.tiers_for("github", "someone@gmail.com");
+        let core = registry.tiers_for("github", "someone@Ethereum.org");
+        assert_eq!(free, vec!["free"]);
+        assert_eq!(core, vec!["free", "core"]);
+
+        assert_eq!(registry.available(&free).len(), 1);
+        assert_eq!(registry.available(&core).len(), 2);
+
+        assert_eq!(
+            registry.resolve(None, &free).unwrap().id,
+            "google/gemini-2.5-flash"
+        );
+        assert_eq!(
+            registry.resolve(Some("anthropic/claude-sonnet-4"), &free),
+            Err(ModelError::NotAllowed("anthropic/claude-sonnet-4".to_string()))
+        );
+        assert!(
+            registry
+                .resolve(Some("anthropic/claude-sonnet-4"), &core)
+                .is_ok()
+        );
+        assert_eq!(
+            registry.resolve(Some("mistralai/ministral-3b"), &core),
+            Err(ModelError::NotAllowed("mistralai/ministral-3b".to_string()))
+        );
+        assert_eq!(
+            registry.resolve(Some("openai/gpt-4"), &core),
+            Err(ModelError::Unknown("openai/gpt-4".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_load_bundled_config() {
+        let config = Figment::new().merge(Toml::file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/config.toml"
+        )));
+        let registry = init_registry(config).unwrap().unwrap();
+
+        assert!(registry.default_model().tools);
+        assert!(registry.summary_model().tiers.is_empty());
+    }
+
+    #[test]
+    fn test_reject_invalid_registry() {
+        let missing = CONFIG.replace(
+            "summary_model = \"mistralai/ministral-3b\"",
+            "summary_model = \"mistralai/ministral-8b\"",
+        );
+        assert!(matches!(
+            load(&missing),
+            Err(RegistryError::MissingModel {
+                field: "summary_model",
+                ..
+            })
+        ));
+
+        let unknown_tier = CONFIG.replace("tiers = [\"core\"]", "tiers = [\"pro\"]");
+        assert!(matches!(
+            load(&unknown_tier),
+            Err(RegistryError::UnknownTier { .. })
+        ));
+
+        let duplicate = format!(
+            "{}\n[[workshop.models]]\nid = \"mistralai/ministral-3b\"\nname = \"Again\"\nprovider = \"Mistral AI\"\ncontext_window = 1",
+            CONFIG
+        );
+        assert!(matches!(
+            load(&duplicate),
+            Err(RegistryError::DuplicateModel(_))
+        ));
+
+        let no_context = CONFIG.replace("context_window = 200000", "context_window = 0");
+        assert!(matches!(
+            load(&no_context),
+            Err(RegistryError::NoContextWindow(_))
+        ));
+    }
+}
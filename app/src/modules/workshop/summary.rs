@@ -0,0 +1,629 @@
+use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestUserMessage, CreateChatCompletionRequest,
+};
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, TryStreamExt, stream};
+use opentelemetry_http::HttpError;
+use serde::Serialize;
+use serde_json::json;
+use tracing::info;
+
+use crate::{
+    models::topics::{
+        Topic,
+        post::{Post, WorkshopPost},
+        summary_chunk::{ChunkRange, TopicSummaryChunk},
+    },
+    modules::workshop::{
+        SUMMARY_MAX_OUTPUT_TOKENS, prompts::truncate_messages_to_token_limit, registry::ModelConfig,
+    },
+    state::AppState,
+};
+
+/// Most posts summarized in one chunk
+pub const CHUNK_MAX_POSTS: usize = 64;
+/// Smaller chunks than large context windows allow, small models summarize them better
+pub const CHUNK_MAX_INPUT_TOKENS: usize = 32_000;
+/// Limit partial summaries so the reduce step has room for all of them
+pub const CHUNK_MAX_OUTPUT_TOKENS: u32 = 1000;
+/// Chunks summarized at the same time
+const CHUNK_CONCURRENCY: usize = 4;
+/// Framing of the JSON around the posts
+const CHUNK_JSON_OVERHEAD: usize = 32;
+const POSTS_PAGE_SIZE: i32 = 500;
+/// Appended to posts cut to fit a chunk
+const TRUNCATED_MARKER: &str = " [truncated]";
+
+/// Summary of the consecutive posts from `first_post` to `last_post`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct PartialSummary {
+    first_post: i32,
+    last_post: i32,
+    summary: String,
+}
+
+/// Split the posts of a topic into chunks
+///
+/// Sealed `stored` ranges are kept so their summaries can be reused, the posts after them are
+/// packed into chunks of at most [`CHUNK_MAX_POSTS`] posts and `budget` tokens as measured by
+/// `cost`. Only the last chunk is left unsealed, unless it is full.
+pub fn plan_chunks(
+    posts: &[WorkshopPost],
+    stored: &[ChunkRange],
+    budget: usize,
+    cost: impl Fn(&WorkshopPost) -> usize,
+) -> Vec<ChunkRange> {
+    let sealed: Vec<ChunkRange> = stored
+        .iter()
+        .take_while(|range| range.sealed)
+        .copied()
+        .collect();
+    let sealed_until = sealed.last().map(|range| range.last_post).unwrap_or(0);
+
+    // Ranges whose posts were all deleted have nothing left to summarize
+    let mut chunks: Vec<ChunkRange> = sealed
+        .into_iter()
+        .filter(|range| posts.iter().any(|post| range.contains(post.post_number)))
+        .collect();
+
+    // The open chunk with its post count and tokens
+    let mut current: Option<(ChunkRange, usize, usize)> = None;
+    for post in posts.iter().filter(|post| post.post_number > sealed_until) {
+        let tokens = cost(post);
+
+        if let Some((range, count, used)) = current.as_mut() {
+            if *count < CHUNK_MAX_POSTS && *used + tokens <= budget {
+                range.last_post = post.post_number;
+                *count += 1;
+                *used += tokens;
+                continue;
+            }
+
+            range.sealed = true;
+            chunks.push(*range);
+        }
+
+        current = Some((
+            ChunkRange {
+                first_post: post.post_number,
+                last_post: post.post_number,
+                sealed: false,
+            },
+            1,
+            tokens,
+        ));
+    }
+
+    if let Some((mut range, count, _)) = current {
+        range.sealed = count >= CHUNK_MAX_POSTS;
+        chunks.push(range);
+    }
+
+    chunks
+}
+
+/// Cut the content of posts that exceed `budget` on their own, so every chunk can be sent
+/// with its posts
+fn fit_posts(posts: &mut [WorkshopPost], budget: usize, cost: impl Fn(&WorkshopPost) -> usize) {
+    for post in posts.iter_mut() {
+        let Some(cooked) = post.cooked.clone() else {
+            continue;
+        };
+        if cost(post) <= budget {
+            continue;
+        }
+
+        // Longest prefix that still fits, by binary search over the number of chars kept
+        let ends: Vec<usize> = cooked
+            .char_indices()
+            .map(|(index, _)| index)
+            .chain([cooked.len()])
+            .collect();
+        let truncated = |chars: usize| format!("{}{}", &cooked[..ends[chars]], TRUNCATED_MARKER);
+        let (mut low, mut high) = (0, ends.len() - 1);
+        while low < high {
+            let middle = (low + high).div_ceil(2);
+            post.cooked = Some(truncated(middle));
+            if cost(post) <= budget {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+
+        post.cooked = Some(truncated(low));
+    }
+}
+
+/// Split partial summaries into groups of consecutive ones that fit `budget` together
+///
+/// A summary too large for any group is left on its own.
+fn group_partials(
+    partials: &[PartialSummary],
+    budget: usize,
+    cost: impl Fn(&PartialSummary) -> usize,
+) -> Vec<&[PartialSummary]> {
+    let mut groups = Vec::new();
+    let (mut start, mut used) = (0, 0);
+    for (index, partial) in partials.iter().enumerate() {
+        let tokens = cost(partial);
+        if index > start && used + tokens > budget {
+            groups.push(&partials[start..index]);
+            (start, used) = (index, 0);
+        }
+        used += tokens;
+    }
+    if start < partials.len() {
+        groups.push(&partials[start..]);
+    }
+
+    groups
+}
+
+/// Truncate `messages` to the context window of `model`, `what` names the input in errors
+///
+/// Fails when only the instructions would be left, a summary of them must not be stored.
+pub fn fit_context(
+    messages: Vec<ChatCompletionRequestMessage>,
+    model: &ModelConfig,
+    max_output_tokens: u32,
+    what: &str,
+) -> Result<Vec<ChatCompletionRequestMessage>, HttpError> {
+    let messages = truncate_messages_to_token_limit(messages, &None, model, max_output_tokens);
+    if messages.len() < 2 {
+        return Err(format!("{} doesn't fit the context window of {}", what, model.id).into());
+    }
+
+    Ok(messages)
+}
+
+/// Messages asking the summary model for the summary of a topic
+///
+/// Topics that fit in one chunk are sent as they are. Longer ones are summarized chunk by chunk
+/// first and the partial summaries are combined. Chunks stored in `topic_summary_chunks` are
+/// reused while none of their posts changed, so new posts only cost the last chunk and the
+/// reduce step. When the partial summaries don't fit the context window together, consecutive
+/// ones are merged in rounds until they do, these merges are not stored.
+pub async fn summary_messages(
+    topic: &Topic,
+    state: &AppState,
+) -> Result<Vec<ChatCompletionRequestMessage>, HttpError> {
+    let model = state.workshop.models()?.summary_model();
+    let counter = model.token_counter();
+    let mut posts = find_posts(topic, state).await?;
+
+    let budget = model
+        .input_limit(SUMMARY_MAX_OUTPUT_TOKENS)
+        .min(CHUNK_MAX_INPUT_TOKENS)
+        .saturating_sub(
+            counter.count_message(&state.workshop.prompts.summerize)
+                + counter.count_text(&serde_json::to_string(topic)?)
+                + CHUNK_JSON_OVERHEAD,
+        );
+
+    let cost =
+        |post: &WorkshopPost| counter.count_text(&serde_json::to_string(post).unwrap_or_default());
+    fit_posts(&mut posts, budget, cost);
+
+    let stored =
+        TopicSummaryChunk::find_by_topic(&topic.discourse_id, topic.topic_id, state).await?;
+    let ranges: Vec<ChunkRange> = stored.iter().map(TopicSummaryChunk::range).collect();
+    let chunks = plan_chunks(&posts, &ranges, budget, cost);
+
+    if chunks.len() <= 1 {
+        TopicSummaryChunk::delete_except(&topic.discourse_id, topic.topic_id, &[], state).await?;
+
+        return Ok(vec![
+            state.workshop.prompts.summerize.clone(),
+            user_message(json!({
+                "topic_info": topic,
+                "posts": posts,
+            }))?,
+        ]);
+    }
+
+    // Collected first, a closure in the stream trips up the `Send` checks of the handlers
+    let summarize: Vec<_> = chunks
+        .iter()
+        .map(|range| summarize_chunk(topic, range, &posts, &stored, state))
+        .collect();
+    let partial_summaries: Vec<TopicSummaryChunk> = stream::iter(summarize)
+        .buffered(CHUNK_CONCURRENCY)
+        .try_collect()
+        .await?;
+
+    let first_posts: Vec<i32> = chunks.iter().map(|range| range.first_post).collect();
+    TopicSummaryChunk::delete_except(&topic.discourse_id, topic.topic_id, &first_posts, state)
+        .await?;
+
+    info!(
+        "Combining {} partial summaries of topic {} on {}",
+        partial_summaries.len(),
+        topic.topic_id,
+        topic.discourse_id
+    );
+
+    let partial_summaries: Vec<PartialSummary> = partial_summaries
+        .into_iter()
+        .map(|chunk| PartialSummary {
+            first_post: chunk.first_post,
+            last_post: chunk.last_post,
+            summary: chunk.summary_text,
+        })
+        .collect();
+    let reduce_budget = model.input_limit(SUMMARY_MAX_OUTPUT_TOKENS).saturating_sub(
+        counter.count_message(&state.workshop.prompts.summerize)
+            + counter.count_text(&serde_json::to_string(topic)?)
+            + CHUNK_JSON_OVERHEAD,
+    );
+    let partial_summaries = reduce_partials(topic, partial_summaries, reduce_budget, state).await?;
+
+    Ok(vec![
+        state.workshop.prompts.summerize.clone(),
+        user_message(json!({
+            "topic_info": topic,
+            "partial_summaries": partial_summaries,
+        }))?,
+    ])
+}
+
+/// Merge consecutive partial summaries in rounds until they fit `budget` together
+async fn reduce_partials(
+    topic: &Topic,
+    mut partials: Vec<PartialSummary>,
+    budget: usize,
+    state: &AppState,
+) -> Result<Vec<PartialSummary>, HttpError> {
+    let model = state.workshop.models()?.summary_model();
+    let counter = model.token_counter();
+    let cost = |partial: &PartialSummary| {
+        counter.count_text(&serde_json::to_string(partial).unwrap_or_default())
+    };
+    let merge_budget = model
+        .input_limit(CHUNK_MAX_OUTPUT_TOKENS)
+        .min(CHUNK_MAX_INPUT_TOKENS)
+        .saturating_sub(
+            counter.count_message(&state.workshop.prompts.chunk_summary)
+                + counter.count_text(&serde_json::to_string(topic)?)
+                + CHUNK_JSON_OVERHEAD,
+        );
+
+    while partials.iter().map(cost).sum::<usize>() > budget {
+        let groups = group_partials(&partials, merge_budget, cost);
+        // Every summary is on its own, merging wouldn't make them any shorter
+        if groups.len() == partials.len() {
+            return Err(format!(
+                "the partial summaries of topic {} don't fit the context window of {}",
+                topic.topic_id, model.id
+            )
+            .into());
+        }
+
+        info!(
+            "Merging {} partial summaries of topic {} on {} into {}",
+            partials.len(),
+            topic.topic_id,
+            topic.discourse_id,
+            groups.len()
+        );
+
+        let merges: Vec<_> = groups
+            .into_iter()
+            .map(|group| merge_partials(topic, group, state))
+            .collect();
+        partials = stream::iter(merges)
+            .buffered(CHUNK_CONCURRENCY)
+            .try_collect()
+            .await?;
+    }
+
+    Ok(partials)
+}
+
+/// Summarize consecutive partial summaries into one, a group of one is kept as it is
+async fn merge_partials(
+    topic: &Topic,
+    group: &[PartialSummary],
+    state: &AppState,
+) -> Result<PartialSummary, HttpError> {
+    let (Some(first), Some(last)) = (group.first(), group.last()) else {
+        return Err(format!("no partial summaries to merge for topic {}", topic.topic_id).into());
+    };
+    if group.len() == 1 {
+        return Ok(first.clone());
+    }
+
+    let messages = vec![
+        state.workshop.prompts.chunk_summary.clone(),
+        user_message(json!({
+            "topic_info": topic,
+            "first_post": first.first_post,
+            "last_post": last.last_post,
+            "partial_summaries": group,
+        }))?,
+    ];
+
+    let model = state.workshop.models()?.summary_model();
+    let messages = fit_context(
+        messages,
+        model,
+        CHUNK_MAX_OUTPUT_TOKENS,
+        &format!(
+            "the summaries of posts {}-{} of topic {}",
+            first.first_post, last.last_post, topic.topic_id
+        ),
+    )?;
+
+    let summary = complete_chunk(model, messages, state).await?;
+
+    Ok(PartialSummary {
+        first_post: first.first_post,
+        last_post: last.last_post,
+        summary,
+    })
+}
+
+/// The stored summary of the chunk if its posts didn't change since, a fresh one otherwise
+async fn summarize_chunk(
+    topic: &Topic,
+    range: &ChunkRange,
+    posts: &[WorkshopPost],
+    stored: &[TopicSummaryChunk],
+    state: &AppState,
+) -> Result<TopicSummaryChunk, HttpError> {
+    let posts: Vec<&WorkshopPost> = posts
+        .iter()
+        .filter(|post| range.contains(post.post_number))
+        .collect();
+    if posts.is_empty() {
+        return Err(format!(
+            "no posts left in posts {}-{} of topic {}",
+            range.first_post, range.last_post, topic.topic_id
+        )
+        .into());
+    }
+    let based_on = posts
+        .iter()
+        .filter_map(|post| post.updated_at.or(post.created_at))
+        .max()
+        .unwrap_or(DateTime::<Utc>::UNIX_EPOCH);
+
+    if let Some(chunk) = stored.iter().find(|chunk| {
+        chunk.first_post == range.first_post
+            && chunk.last_post == range.last_post
+            && chunk.based_on >= based_on
+    }) {
+        if chunk.sealed == range.sealed {
+            return Ok(chunk.clone());
+        }
+
+        return Ok(TopicSummaryChunk::upsert(
+            &topic.discourse_id,
+            topic.topic_id,
+            range,
+            chunk.based_on,
+            &chunk.summary_text,
+            state,
+        )
+        .await?);
+    }
+
+    info!(
+        "Summarizing posts {}-{} of topic {} on {}",
+        range.first_post, range.last_post, topic.topic_id, topic.discourse_id
+    );
+
+    let messages = vec![
+        state.workshop.prompts.chunk_summary.clone(),
+        user_message(json!({
+            "topic_info": topic,
+            "first_post": range.first_post,
+            "last_post": range.last_post,
+            "posts": posts,
+        }))?,
+    ];
+
+    let model = state.workshop.models()?.summary_model();
+    let messages = fit_context(
+        messages,
+        model,
+        CHUNK_MAX_OUTPUT_TOKENS,
+        &format!(
+            "posts {}-{} of topic {}",
+            range.first_post, range.last_post, topic.topic_id
+        ),
+    )?;
+
+    let summary_text = complete_chunk(model, messages, state).await?;
+
+    Ok(TopicSummaryChunk::upsert(
+        &topic.discourse_id,
+        topic.topic_id,
+        range,
+        based_on,
+        &summary_text,
+        state,
+    )
+    .await?)
+}
+
+/// Run a chunk or merge prompt, returns the notes the model wrote
+async fn complete_chunk(
+    model: &ModelConfig,
+    messages: Vec<ChatCompletionRequestMessage>,
+    state: &AppState,
+) -> Result<String, HttpError> {
+    let request = CreateChatCompletionRequest {
+        model: model.id.clone(),
+        messages,
+        max_completion_tokens: Some(CHUNK_MAX_OUTPUT_TOKENS),
+        ..Default::default()
+    };
+
+    let chat_completion = state.workshop.provider()?.chat(request).await?;
+
+    if let Some(usage) = &chat_completion.usage {
+        info!(
+            "💰 Chunk summary usage - prompt: {}, completion: {}, total: {}",
+            usage.prompt_tokens, usage.completion_tokens, usage.total_tokens
+        );
+    }
+
+    Ok(chat_completion
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .filter(|content| !content.trim().is_empty())
+        .ok_or("the model returned an empty chunk summary")?)
+}
+
+/// Every post of the topic, oldest first
+async fn find_posts(topic: &Topic, state: &AppState) -> Result<Vec<WorkshopPost>, sqlx::Error> {
+    let mut posts = Vec::new();
+
+    for page in 1.. {
+        let (page_posts, has_more) = Post::find_by_topic_id(
+            &topic.discourse_id,
+            topic.topic_id,
+            page,
+            Some(POSTS_PAGE_SIZE),
+            state,
+        )
+        .await?;
+        posts.extend(page_posts.into_iter().map(WorkshopPost::from));
+
+        if !has_more {
+            break;
+        }
+    }
+
+    Ok(posts)
+}
+
+fn user_message(content: serde_json::Value) -> Result<ChatCompletionRequestMessage, HttpError> {
+    Ok(ChatCompletionRequestMessage::User(
+        ChatCompletionRequestUserMessage {
+            content: serde_json::to_string(&content)?.into(),
+            name: None,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_posts() {
+        let cost = |post: &WorkshopPost| post.cooked.as_deref().unwrap_or_default().len();
+        let mut posts = vec![post(1), post(2), post(3)];
+        posts[0].cooked = Some("short".to_string());
+        posts[1].cooked = Some("ä".repeat(100));
+
+        fit_posts(&mut posts, 50, cost);
+
+        assert_eq!(posts[0].cooked.as_deref(), Some("short"));
+        // 19 two byte chars and the marker are 50 bytes
+        assert_eq!(
+            posts[1].cooked,
+            Some(format!("{}{}", "ä".repeat(19), TRUNCATED_MARKER))
+        );
+        assert_eq!(posts[2].cooked, None);
+    }
+
+    fn post(post_number: i32) -> WorkshopPost {
+        WorkshopPost {
+            discourse_id: "magicians".to_string(),
+            post_id: post_number + 1000,
+            post_number,
+            user_id: 1,
+            username: None,
+            updated_at: None,
+            created_at: None,
+            cooked: None,
+        }
+    }
+
+    fn range(first_post: i32, last_post: i32, sealed: bool) -> ChunkRange {
+        ChunkRange {
+            first_post,
+            last_post,
+            sealed,
+        }
+    }
+
+    fn partial(first_post: i32, last_post: i32) -> PartialSummary {
+        PartialSummary {
+            first_post,
+            last_post,
+            summary: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_group_partials() {
+        let partials: Vec<PartialSummary> =
+            (0..5).map(|i| partial(i * 10 + 1, i * 10 + 10)).collect();
+        let cost = |partial: &PartialSummary| if partial.first_post == 21 { 50 } else { 10 };
+
+        // Consecutive summaries are packed up to the budget
+        let groups = group_partials(&partials, 30, |_| 10);
+        assert_eq!(groups, vec![&partials[..3], &partials[3..]]);
+
+        // Oversized summaries stay on their own
+        let groups = group_partials(&partials, 30, cost);
+        assert_eq!(
+            groups,
+            vec![&partials[..2], &partials[2..3], &partials[3..]]
+        );
+
+        assert!(group_partials(&[], 30, cost).is_empty());
+    }
+
+    #[test]
+    fn test_plan_chunks() {
+        let posts: Vec<WorkshopPost> = (1..=150).map(post).collect();
+
+        // Short topics are a single open chunk
+        assert_eq!(
+            plan_chunks(&posts[..10], &[], 10_000, |_| 10),
+            vec![range(1, 10, false)]
+        );
+
+        // Full chunks are sealed, the rest stays open
+        let planned = plan_chunks(&posts, &[], 10_000, |_| 10);
+        assert_eq!(
+            planned,
+            vec![
+                range(1, 64, true),
+                range(65, 128, true),
+                range(129, 150, false)
+            ]
+        );
+
+        // The token budget closes chunks before the post limit
+        let planned = plan_chunks(&posts[..30], &[], 100, |_| 10);
+        assert_eq!(
+            planned,
+            vec![
+                range(1, 10, true),
+                range(11, 20, true),
+                range(21, 30, false)
+            ]
+        );
+
+        // Sealed ranges are kept even if they'd be packed differently now, the open one grows
+        let stored = [range(1, 40, true), range(41, 45, false)];
+        let planned = plan_chunks(&posts[..60], &stored, 10_000, |_| 10);
+        assert_eq!(planned, vec![range(1, 40, true), range(41, 60, false)]);
+
+        // Ranges without posts left are dropped
+        let remaining: Vec<WorkshopPost> = posts[..60]
+            .iter()
+            .filter(|post| post.post_number > 40)
+            .cloned()
+            .collect();
+        let planned = plan_chunks(&remaining, &stored, 10_000, |_| 10);
+        assert_eq!(planned, vec![range(41, 60, false)]);
+    }
+}
@@ -0,0 +1,247 @@
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessageContent, ChatCompletionRequestDeveloperMessageContent,
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageContent,
+    ChatCompletionRequestToolMessageContent, ChatCompletionRequestUserMessageContent,
+    ChatCompletionTool,
+};
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::{CoreBPE, cl100k_base_singleton, o200k_base_singleton};
+
+/// Every message is wrapped in `<|start|>{role}\n{content}<|end|>\n`
+const TOKENS_PER_MESSAGE: usize = 3;
+/// Additional tokens if name is present
+const TOKENS_PER_NAME: usize = 1;
+/// Every reply is primed with `<|start|>assistant<|message|>`
+const TOKENS_PER_REPLY: usize = 3;
+/// Wrapping of each tool call in an assistant message
+const TOKENS_PER_TOOL_CALL: usize = 4;
+/// Headroom in percent for models whose vocabulary isn't bundled, counted with the closest one
+const FOREIGN_VOCAB_MARGIN: usize = 10;
+
+/// A BPE vocabulary, the `.tiktoken` files are bundled with `tiktoken-rs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Tokenizer {
+    /// GPT-4o, GPT-4.1 and the o-series
+    #[serde(rename = "o200k_base")]
+    O200kBase,
+    /// GPT-4 and GPT-3.5
+    #[serde(rename = "cl100k_base")]
+    Cl100kBase,
+}
+
+impl Tokenizer {
+    /// The vocabulary of an OpenRouter style `<vendor>/<model>` id, `None` for families
+    /// without a published BPE vocabulary
+    pub fn for_model(id: &str) -> Option<Self> {
+        let model = id.strip_prefix("openai/")?;
+        if model.starts_with("gpt-4o")
+            || model.starts_with("gpt-4.1")
+            || model.starts_with("gpt-5")
+            || model.starts_with('o')
+        {
+            Some(Self::O200kBase)
+        } else {
+            Some(Self::Cl100kBase)
+        }
+    }
+
+    fn bpe(&self) -> &'static CoreBPE {
+        match self {
+            Self::O200kBase => o200k_base_singleton(),
+            Self::Cl100kBase => cl100k_base_singleton(),
+        }
+    }
+}
+
+/// Counts prompt tokens the way the model's tokenizer would
+///
+/// Models from families without a bundled vocabulary (Gemini, Claude, ...) are counted with
+/// `cl100k_base` plus a margin, their vocabularies split English text into a similar number
+/// of tokens.
+pub struct TokenCounter {
+    bpe: &'static CoreBPE,
+    /// Percent added to every count
+    margin: usize,
+}
+
+impl TokenCounter {
+    /// `tokenizer` overrides the one guessed from the model id
+    pub fn new(id: &str, tokenizer: Option<Tokenizer>) -> Self {
+        match tokenizer.or_else(|| Tokenizer::for_model(id)) {
+            Some(tokenizer) => Self {
+                bpe: tokenizer.bpe(),
+                margin: 0,
+            },
+            None => Self {
+                bpe: Tokenizer::Cl100kBase.bpe(),
+                margin: FOREIGN_VOCAB_MARGIN,
+            },
+        }
+    }
+
+    fn with_margin(&self, tokens: usize) -> usize {
+        tokens + (tokens * self.margin).div_ceil(100)
+    }
+
+    fn encode(&self, text: &str) -> usize {
+        self.bpe.encode_ordinary(text).len()
+    }
+
+    pub fn count_text(&self, text: &str) -> usize {
+        self.with_margin(self.encode(text))
+    }
+
+    /// Role, content, name and tool calls of a message with its framing
+    pub fn count_message(&self, message: &ChatCompletionRequestMessage) -> usize {
+        let mut tokens = TOKENS_PER_MESSAGE;
+
+        let (role, name) = match message {
+            ChatCompletionRequestMessage::System(system_msg) => {
+                tokens += match &system_msg.content {
+                    ChatCompletionRequestSystemMessageContent::Text(text) => self.encode(text),
+                    ChatCompletionRequestSystemMessageContent::Array(parts) => {
+                        self.encode_json(parts)
+                    }
+                };
+                ("system", system_msg.name.as_deref())
+            }
+            ChatCompletionRequestMessage::Developer(developer_msg) => {
+                tokens += match &developer_msg.content {
+                    ChatCompletionRequestDeveloperMessageContent::Text(text) => self.encode(text),
+                    ChatCompletionRequestDeveloperMessageContent::Array(parts) => {
+                        self.encode_json(parts)
+                    }
+                };
+                ("developer", developer_msg.name.as_deref())
+            }
+            ChatCompletionRequestMessage::User(user_msg) => {
+                tokens += match &user_msg.content {
+                    ChatCompletionRequestUserMessageContent::Text(text) => self.encode(text),
+                    ChatCompletionRequestUserMessageContent::Array(parts) => {
+                        self.encode_json(parts)
+                    }
+                };
+                ("user", user_msg.name.as_deref())
+            }
+            ChatCompletionRequestMessage::Assistant(assistant_msg) => {
+                tokens += match &assistant_msg.content {
+                    Some(ChatCompletionRequestAssistantMessageContent::Text(text)) => {
+                        self.encode(text)
+                    }
+                    Some(ChatCompletionRequestAssistantMessageContent::Array(parts)) => {
+                        self.encode_json(parts)
+                    }
+                    None => 0,
+                };
+                if let Some(refusal) = &assistant_msg.refusal {
+                    tokens += self.encode(refusal);
+                }
+                for tool_call in assistant_msg.tool_calls.iter().flatten() {
+                    tokens += TOKENS_PER_TOOL_CALL
+                        + self.encode(&tool_call.id)
+                        + self.encode(&tool_call.function.name)
+                        + self.encode(&tool_call.function.arguments);
+                }
+                ("assistant", assistant_msg.name.as_deref())
+            }
+            ChatCompletionRequestMessage::Tool(tool_msg) => {
+                tokens += match &tool_msg.content {
+                    ChatCompletionRequestToolMessageContent::Text(text) => self.encode(text),
+                    ChatCompletionRequestToolMessageContent::Array(parts) => {
+                        self.encode_json(parts)
+                    }
+                };
+                tokens += self.encode(&tool_msg.tool_call_id);
+                ("tool", None)
+            }
+            ChatCompletionRequestMessage::Function(function_msg) => {
+                tokens += self.encode(function_msg.content.as_deref().unwrap_or_default());
+                ("function", Some(function_msg.name.as_str()))
+            }
+        };
+
+        tokens += self.encode(role);
+        if let Some(name) = name {
+            tokens += self.encode(name) + TOKENS_PER_NAME;
+        }
+
+        self.with_margin(tokens)
+    }
+
+    /// Tool definitions are sent along the prompt, counted by their full JSON schema
+    pub fn count_tools(&self, tools: &[ChatCompletionTool]) -> usize {
+        self.with_margin(tools.iter().map(|tool| self.encode_json(tool)).sum())
+    }
+
+    /// Tokens the provider adds to prime the reply
+    pub fn count_reply_priming(&self) -> usize {
+        TOKENS_PER_REPLY
+    }
+
+    fn encode_json<T: Serialize + ?Sized>(&self, value: &T) -> usize {
+        serde_json::to_string(value)
+            .map(|json| self.encode(&json))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::{
+        ChatCompletionRequestUserMessage, ChatCompletionToolType, FunctionObject,
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_token_counter() {
+        assert_eq!(
+            Tokenizer::for_model("openai/o4-mini"),
+            Some(Tokenizer::O200kBase)
+        );
+        assert_eq!(
+            Tokenizer::for_model("openai/gpt-4-turbo"),
+            Some(Tokenizer::Cl100kBase)
+        );
+        assert_eq!(Tokenizer::for_model("google/gemini-2.5-flash"), None);
+
+        let gpt = TokenCounter::new("openai/gpt-4o", None);
+        // "hello world" is two tokens in both vocabularies
+        assert_eq!(gpt.count_text("hello world"), 2);
+
+        // 3 framing + "user" + 2 content tokens
+        let message = ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+            content: "hello world".to_string().into(),
+            name: None,
+        });
+        assert_eq!(gpt.count_message(&message), 6);
+
+        // 10% on top for vocabularies that aren't bundled
+        let gemini = TokenCounter::new("google/gemini-2.5-flash", None);
+        let text = "Inclusion lists force block builders to include transactions ".repeat(10);
+        let counted = TokenCounter::new("openai/gpt-4", None).count_text(&text);
+        assert_eq!(gemini.count_text(&text), counted + counted.div_ceil(10));
+
+        let explicit = TokenCounter::new("qwen3:8b", Some(Tokenizer::O200kBase));
+        assert_eq!(explicit.count_text(&text), gpt.count_text(&text));
+
+        // Tools cost their whole schema, not a flat amount
+        let tool = |description: &str| ChatCompletionTool {
+            r#type: ChatCompletionToolType::Function,
+            function: FunctionObject {
+                name: "search_forum".to_string(),
+                description: Some(description.to_string()),
+                parameters: Some(json!({
+                    "type": "object",
+                    "properties": { "query": { "type": "string" } },
+                })),
+                strict: None,
+            },
+        };
+        let short = gpt.count_tools(&[tool("Search")]);
+        let long = gpt.count_tools(&[tool(&text)]);
+        assert!(short > 10);
+        assert!(long - short > gpt.count_text(&text) - 5);
+    }
+}
@@ -1,32 +1,24 @@
-use crate::models::topics::{post::Post, Topic};
+use crate::models::crawl::CrawlCursor;
+use crate::models::crawl::job::{IndexJob, IndexQueueStats};
+use crate::models::search::reindex::ReindexJob;
 use crate::models::workshop::usage::UserUsageOverview;
 use crate::models::workshop::usage::get_all_users_usage_overview;
-use crate::modules::discourse::{DiscourseService, ForumSearchDocument};
+use crate::modules::discourse::DiscourseIndexerSchedule;
+use crate::modules::search::reconcile::ReconcileReport;
 use crate::server::ApiTags;
 use crate::state::AppState;
 use poem::Result;
 use poem::web::Data;
-use poem_openapi::param::Header;
+use poem_openapi::param::{Header, Path, Query};
 use poem_openapi::payload::Json;
 use poem_openapi::{Object, OpenApi};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use sqlx::query_as;
-use strip_tags::strip_tags;
 use tracing::{error, info, warn};
 
 #[derive(Debug, Serialize, Deserialize, Object)]
 pub struct AdminApi;
 
-#[derive(Debug, Serialize, Deserialize, Object)]
-pub struct ReindexResponse {
-    pub success: bool,
-    pub message: String,
-    pub topics_processed: i32,
-    pub posts_processed: i32,
-    pub errors: i32,
-}
-
 #[derive(Debug, Serialize, Deserialize, Object)]
 pub struct AdminStatsResponse {
     pub database_topics: i64,
@@ -34,6 +26,19 @@ pub struct AdminStatsResponse {
     pub meilisearch_documents: Option<i64>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct BackfillStatusResponse {
+    pub discourse_id: String,
+    pub running: bool,
+    pub cursors: Vec<CrawlCursor>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct ReconcileStatusResponse {
+    pub running: bool,
+    pub last_report: Option<ReconcileReport>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Object)]
 pub struct AdminUsageResponse {
     pub total_users: i32,
@@ -60,182 +65,127 @@ impl AdminApi {
 impl AdminApi {
     /// /admin/reindex
     ///
-    /// Trigger a full reindex of all topics and posts from database to Meilisearch
+    /// Start a background rebuild of the Meilisearch index from the database, or get the
+    /// one already running. Pass `swap=true` to build a new index and swap it in once
+    /// complete, search keeps serving the current index meanwhile.
     #[oai(path = "/admin/reindex", method = "post", tag = "ApiTags::Admin")]
     async fn reindex_all(
         &self,
         state: Data<&AppState>,
         #[oai(name = "X-Admin-Key")] admin_key: Header<Option<String>>,
-    ) -> Result<Json<ReindexResponse>> {
+        #[oai(style = "simple")] swap: Query<Option<bool>>,
+    ) -> Result<Json<ReindexJob>> {
         Self::verify_admin_key(admin_key.0)?;
 
-        let Some(meili) = &state.meili else {
-            return Ok(Json(ReindexResponse {
-                success: false,
-                message: "Meilisearch is not configured".to_string(),
-                topics_processed: 0,
-                posts_processed: 0,
-                errors: 0,
-            }));
-        };
+        if state.meili.is_none() {
+            return Err(poem::Error::from_status(StatusCode::SERVICE_UNAVAILABLE));
+        }
+
+        let job = state
+            .reindex
+            .start(swap.0.unwrap_or(false), &state)
+            .await
+            .map_err(|e| {
+                error!("Failed to start reindex job: {}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
 
-        info!("Starting full reindex of all topics and posts");
+        info!("Reindex job {} is {} in phase {}", job.job_id, job.status, job.phase);
 
-        let mut topics_processed = 0i32;
-        let mut posts_processed = 0i32;
-        let mut errors = 0i32;
+        Ok(Json(job))
+    }
+
+    /// /admin/jobs/:job_id
+    ///
+    /// Get the progress of a reindex job
+    #[oai(path = "/admin/jobs/:job_id", method = "get", tag = "ApiTags::Admin")]
+    async fn get_reindex_job(
+        &self,
+        state: Data<&AppState>,
+        #[oai(name = "X-Admin-Key")] admin_key: Header<Option<String>>,
+        #[oai(style = "simple")] job_id: Path<i64>,
+    ) -> Result<Json<ReindexJob>> {
+        Self::verify_admin_key(admin_key.0)?;
 
-        // Get all topics from database
-        let topics = match query_as!(Topic, "SELECT * FROM topics ORDER BY topic_id ASC")
-            .fetch_all(&state.database.pool)
+        ReindexJob::find_by_id(job_id.0, &state)
             .await
-        {
-            Ok(topics) => topics,
-            Err(e) => {
-                error!("Failed to fetch topics from database: {}", e);
-                return Ok(Json(ReindexResponse {
-                    success: false,
-                    message: format!("Database error: {}", e),
-                    topics_processed: 0,
-                    posts_processed: 0,
-                    errors: 1,
-                }));
-            }
-        };
+            .map_err(|e| {
+                error!("Failed to load reindex job: {}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?
+            .map(Json)
+            .ok_or_else(|| poem::Error::from_status(StatusCode::NOT_FOUND))
+    }
 
-        info!("Found {} topics to reindex", topics.len());
-
-        // Index all topics
-        let forum_index = meili.index("forum");
-        let mut topic_docs = Vec::new();
-
-        for topic in &topics {
-            topic_docs.push(ForumSearchDocument {
-                entity_type: "topic".to_string(),
-                discourse_id: Some(topic.discourse_id.clone()),
-                topic_id: Some(topic.topic_id),
-                post_id: None,
-                post_number: None,
-                user_id: None,
-                username: None,
-                title: Some(topic.title.clone()),
-                slug: Some(topic.slug.clone()),
-                pm_issue: topic.pm_issue,
-                cooked: None,
-                entity_id: format!("topic_{}", topic.topic_id),
-            });
-            topics_processed += 1;
-        }
+    /// /admin/jobs/:job_id/resume
+    ///
+    /// Resume a failed reindex job from the last batch it indexed
+    #[oai(path = "/admin/jobs/:job_id/resume", method = "post", tag = "ApiTags::Admin")]
+    async fn resume_reindex_job(
+        &self,
+        state: Data<&AppState>,
+        #[oai(name = "X-Admin-Key")] admin_key: Header<Option<String>>,
+        #[oai(style = "simple")] job_id: Path<i64>,
+    ) -> Result<Json<ReindexJob>> {
+        Self::verify_admin_key(admin_key.0)?;
 
-        // Batch insert topics
-        if !topic_docs.is_empty() {
-            match forum_index
-                .add_documents(&topic_docs, Some("entity_id"))
-                .await
-            {
-                Ok(_) => info!("Successfully indexed {} topics", topic_docs.len()),
-                Err(e) => {
-                    error!("Failed to index topics: {}", e);
-                    errors += 1;
+        state
+            .reindex
+            .resume(job_id.0, &state)
+            .await
+            .map_err(|e| match e.as_database_error() {
+                Some(e) if e.is_unique_violation() => poem::Error::from_status(StatusCode::CONFLICT),
+                _ => {
+                    error!("Failed to resume reindex job: {}", e);
+                    poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
                 }
-            }
-        }
+            })?
+            .map(Json)
+            .ok_or_else(|| poem::Error::from_status(StatusCode::NOT_FOUND))
+    }
 
-        // Get all posts from database
-        let posts = match query_as!(Post, "SELECT * FROM posts ORDER BY post_id ASC")
-            .fetch_all(&state.database.pool)
-            .await
-        {
-            Ok(posts) => posts,
-            Err(e) => {
-                error!("Failed to fetch posts from database: {}", e);
-                errors += 1;
-                return Ok(Json(ReindexResponse {
-                    success: false,
-                    message: format!("Database error fetching posts: {}", e),
-                    topics_processed,
-                    posts_processed: 0,
-                    errors,
-                }));
-            }
-        };
+    /// /admin/reconcile
+    ///
+    /// Get whether a reconciliation of the database and the Meilisearch index is running,
+    /// and the report of the last one
+    #[oai(path = "/admin/reconcile", method = "get", tag = "ApiTags::Admin")]
+    async fn get_reconcile(
+        &self,
+        state: Data<&AppState>,
+        #[oai(name = "X-Admin-Key")] admin_key: Header<Option<String>>,
+    ) -> Result<Json<ReconcileStatusResponse>> {
+        Self::verify_admin_key(admin_key.0)?;
 
-        info!("Found {} posts to reindex", posts.len());
-
-        // Build user mapping from post extras for more efficient username lookup
-        let user_mapping = build_user_mapping_from_posts(&posts);
-        info!("Built user mapping for {} users", user_mapping.len());
-
-        // Index all posts in batches to avoid memory issues
-        const BATCH_SIZE: usize = 100;
-        let post_batches = posts.chunks(BATCH_SIZE);
-
-        for batch in post_batches {
-            let mut post_docs = Vec::new();
-
-            for post in batch {
-                // Try to get username from our mapping first, then fallback to API
-                let username = user_mapping
-                    .get(&post.user_id)
-                    .map(|u| u.clone())
-                    .or_else(|| {
-                        // Fallback to API lookup (currently returns None for efficiency)
-                        None
-                    });
-
-                post_docs.push(ForumSearchDocument {
-                    entity_type: "post".to_string(),
-                    discourse_id: Some(post.discourse_id.clone()),
-                    topic_id: Some(post.topic_id),
-                    post_id: Some(post.post_id),
-                    post_number: Some(post.post_number),
-                    user_id: Some(post.user_id),
-                    username,
-                    title: None,
-                    slug: None,
-                    pm_issue: None,
-                    cooked: post.cooked.as_deref().map(strip_tags),
-                    entity_id: format!("post_{}", post.post_id),
-                });
-                posts_processed += 1;
-            }
+        Ok(Json(ReconcileStatusResponse {
+            running: state.reconcile.is_running(),
+            last_report: state.reconcile.last_report().await,
+        }))
+    }
 
-            // Batch insert posts
-            if !post_docs.is_empty() {
-                match forum_index
-                    .add_documents(&post_docs, Some("entity_id"))
-                    .await
-                {
-                    Ok(_) => info!("Successfully indexed batch of {} posts", post_docs.len()),
-                    Err(e) => {
-                        error!("Failed to index post batch: {}", e);
-                        errors += 1;
-                    }
-                }
-            }
-        }
+    /// /admin/reconcile
+    ///
+    /// Compare the database with the Meilisearch index in the background, re-push missing
+    /// and stale documents and delete orphans. Pass `dry_run=true` to only count them.
+    #[oai(path = "/admin/reconcile", method = "post", tag = "ApiTags::Admin")]
+    async fn start_reconcile(
+        &self,
+        state: Data<&AppState>,
+        #[oai(name = "X-Admin-Key")] admin_key: Header<Option<String>>,
+        #[oai(style = "simple")] dry_run: Query<Option<bool>>,
+    ) -> Result<Json<ReconcileStatusResponse>> {
+        Self::verify_admin_key(admin_key.0)?;
 
-        let success = errors == 0;
-        let message = if success {
-            format!(
-                "Successfully reindexed {} topics and {} posts",
-                topics_processed, posts_processed
-            )
-        } else {
-            format!(
-                "Reindexing completed with {} errors. Processed {} topics and {} posts",
-                errors, topics_processed, posts_processed
-            )
-        };
+        if state.meili.is_none() {
+            return Err(poem::Error::from_status(StatusCode::SERVICE_UNAVAILABLE));
+        }
 
-        info!("{}", message);
+        if !state.reconcile.start(dry_run.0.unwrap_or(false), &state) {
+            info!("Search reconciliation is already running");
+        }
 
-        Ok(Json(ReindexResponse {
-            success,
-            message,
-            topics_processed,
-            posts_processed,
-            errors,
+        Ok(Json(ReconcileStatusResponse {
+            running: true,
+            last_report: state.reconcile.last_report().await,
         }))
     }
 
@@ -294,6 +244,171 @@ impl AdminApi {
         }))
     }
 
+    /// /admin/indexers
+    ///
+    /// Get the scrape schedule, last and next run of every discourse indexer
+    #[oai(path = "/admin/indexers", method = "get", tag = "ApiTags::Admin")]
+    async fn get_indexers(
+        &self,
+        state: Data<&AppState>,
+        #[oai(name = "X-Admin-Key")] admin_key: Header<Option<String>>,
+    ) -> Result<Json<Vec<DiscourseIndexerSchedule>>> {
+        Self::verify_admin_key(admin_key.0)?;
+
+        Ok(Json(state.discourse.schedules().await))
+    }
+
+    /// /admin/discourse/:discourse_id/backfill
+    ///
+    /// Get the progress of the backfill crawl for a discourse instance
+    #[oai(
+        path = "/admin/discourse/:discourse_id/backfill",
+        method = "get",
+        tag = "ApiTags::Admin"
+    )]
+    async fn get_backfill(
+        &self,
+        state: Data<&AppState>,
+        #[oai(name = "X-Admin-Key")] admin_key: Header<Option<String>>,
+        #[oai(style = "simple")] discourse_id: Path<String>,
+    ) -> Result<Json<BackfillStatusResponse>> {
+        Self::verify_admin_key(admin_key.0)?;
+
+        let running = state
+            .discourse
+            .is_backfilling(&discourse_id.0)
+            .ok_or_else(|| poem::Error::from_status(StatusCode::NOT_FOUND))?;
+
+        let cursors = CrawlCursor::find_by_discourse_id(&discourse_id.0, &state)
+            .await
+            .map_err(|e| {
+                error!("Failed to load crawl cursors: {}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(BackfillStatusResponse {
+            discourse_id: discourse_id.0,
+            running,
+            cursors,
+        }))
+    }
+
+    /// /admin/discourse/:discourse_id/backfill
+    ///
+    /// Start or resume the backfill crawl for a discourse instance,
+    /// pass `restart=true` to discard stored progress and crawl from the first page
+    #[oai(
+        path = "/admin/discourse/:discourse_id/backfill",
+        method = "post",
+        tag = "ApiTags::Admin"
+    )]
+    async fn start_backfill(
+        &self,
+        state: Data<&AppState>,
+        #[oai(name = "X-Admin-Key")] admin_key: Header<Option<String>>,
+        #[oai(style = "simple")] discourse_id: Path<String>,
+        #[oai(style = "simple")] restart: Query<Option<bool>>,
+    ) -> Result<Json<BackfillStatusResponse>> {
+        Self::verify_admin_key(admin_key.0)?;
+
+        let started = state
+            .discourse
+            .start_backfill(&discourse_id.0, state.0.clone(), restart.0.unwrap_or(false))
+            .map_err(|_| poem::Error::from_status(StatusCode::NOT_FOUND))?;
+
+        if !started {
+            info!("Backfill for {} is already running", discourse_id.0);
+        }
+
+        let cursors = CrawlCursor::find_by_discourse_id(&discourse_id.0, &state)
+            .await
+            .map_err(|e| {
+                error!("Failed to load crawl cursors: {}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(BackfillStatusResponse {
+            discourse_id: discourse_id.0,
+            running: true,
+            cursors,
+        }))
+    }
+
+    /// /admin/queue
+    ///
+    /// Get the number of queued and failed index jobs per instance and priority
+    #[oai(path = "/admin/queue", method = "get", tag = "ApiTags::Admin")]
+    async fn get_queue(
+        &self,
+        state: Data<&AppState>,
+        #[oai(name = "X-Admin-Key")] admin_key: Header<Option<String>>,
+    ) -> Result<Json<Vec<IndexQueueStats>>> {
+        Self::verify_admin_key(admin_key.0)?;
+
+        let stats = IndexJob::stats(&state).await.map_err(|e| {
+            error!("Failed to load queue stats: {}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        Ok(Json(stats))
+    }
+
+    /// /admin/discourse/:discourse_id/queue/failed
+    ///
+    /// Get the most recently failed index jobs for a discourse instance
+    #[oai(
+        path = "/admin/discourse/:discourse_id/queue/failed",
+        method = "get",
+        tag = "ApiTags::Admin"
+    )]
+    async fn get_failed_jobs(
+        &self,
+        state: Data<&AppState>,
+        #[oai(name = "X-Admin-Key")] admin_key: Header<Option<String>>,
+        #[oai(style = "simple")] discourse_id: Path<String>,
+        #[oai(style = "simple")] limit: Query<Option<i64>>,
+    ) -> Result<Json<Vec<IndexJob>>> {
+        Self::verify_admin_key(admin_key.0)?;
+
+        let limit = limit.0.unwrap_or(50).clamp(1, 500);
+        let jobs = IndexJob::find_failed(&discourse_id.0, limit, &state)
+            .await
+            .map_err(|e| {
+                error!("Failed to load failed jobs: {}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(jobs))
+    }
+
+    /// /admin/discourse/:discourse_id/queue/retry
+    ///
+    /// Put all failed index jobs of a discourse instance back in the queue
+    #[oai(
+        path = "/admin/discourse/:discourse_id/queue/retry",
+        method = "post",
+        tag = "ApiTags::Admin"
+    )]
+    async fn retry_failed_jobs(
+        &self,
+        state: Data<&AppState>,
+        #[oai(name = "X-Admin-Key")] admin_key: Header<Option<String>>,
+        #[oai(style = "simple")] discourse_id: Path<String>,
+    ) -> Result<Json<serde_json::Value>> {
+        Self::verify_admin_key(admin_key.0)?;
+
+        let retried = IndexJob::retry_failed(&discourse_id.0, &state)
+            .await
+            .map_err(|e| {
+                error!("Failed to retry failed jobs: {}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        info!("Re-queued {} failed jobs for {}", retried, discourse_id.0);
+
+        Ok(Json(serde_json::json!({ "retried": retried })))
+    }
+
     /// /admin/usage
     ///
     /// Get workshop usage statistics for all users
@@ -328,26 +443,3 @@ impl AdminApi {
         }))
     }
 }
-
-/// Helper function to get username for a user_id using Discourse API with caching
-async fn get_username_for_user_id(_user_id: i32, _discourse: &DiscourseService) -> Option<String> {
-    // For reindexing, we try to get usernames from Discourse, but don't block on failures
-    // This is best-effort - new posts will have usernames from the API
-    None
-}
-
-/// Build a comprehensive user mapping by extracting user info from post extras
-fn build_user_mapping_from_posts(posts: &[Post]) -> std::collections::HashMap<i32, String> {
-    let mut user_map = std::collections::HashMap::new();
-
-    for post in posts {
-        if let Some(extra) = &post.extra {
-            // Try to extract username from the extra JSON data
-            if let Some(username) = extra.get("username").and_then(|u| u.as_str()) {
-                user_map.insert(post.user_id, username.to_string());
-            }
-        }
-    }
-
-    user_map
-}
@@ -43,7 +43,8 @@ use crate::state::AppState;
 /// Represents an authenticated user with their information
 #[derive(Debug, Clone)]
 pub struct AuthenticatedUser {
-    pub user: User,
+    /// Boxed to keep [`OptionalAuthUser`] small
+    pub user: Box<User>,
     pub claims: JWTClaims,
 }
 
@@ -155,12 +156,38 @@ async fn validate_bearer_token(req: &Request, bearer: Bearer) -> Option<Authenti
     // Single line for successful authentication
     tracing::debug!("✅ JWT auth: {}", user.display_name.as_deref().unwrap_or("unknown"));
 
-    Some(AuthenticatedUser { user, claims })
+    Some(AuthenticatedUser {
+        user: Box::new(user),
+        claims,
+    })
 }
 
 // Convenience type alias for easier usage in handlers
 pub type AuthUser = JWTAuth;
 
+/// Bearer authentication for public endpoints that show more to signed in users
+///
+/// Missing or invalid tokens fall back to an anonymous request instead of a 401.
+#[derive(SecurityScheme)]
+pub enum OptionalAuthUser {
+    Bearer(JWTAuth),
+    #[oai(fallback)]
+    Anonymous,
+}
+
+impl OptionalAuthUser {
+    pub fn user(&self) -> Option<&AuthenticatedUser> {
+        match self {
+            Self::Bearer(auth) => Some(&auth.0),
+            Self::Anonymous => None,
+        }
+    }
+
+    pub fn claims(&self) -> Option<&JWTClaims> {
+        self.user().map(|user| &user.claims)
+    }
+}
+
 /// Authentication error types for better error handling
 #[derive(Debug)]
 pub enum AuthError {
@@ -222,5 +249,24 @@ pub async fn extract_user_from_request(req: &Request) -> Result<Option<Authentic
         .map_err(AuthError::DatabaseError)?
         .ok_or(AuthError::UserNotFound)?;
 
-    Ok(Some(AuthenticatedUser { user, claims }))
+    Ok(Some(AuthenticatedUser {
+        user: Box::new(user),
+        claims,
+    }))
+}
+
+/// Validate the bearer token of a request without touching the database
+///
+/// Used where extraction has to be synchronous, such as when an MCP session is created.
+pub fn bearer_claims(req: &Request, state: &AppState) -> Option<JWTClaims> {
+    let token = req
+        .headers()
+        .get("authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+
+    let claims = state.sso.as_ref()?.validate_jwt_token(token).ok()?;
+
+    (claims.exp > chrono::Utc::now().timestamp()).then_some(claims)
 }
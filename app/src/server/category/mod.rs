@@ -0,0 +1,113 @@
+use poem::{Result, web::Data};
+use poem_openapi::param::{Path, Query};
+use poem_openapi::{Object, OpenApi, payload::Json};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::models::categories::Category;
+use crate::models::topics::Topic;
+use crate::server::ApiTags;
+use crate::server::auth::OptionalAuthUser;
+use crate::state::AppState;
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct CategoryApi;
+
+#[OpenApi]
+impl CategoryApi {
+    /// /categories
+    ///
+    /// List categories, optionally of a single discourse instance
+    #[oai(path = "/categories", method = "get", tag = "ApiTags::Category")]
+    async fn list(
+        &self,
+        state: Data<&AppState>,
+        auth: OptionalAuthUser,
+        #[oai(style = "simple")] discourse_id: Query<Option<String>>,
+    ) -> Result<Json<Vec<Category>>> {
+        let access = state.discourse.access_for(auth.claims());
+        let categories = Category::find_all(discourse_id.0.as_deref(), &access, &state)
+            .await
+            .map_err(|e| {
+                tracing::error!("Error getting categories: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(categories))
+    }
+
+    /// /c/:discourse_id/:category_id/topics
+    ///
+    /// List topics in a category by latest activity
+    /// This endpoint is paginated, and uses ?page=1 as the first page
+    #[oai(
+        path = "/c/:discourse_id/:category_id/topics",
+        method = "get",
+        tag = "ApiTags::Category"
+    )]
+    async fn topics_by_category(
+        &self,
+        state: Data<&AppState>,
+        auth: OptionalAuthUser,
+        #[oai(style = "simple")] discourse_id: Path<String>,
+        #[oai(style = "simple")] category_id: Path<i32>,
+        #[oai(style = "simple")] page: Query<Option<i32>>,
+    ) -> Result<Json<Vec<Topic>>> {
+        let access = state.discourse.access_for(auth.claims());
+
+        let category = Category::find_by_id(&discourse_id, category_id.0, &state)
+            .await
+            .map_err(|e| {
+                tracing::error!("Error getting category: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+        if !category.is_some_and(|category| category.can_view(&access)) {
+            return Err(poem::Error::from_status(StatusCode::NOT_FOUND));
+        }
+
+        let topics = Topic::get_by_category(
+            &discourse_id,
+            category_id.0,
+            page.0.unwrap_or(1),
+            &access,
+            &state,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Error getting category topics: {:?}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        Ok(Json(topics))
+    }
+
+    /// /tags/:tag/topics
+    ///
+    /// List topics with a tag by latest activity, across all instances unless `discourse_id` is set
+    /// This endpoint is paginated, and uses ?page=1 as the first page
+    #[oai(path = "/tags/:tag/topics", method = "get", tag = "ApiTags::Category")]
+    async fn topics_by_tag(
+        &self,
+        state: Data<&AppState>,
+        auth: OptionalAuthUser,
+        #[oai(style = "simple")] tag: Path<String>,
+        #[oai(style = "simple")] discourse_id: Query<Option<String>>,
+        #[oai(style = "simple")] page: Query<Option<i32>>,
+    ) -> Result<Json<Vec<Topic>>> {
+        let access = state.discourse.access_for(auth.claims());
+        let topics = Topic::get_by_tag(
+            &tag,
+            discourse_id.0.as_deref(),
+            page.0.unwrap_or(1),
+            &access,
+            &state,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Error getting tag topics: {:?}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        Ok(Json(topics))
+    }
+}
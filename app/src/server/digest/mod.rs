@@ -0,0 +1,172 @@
+use std::{num::NonZero, sync::LazyLock};
+
+use chrono::Utc;
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use poem::web::{Data, Html, Path};
+use poem::{Result, handler};
+use poem_openapi::payload::Json;
+use poem_openapi::{Object, OpenApi};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::models::user::digest::{DigestPreferences, DigestPreferencesInput, DigestRecipient};
+use crate::modules::digest;
+use crate::server::ApiTags;
+use crate::server::auth::AuthUser;
+use crate::state::AppState;
+
+/// Digests a user can send themselves on demand, each one is an email
+static SEND_LIMITER: LazyLock<DefaultKeyedRateLimiter<Uuid>> =
+    LazyLock::new(|| RateLimiter::keyed(Quota::per_hour(NonZero::new(5).unwrap())));
+
+pub struct DigestApi;
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct DigestSendResponse {
+    /// `false` when followed topics had no activity in the period
+    pub sent: bool,
+}
+
+#[OpenApi]
+impl DigestApi {
+    /// /digest/preferences
+    ///
+    /// Digest email schedule of the current user, off by default
+    #[oai(path = "/digest/preferences", method = "get", tag = "ApiTags::User")]
+    async fn get_digest_preferences(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+    ) -> Result<Json<DigestPreferences>> {
+        let preferences = DigestPreferences::find_or_create(auth_user.0.user_id(), &state)
+            .await
+            .map_err(|e| {
+                error!("Error finding digest preferences: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(preferences))
+    }
+
+    /// /digest/preferences
+    ///
+    /// Receive a daily or weekly digest of followed topics at `send_hour` UTC, weekly
+    /// digests on `weekday`
+    #[oai(path = "/digest/preferences", method = "put", tag = "ApiTags::User")]
+    async fn update_digest_preferences(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+        payload: Json<DigestPreferencesInput>,
+    ) -> Result<Json<DigestPreferences>> {
+        if payload
+            .send_hour
+            .is_some_and(|hour| !(0..=23).contains(&hour))
+        {
+            return Err(poem::Error::from_string(
+                "send_hour must be between 0 and 23",
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        if payload
+            .weekday
+            .is_some_and(|weekday| !(1..=7).contains(&weekday))
+        {
+            return Err(poem::Error::from_string(
+                "weekday must be between 1 (Monday) and 7 (Sunday)",
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+
+        let preferences = DigestPreferences::upsert(auth_user.0.user_id(), &payload, &state)
+            .await
+            .map_err(|e| {
+                error!("Error updating digest preferences: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(preferences))
+    }
+
+    /// /digest/send
+    ///
+    /// Send the current user their digest now, covering the period since the last one
+    /// Does not move the schedule, useful to check the setup against a local SMTP sink
+    /// Limited to 5 sends per hour
+    #[oai(path = "/digest/send", method = "post", tag = "ApiTags::User")]
+    async fn send_digest(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+    ) -> Result<Json<DigestSendResponse>> {
+        if state.email.is_none() {
+            return Err(poem::Error::from_string(
+                "email is not configured",
+                StatusCode::SERVICE_UNAVAILABLE,
+            ));
+        }
+        if SEND_LIMITER.check_key(&auth_user.0.user_id()).is_err() {
+            return Err(poem::Error::from_string(
+                "too many digests sent, try again later",
+                StatusCode::TOO_MANY_REQUESTS,
+            ));
+        }
+
+        let recipient = DigestRecipient::find_by_user_id(auth_user.0.user_id(), &state)
+            .await
+            .map_err(|e| {
+                error!("Error finding digest recipient: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?
+            .ok_or_else(|| {
+                poem::Error::from_string("no email address on file", StatusCode::BAD_REQUEST)
+            })?;
+
+        let sent = digest::send_digest(&recipient, Utc::now(), &state)
+            .await
+            .map_err(|e| {
+                error!("Error sending digest: {:?}", e);
+                poem::Error::from_status(StatusCode::BAD_GATEWAY)
+            })?;
+
+        Ok(Json(DigestSendResponse { sent }))
+    }
+}
+
+/// /digest/unsubscribe/:token
+///
+/// Confirmation page of the unsubscribe link in digest emails, unsubscribing takes
+/// a POST so link scanners don't unsubscribe by following it
+#[handler]
+pub async fn unsubscribe_page(Path(token): Path<Uuid>) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Unsubscribe</title></head><body style=\"font-family:sans-serif;max-width:480px;margin:64px auto;\"><h1>Unsubscribe from digests</h1><p>You will no longer receive digest emails.</p><form method=\"post\" action=\"/digest/unsubscribe/{}\"><button type=\"submit\">Unsubscribe</button></form></body></html>",
+        token
+    ))
+}
+
+/// /digest/unsubscribe/:token
+///
+/// Turns digests off, also the RFC 8058 one-click target of `List-Unsubscribe-Post`
+#[handler]
+pub async fn unsubscribe(
+    Path(token): Path<Uuid>,
+    state: Data<&AppState>,
+) -> Result<Html<&'static str>> {
+    let unsubscribed = DigestPreferences::unsubscribe(token, &state)
+        .await
+        .map_err(|e| {
+            error!("Error unsubscribing from digests: {:?}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+    if !unsubscribed {
+        return Err(poem::Error::from_status(StatusCode::NOT_FOUND));
+    }
+
+    Ok(Html(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Unsubscribed</title></head><body style=\"font-family:sans-serif;max-width:480px;margin:64px auto;\"><h1>Unsubscribed</h1><p>You will no longer receive digest emails. You can turn them back on in your settings.</p></body></html>",
+    ))
+}
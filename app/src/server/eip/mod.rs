@@ -0,0 +1,43 @@
+use poem::{Result, web::Data};
+use poem_openapi::param::{Path, Query};
+use poem_openapi::{Object, OpenApi, payload::Json};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::models::eips::EipMention;
+use crate::server::ApiTags;
+use crate::server::auth::OptionalAuthUser;
+use crate::state::AppState;
+
+const MENTIONS_PER_PAGE: i64 = 20;
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct EipApi;
+
+#[OpenApi]
+impl EipApi {
+    /// /eips/:number/mentions
+    ///
+    /// List topics mentioning an EIP or ERC, most recently mentioned first
+    /// This endpoint is paginated, and uses ?page=1 as the first page
+    #[oai(path = "/eips/:number/mentions", method = "get", tag = "ApiTags::Eip")]
+    async fn mentions(
+        &self,
+        state: Data<&AppState>,
+        auth: OptionalAuthUser,
+        #[oai(style = "simple")] number: Path<i32>,
+        #[oai(style = "simple")] page: Query<Option<i32>>,
+    ) -> Result<Json<Vec<EipMention>>> {
+        let access = state.discourse.access_for(auth.claims());
+        let offset = (page.0.unwrap_or(1).max(1) as i64 - 1) * MENTIONS_PER_PAGE;
+
+        let mentions = EipMention::find_by_eip(number.0, MENTIONS_PER_PAGE, offset, &access, &state)
+            .await
+            .map_err(|e| {
+                tracing::error!("Error finding EIP mentions: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(mentions))
+    }
+}
@@ -8,19 +8,54 @@ use poem_mcpserver::{
 use crate::{
     models::{
         discourse::user::{DiscourseUserProfile, DiscourseUserSummaryResponse},
-        topics::{post::Post, Topic},
+        eips::EipMention,
+        topics::{post::Post, Topic, TopicAccess},
     },
-    modules::discourse::{ForumSearchDocument, LResult},
+    modules::{
+        discourse::{ForumSearchDocument, LResult},
+        search::{SearchEntityType, SearchQuery},
+    },
+    server::auth::bearer_claims,
     state::AppState,
 };
 
 pub struct ForumTools {
     state: AppState,
+    /// Restricted topics the session may read, resolved from its bearer token
+    access: TopicAccess,
 }
 
 impl ForumTools {
-    pub fn new(state: AppState) -> Self {
-        Self { state }
+    pub fn new(state: AppState, access: TopicAccess) -> Self {
+        Self { state, access }
+    }
+
+    /// Whether the session may read the topic, unknown topics count as not visible
+    async fn can_view_topic(&self, discourse_id: &str, topic_id: i32) -> bool {
+        match Topic::get_by_topic_id(discourse_id, topic_id, &self.state).await {
+            Ok(topic) => self.access.can_view(&topic),
+            Err(_) => false,
+        }
+    }
+
+    /// Search through the configured backend, failures are returned as an error document
+    async fn search(&self, query: SearchQuery) -> Json<Vec<ForumSearchDocument>> {
+        match self.state.search.search(&query, &self.access).await {
+            Ok(results) => Json(
+                results
+                    .documents
+                    .into_iter()
+                    .map(|hit| hit.document)
+                    .collect(),
+            ),
+            Err(err) => Json(vec![Self::create_error_document(
+                format!("search error: {err}"),
+                query.discourse_id,
+                query.topic_id,
+                query.user_id,
+                None,
+            )]),
+        }
     }
 
     /// Create a standardized error response for Meilisearch operations
@@ -43,7 +78,14 @@ impl ForumTools {
             slug: None,
             pm_issue: None,
             cooked: Some(error_message),
+            visibility: None,
+            category_id: None,
+            tags: None,
+            created_at: None,
+            created_year: None,
+            updated_at: None,
             entity_id: "error".to_string(),
+            vectors: None,
         }
     }
 }
@@ -72,6 +114,10 @@ impl ForumTools {
     /// - "What is topic 1234 about?" → Use this tool with topic_id=1234
     /// - "Can you summarize the discussion on EIP-4844?" → First search for the topic, then summarize it
     async fn get_topic_summary(&self, discourse_id: String, topic_id: i32) -> Text<String> {
+        if !self.can_view_topic(&discourse_id, topic_id).await {
+            return Text("error: topic not found".to_string());
+        }
+
         match Topic::get_summary_by_topic_id(&discourse_id, topic_id, &self.state).await {
             Ok(summary) => Text(summary.summary_text),
             Err(err) => Text(format!("error: {err}")),
@@ -111,8 +157,16 @@ impl ForumTools {
         size: Option<i32>,
     ) -> Json<Vec<Post>> {
         let page = page.unwrap_or(1);
-        match Post::find_by_topic_id(&discourse_id, topic_id, page, size, &self.state).await {
-            Ok((posts, _)) => Json(posts),
+        let posts = if self.can_view_topic(&discourse_id, topic_id).await {
+            Post::find_by_topic_id(&discourse_id, topic_id, page, size, &self.state)
+                .await
+                .map_err(|err| err.to_string())
+        } else {
+            Err("topic not found".to_string())
+        };
+
+        match posts.map(|(posts, _)| posts) {
+            Ok(posts) => Json(posts),
             Err(err) => Json(vec![Post {
                 discourse_id,
                 post_id: -1,
@@ -124,6 +178,8 @@ impl ForumTools {
                 cooked: Some(format!("error: {err}")),
                 post_url: None,
                 extra: None,
+                version: 0,
+                deleted_at: None,
             }]),
         }
     }
@@ -151,6 +207,10 @@ impl ForumTools {
     /// - query (required): Search terms or phrases
     /// - limit (optional, default=20): Maximum number of results to return
     /// - offset (optional, default=0): Number of results to skip (for pagination)
+    /// - semantic_ratio (optional, default=0.5): How much results are ranked by meaning rather
+    ///   than exact keywords, from 0.0 (keywords only) to 1.0 (meaning only). Raise it for
+    ///   paraphrased questions like "blob fee market", lower it for exact terms like "EIP-4844".
+    ///   Ignored when semantic search is not enabled on the server.
     ///
     /// **Output**: Array of forum documents (both topics and posts) ranked by relevance
     ///
@@ -163,41 +223,69 @@ impl ForumTools {
         query: String,
         limit: Option<usize>,
         offset: Option<usize>,
+        semantic_ratio: Option<f32>,
     ) -> Json<Vec<ForumSearchDocument>> {
-        let Some(meili) = &self.state.meili else {
-            return Json(vec![Self::create_error_document(
-                "Meilisearch is not configured".to_string(),
-                None,
-                None,
-                None,
-                None,
-            )]);
-        };
-
-        let forum = meili.index("forum");
-        let limit = limit.unwrap_or(20);
-        let offset = offset.unwrap_or(0);
+        self.search(SearchQuery {
+            query,
+            semantic_ratio,
+            offset: offset.unwrap_or(0),
+            limit: limit.unwrap_or(20),
+            ..Default::default()
+        })
+        .await
+    }
 
-        match forum
-            .search()
-            .with_query(&query)
-            .with_limit(limit)
-            .with_offset(offset)
-            .execute::<ForumSearchDocument>()
-            .await
-        {
-            Ok(results) => {
-                let documents: Vec<ForumSearchDocument> =
-                    results.hits.into_iter().map(|hit| hit.result).collect();
-                Json(documents)
-            }
-            Err(err) => Json(vec![Self::create_error_document(
-                format!("search error: {err}"),
-                None,
-                None,
-                None,
-                None,
-            )]),
+    /// **Search Facets** - Count how the matches of a search are distributed, without returning them.
+    ///
+    /// **Purpose**: This tool shows at a glance where a subject is discussed: on which forum, as
+    /// topics or posts, by which authors, for which Protocol Meeting issue and in which years.
+    ///
+    /// **When to use**:
+    /// - User asks who discusses a subject the most, or where it is discussed
+    /// - User asks when a subject was discussed, or how interest changed over the years
+    /// - You want to narrow a broad search before calling search_forum or search_by_username
+    ///
+    /// **Parameters**:
+    /// - query (required): Search terms or phrases, an empty query counts all content
+    /// - discourse_id (optional): Only count content of this discourse instance
+    /// - username (optional): Only count posts by this user
+    /// - pm_issue (optional): Only count topics linked to this Protocol Meeting issue
+    ///
+    /// **Output**: Match counts per value of discourse_id, entity_type, username, pm_issue and
+    /// created_year, most frequent first
+    ///
+    /// **Example usage**:
+    /// - "Who talks most about EIP-7702?" → search_facets(query="EIP-7702"), then read `username`
+    /// - "When was statelessness discussed?" → search_facets(query="statelessness"), then read `created_year`
+    async fn search_facets(
+        &self,
+        query: String,
+        discourse_id: Option<String>,
+        username: Option<String>,
+        pm_issue: Option<i32>,
+    ) -> Text<String> {
+        let results = self
+            .state
+            .search
+            .search(
+                &SearchQuery {
+                    query,
+                    discourse_id,
+                    username,
+                    pm_issue,
+                    facets: true,
+                    limit: 0,
+                    ..Default::default()
+                },
+                &self.access,
+            )
+            .await;
+
+        match results {
+            Ok(results) => Text(
+                serde_json::to_string(&results.facets.unwrap_or_default()).unwrap_or_default(),
+            ),
+            Err(err) => Text(format!("search error: {err}")),
         }
     }
 
@@ -229,42 +317,14 @@ impl ForumTools {
         limit: Option<usize>,
         offset: Option<usize>,
     ) -> Json<Vec<ForumSearchDocument>> {
-        let Some(meili) = &self.state.meili else {
-            return Json(vec![Self::create_error_document(
-                "Meilisearch is not configured".to_string(),
-                None,
-                None,
-                None,
-                None,
-            )]);
-        };
-
-        let forum = meili.index("forum");
-        let limit = limit.unwrap_or(20);
-        let offset = offset.unwrap_or(0);
-
-        match forum
-            .search()
-            .with_query(&query)
-            .with_filter("entity_type = topic")
-            .with_limit(limit)
-            .with_offset(offset)
-            .execute::<ForumSearchDocument>()
-            .await
-        {
-            Ok(results) => {
-                let documents: Vec<ForumSearchDocument> =
-                    results.hits.into_iter().map(|hit| hit.result).collect();
-                Json(documents)
-            }
-            Err(err) => Json(vec![Self::create_error_document(
-                format!("search error: {err}"),
-                None,
-                None,
-                None,
-                None,
-            )]),
-        }
+        self.search(SearchQuery {
+            query,
+            entity_type: Some(SearchEntityType::Topic),
+            offset: offset.unwrap_or(0),
+            limit: limit.unwrap_or(20),
+            ..Default::default()
+        })
+        .await
     }
 
     /// **Search Posts Only** - Search specifically within post content across all topics.
@@ -295,42 +355,14 @@ impl ForumTools {
         limit: Option<usize>,
         offset: Option<usize>,
     ) -> Json<Vec<ForumSearchDocument>> {
-        let Some(meili) = &self.state.meili else {
-            return Json(vec![Self::create_error_document(
-                "Meilisearch is not configured".to_string(),
-                None,
-                None,
-                None,
-                None,
-            )]);
-        };
-
-        let forum = meili.index("forum");
-        let limit = limit.unwrap_or(20);
-        let offset = offset.unwrap_or(0);
-
-        match forum
-            .search()
-            .with_query(&query)
-            .with_filter("entity_type = post")
-            .with_limit(limit)
-            .with_offset(offset)
-            .execute::<ForumSearchDocument>()
-            .await
-        {
-            Ok(results) => {
-                let documents: Vec<ForumSearchDocument> =
-                    results.hits.into_iter().map(|hit| hit.result).collect();
-                Json(documents)
-            }
-            Err(err) => Json(vec![Self::create_error_document(
-                format!("search error: {err}"),
-                None,
-                None,
-                None,
-                None,
-            )]),
-        }
+        self.search(SearchQuery {
+            query,
+            entity_type: Some(SearchEntityType::Post),
+            offset: offset.unwrap_or(0),
+            limit: limit.unwrap_or(20),
+            ..Default::default()
+        })
+        .await
     }
 
     /// **Search Posts in Topic** - Search for posts within a specific topic/discussion.
@@ -367,44 +399,16 @@ impl ForumTools {
         limit: Option<usize>,
         offset: Option<usize>,
     ) -> Json<Vec<ForumSearchDocument>> {
-        let Some(meili) = &self.state.meili else {
-            return Json(vec![Self::create_error_document(
-                "Meilisearch is not configured".to_string(),
-                Some(discourse_id),
-                Some(topic_id),
-                None,
-                None,
-            )]);
-        };
-
-        let forum = meili.index("forum");
-        let limit = limit.unwrap_or(20);
-        let offset = offset.unwrap_or(0);
-
-        let filter = format!("entity_type = post AND topic_id = {}", topic_id);
-
-        match forum
-            .search()
-            .with_query(&query)
-            .with_filter(&filter)
-            .with_limit(limit)
-            .with_offset(offset)
-            .execute::<ForumSearchDocument>()
-            .await
-        {
-            Ok(results) => {
-                let documents: Vec<ForumSearchDocument> =
-                    results.hits.into_iter().map(|hit| hit.result).collect();
-                Json(documents)
-            }
-            Err(err) => Json(vec![Self::create_error_document(
-                format!("search error: {err}"),
-                Some(discourse_id),
-                Some(topic_id),
-                None,
-                None,
-            )]),
-        }
+        self.search(SearchQuery {
+            query,
+            discourse_id: Some(discourse_id),
+            entity_type: Some(SearchEntityType::Post),
+            topic_id: Some(topic_id),
+            offset: offset.unwrap_or(0),
+            limit: limit.unwrap_or(20),
+            ..Default::default()
+        })
+        .await
     }
 
     /// **Search by User ID** - Find all forum content (topics and posts) created by a specific user.
@@ -436,55 +440,15 @@ impl ForumTools {
         limit: Option<usize>,
         offset: Option<usize>,
     ) -> Json<Vec<ForumSearchDocument>> {
-        let Some(meili) = &self.state.meili else {
-            return Json(vec![Self::create_error_document(
-                "Meilisearch is not configured".to_string(),
-                Some(discourse_id),
-                None,
-                Some(user_id),
-                None,
-            )]);
-        };
-
-        let forum = meili.index("forum");
-        let limit = limit.unwrap_or(20);
-        let offset = offset.unwrap_or(0);
-
-        let filter = format!("user_id = {} AND discourse_id = {}", user_id, discourse_id);
-
-        let result = if let Some(q) = query {
-            forum
-                .search()
-                .with_filter(&filter)
-                .with_limit(limit)
-                .with_offset(offset)
-                .with_query(&q)
-                .execute::<ForumSearchDocument>()
-                .await
-        } else {
-            forum
-                .search()
-                .with_filter(&filter)
-                .with_limit(limit)
-                .with_offset(offset)
-                .execute::<ForumSearchDocument>()
-                .await
-        };
-
-        match result {
-            Ok(results) => {
-                let documents: Vec<ForumSearchDocument> =
-                    results.hits.into_iter().map(|hit| hit.result).collect();
-                Json(documents)
-            }
-            Err(err) => Json(vec![Self::create_error_document(
-                format!("search error: {err}"),
-                Some(discourse_id),
-                None,
-                Some(user_id),
-                None,
-            )]),
-        }
+        self.search(SearchQuery {
+            query: query.unwrap_or_default(),
+            discourse_id: Some(discourse_id),
+            user_id: Some(user_id),
+            offset: offset.unwrap_or(0),
+            limit: limit.unwrap_or(20),
+            ..Default::default()
+        })
+        .await
     }
 
     /// **Get User Profile** - Retrieve detailed profile information for a forum user by username.
@@ -675,8 +639,58 @@ impl ForumTools {
         self.search_by_username(discourse_id, clean_username, query, limit, offset)
             .await
     }
+
+    /// **Find EIP Mentions** - List the topics where a specific EIP or ERC has been discussed.
+    ///
+    /// **Purpose**: Every post is scanned for references such as "EIP-4844", "ERC-20" or links to
+    /// eips.ethereum.org while indexing. This tool returns the topics containing those references,
+    /// which is more precise than a full-text search for the number.
+    ///
+    /// **When to use**:
+    /// - User asks where or when an EIP/ERC has been discussed
+    /// - User wants to find the discussions that shaped a specific proposal
+    /// - You need topic IDs of discussions about a proposal for get_posts or get_topic_summary
+    ///
+    /// **Parameters**:
+    /// - eip (required): The EIP or ERC number, e.g. 7702 for EIP-7702
+    /// - limit (optional, default=20): Maximum number of topics to return
+    /// - offset (optional, default=0): Number of topics to skip
+    ///
+    /// **Output**: Array of topics with the number of mentions and the post numbers mentioning the EIP,
+    /// most recently mentioned first
+    ///
+    /// **Example usage**:
+    /// - "Where has EIP-7702 been discussed?" → find_eip_mentions(eip=7702)
+    /// - "Which threads talk about ERC-4337?" → find_eip_mentions(eip=4337)
+    async fn find_eip_mentions(
+        &self,
+        eip: i32,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Json<Vec<EipMention>> {
+        match EipMention::find_by_eip(
+            eip,
+            limit.unwrap_or(20),
+            offset.unwrap_or(0),
+            &self.access,
+            &self.state,
+        )
+        .await
+        {
+            Ok(mentions) => Json(mentions),
+            Err(err) => {
+                tracing::error!("Error finding EIP mentions: {:?}", err);
+                Json(vec![])
+            }
+        }
+    }
 }
 
 pub fn endpoint(state: AppState) -> impl IntoEndpoint {
-    streamable_http::endpoint(move |_| McpServer::new().tools(ForumTools::new(state.clone())))
+    streamable_http::endpoint(move |req| {
+        let claims = bearer_claims(req, &state);
+        let access = state.discourse.access_for(claims.as_ref());
+
+        McpServer::new().tools(ForumTools::new(state.clone(), access))
+    })
 }
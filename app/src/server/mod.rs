@@ -1,12 +1,16 @@
 use admin::AdminApi;
+use category::CategoryApi;
+use digest::DigestApi;
+use eip::EipApi;
 use events::EventsApi;
 use governor::Quota;
+use notification::NotificationApi;
 use opengraph::OpenGraph;
 use pm::PMApi;
 use poem::{
     EndpointExt, Route, Server,
     endpoint::StaticFilesEndpoint,
-    get, handler,
+    get, handler, post,
     listener::TcpListener,
     middleware::{Cors, OpenTelemetryMetrics},
 };
@@ -18,27 +22,39 @@ use tracing::info;
 use user::UserApi;
 
 use crate::{
-    server::{search::SearchApi, workshop::WorkshopApi},
+    server::{
+        search::{SearchApi, saved::SavedSearchApi},
+        workshop::WorkshopApi,
+    },
     state::AppState,
 };
 // use tracing_mw::TraceId;
 
 pub mod admin;
 pub mod auth;
+pub mod category;
+pub mod digest;
+pub mod eip;
 pub mod events;
 pub mod mcp;
+pub mod notification;
 pub mod opengraph;
 pub mod pm;
 pub mod ratelimit;
 pub mod search;
 pub mod topic;
 pub mod user;
+pub mod webhook;
 pub mod workshop;
 
 #[derive(Tags)]
 pub enum ApiTags {
     /// Topic Related Operations
     Topic,
+    /// Category and Tag Related Operations
+    Category,
+    /// EIP Related Operations
+    Eip,
     /// User Related Operations
     User,
     /// Events Related Operations
@@ -54,11 +70,16 @@ pub enum ApiTags {
 fn get_api(_state: AppState) -> impl OpenApi {
     (
         TopicApi,
+        CategoryApi,
+        EipApi,
         UserApi,
+        NotificationApi,
+        DigestApi,
         EventsApi,
         PMApi,
         WorkshopApi,
         SearchApi,
+        SavedSearchApi,
         AdminApi,
     )
 }
@@ -98,6 +119,12 @@ pub async fn start_http(state: AppState) {
         .nest("/docs", get(get_openapi_docs))
         .nest("/api", api_service)
         .nest("/mcp", mcp::endpoint(state.clone()))
+        .at("/webhooks/discourse/:discourse_id", post(webhook::discourse_webhook))
+        .at("/feeds/searches/:feed_token", get(search::saved::saved_search_feed))
+        .at(
+            "/digest/unsubscribe/:token",
+            get(digest::unsubscribe_page).post(digest::unsubscribe),
+        )
         .data(state)
         .with(Cors::new());
 
@@ -0,0 +1,209 @@
+use chrono::{DateTime, Utc};
+use poem::Result;
+use poem::web::Data;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::Json;
+use poem_openapi::{Object, OpenApi};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::models::topics::Topic;
+use crate::models::user::notification::Notification;
+use crate::models::user::subscription::{Subscription, SubscriptionInput};
+use crate::server::ApiTags;
+use crate::server::auth::AuthUser;
+use crate::state::AppState;
+
+const DEFAULT_NOTIFICATION_LIMIT: i64 = 50;
+const MAX_NOTIFICATION_LIMIT: i64 = 200;
+
+pub struct NotificationApi;
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct MarkNotificationsReadInput {
+    /// Only these notifications, all when omitted
+    pub notification_ids: Option<Vec<i64>>,
+    /// Only notifications created up to then, pass the newest `created_at` fetched so
+    /// notifications created in the meantime stay unread
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct MarkNotificationsReadResponse {
+    pub marked: u64,
+}
+
+#[OpenApi]
+impl NotificationApi {
+    /// /subscriptions
+    ///
+    /// Topics and Discourse users followed by the current user
+    #[oai(path = "/subscriptions", method = "get", tag = "ApiTags::User")]
+    async fn list_subscriptions(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+    ) -> Result<Json<Vec<Subscription>>> {
+        let subscriptions = Subscription::find_by_user_id(auth_user.0.user_id(), &state)
+            .await
+            .map_err(|e| {
+                error!("Error listing subscriptions: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(subscriptions))
+    }
+
+    /// /subscriptions
+    ///
+    /// Follow a topic or a Discourse user, exactly one of `topic_id` and `username`
+    /// Subscribing again to the same target updates `muted`
+    #[oai(path = "/subscriptions", method = "post", tag = "ApiTags::User")]
+    async fn subscribe(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+        payload: Json<SubscriptionInput>,
+    ) -> Result<Json<Subscription>> {
+        let input = payload.0;
+        if input.topic_id.is_some() == input.username.is_some() {
+            return Err(poem::Error::from_string(
+                "exactly one of topic_id and username is required",
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        if state.discourse.get_client(&input.discourse_id).is_none() {
+            return Err(poem::Error::from_status(StatusCode::NOT_FOUND));
+        }
+
+        let access = state.discourse.access_for(Some(&auth_user.0.claims));
+        if let Some(topic_id) = input.topic_id {
+            let topic = Topic::get_by_topic_id(&input.discourse_id, topic_id, &state)
+                .await
+                .map_err(|_| poem::Error::from_status(StatusCode::NOT_FOUND))?;
+            if !access.can_view(&topic) {
+                return Err(poem::Error::from_status(StatusCode::NOT_FOUND));
+            }
+        }
+
+        let subscription = Subscription::upsert(auth_user.0.user_id(), &input, &state)
+            .await
+            .map_err(|e| {
+                error!("Error subscribing: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(subscription))
+    }
+
+    /// /subscriptions/:subscription_id/mute
+    ///
+    /// Mute a subscription, or unmute it with `muted=false`
+    #[oai(path = "/subscriptions/:subscription_id/mute", method = "post", tag = "ApiTags::User")]
+    async fn mute_subscription(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+        #[oai(style = "simple")] subscription_id: Path<Uuid>,
+        #[oai(style = "simple")] muted: Query<Option<bool>>,
+    ) -> Result<Json<Subscription>> {
+        let subscription = Subscription::set_muted(
+            subscription_id.0,
+            auth_user.0.user_id(),
+            muted.0.unwrap_or(true),
+            &state,
+        )
+        .await
+        .map_err(|e| {
+            error!("Error muting subscription: {:?}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?
+        .ok_or_else(|| poem::Error::from_status(StatusCode::NOT_FOUND))?;
+
+        Ok(Json(subscription))
+    }
+
+    /// /subscriptions/:subscription_id
+    ///
+    /// Unsubscribe, existing notifications are kept
+    #[oai(path = "/subscriptions/:subscription_id", method = "delete", tag = "ApiTags::User")]
+    async fn unsubscribe(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+        #[oai(style = "simple")] subscription_id: Path<Uuid>,
+    ) -> Result<()> {
+        let deleted = Subscription::delete(subscription_id.0, auth_user.0.user_id(), &state)
+            .await
+            .map_err(|e| {
+                error!("Error unsubscribing: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        if !deleted {
+            return Err(poem::Error::from_status(StatusCode::NOT_FOUND));
+        }
+
+        Ok(())
+    }
+
+    /// /notifications
+    ///
+    /// New posts, edits and summaries in followed topics and by followed users, newest first
+    /// `unread` (default true) limits them to unread notifications
+    #[oai(path = "/notifications", method = "get", tag = "ApiTags::User")]
+    async fn list_notifications(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+        #[oai(style = "simple")] unread: Query<Option<bool>>,
+        #[oai(style = "simple")] limit: Query<Option<i64>>,
+    ) -> Result<Json<Vec<Notification>>> {
+        let access = state.discourse.access_for(Some(&auth_user.0.claims));
+        let limit = limit
+            .0
+            .unwrap_or(DEFAULT_NOTIFICATION_LIMIT)
+            .clamp(1, MAX_NOTIFICATION_LIMIT);
+        let notifications = Notification::find_by_user_id(
+            auth_user.0.user_id(),
+            unread.0.unwrap_or(true),
+            &access,
+            limit,
+            &state,
+        )
+        .await
+        .map_err(|e| {
+            error!("Error listing notifications: {:?}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        Ok(Json(notifications))
+    }
+
+    /// /notifications/read
+    ///
+    /// Mark notifications as read
+    #[oai(path = "/notifications/read", method = "post", tag = "ApiTags::User")]
+    async fn mark_notifications_read(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+        payload: Json<MarkNotificationsReadInput>,
+    ) -> Result<Json<MarkNotificationsReadResponse>> {
+        let marked = Notification::mark_read(
+            auth_user.0.user_id(),
+            payload.notification_ids.as_deref(),
+            payload.until.unwrap_or_else(Utc::now),
+            &state,
+        )
+        .await
+        .map_err(|e| {
+            error!("Error marking notifications as read: {:?}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        Ok(Json(MarkNotificationsReadResponse { marked }))
+    }
+}
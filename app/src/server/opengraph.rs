@@ -5,7 +5,7 @@ use poem::{Endpoint, Request, Response, middleware::Middleware};
 use regex::Regex;
 use tracing::info;
 
-use crate::models::topics::Topic;
+use crate::models::topics::{Topic, TopicAccess};
 use crate::state::AppState;
 
 #[derive(Clone)]
@@ -63,7 +63,8 @@ where
             if let Some(topic_id) = topic_id {
                 let topic = Topic::get_by_topic_id("magicians", topic_id, &self.state).await;
 
-                if let Ok(topic) = topic {
+                // Link previews are public, never expose restricted topics in them
+                if let Some(topic) = topic.ok().filter(|topic| TopicAccess::public().can_view(topic)) {
                     let first_post = topic.get_first_post(&self.state).await.ok();
 
                     //
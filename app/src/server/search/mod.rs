@@ -1,28 +1,75 @@
-use poem::{web::Data, Result};
-use poem_openapi::{payload::Json, Object, OpenApi};
-use serde::{Deserialize, Serialize};
-use super::ApiTags;
+use chrono::{DateTime, Utc};
+use poem::{Result, web::Data};
+use poem_openapi::param::Query;
+use poem_openapi::{OpenApi, payload::Json};
+use reqwest::StatusCode;
 
+use super::ApiTags;
+use crate::modules::search::{
+    DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE, SearchEntityType, SearchQuery, SearchResponse,
+};
+use crate::server::auth::OptionalAuthUser;
 use crate::state::AppState;
 
-pub struct SearchApi;
+pub mod saved;
 
-#[derive(Clone, Serialize, Deserialize, Object)]
-pub struct SearchResponse {
-
-}
+pub struct SearchApi;
 
 #[OpenApi]
 impl SearchApi {
-
     /// /search
     ///
-    /// Search everything
+    /// Search topics and posts across all forums
+    /// This endpoint is paginated, and uses ?page=1 as the first page
+    /// `from` and `to` limit results to content created within that range
+    /// `semantic_ratio` weighs meaning over keywords from 0.0 to 1.0 when semantic search is enabled
+    /// `facets` counts all matches per forum, type, author, PM issue and year, off by default
     #[oai(path = "/search", method = "get", tag = "ApiTags::Search")]
+    #[allow(clippy::too_many_arguments)]
     async fn search_everything(
         &self,
-        _state: Data<&AppState>,
+        state: Data<&AppState>,
+        auth: OptionalAuthUser,
+        #[oai(style = "simple")] q: Query<String>,
+        #[oai(style = "simple")] discourse_id: Query<Option<String>>,
+        #[oai(style = "simple")] entity_type: Query<Option<SearchEntityType>>,
+        #[oai(style = "simple")] username: Query<Option<String>>,
+        #[oai(style = "simple")] pm_issue: Query<Option<i32>>,
+        #[oai(style = "simple")] from: Query<Option<DateTime<Utc>>>,
+        #[oai(style = "simple")] to: Query<Option<DateTime<Utc>>>,
+        #[oai(style = "simple")] semantic_ratio: Query<Option<f32>>,
+        #[oai(style = "simple")] facets: Query<Option<bool>>,
+        #[oai(style = "simple")] page: Query<Option<usize>>,
+        #[oai(style = "simple")] size: Query<Option<usize>>,
     ) -> Result<Json<SearchResponse>> {
-        todo!()
+        let page = page.0.unwrap_or(1).max(1);
+        let size = size.0.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let access = state.discourse.access_for(auth.claims());
+        let query = SearchQuery {
+            query: q.0,
+            discourse_id: discourse_id.0,
+            entity_type: entity_type.0,
+            username: username.0,
+            pm_issue: pm_issue.0,
+            from: from.0,
+            to: to.0,
+            semantic_ratio: semantic_ratio.0,
+            facets: facets.0.unwrap_or(false),
+            offset: page.saturating_sub(1).saturating_mul(size),
+            limit: size,
+            ..Default::default()
+        };
+
+        let results = state.search.search(&query, &access).await.map_err(|e| {
+            tracing::error!("Error searching forum: {:?}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        Ok(Json(SearchResponse::from_page(
+            query.query,
+            page,
+            size,
+            results,
+        )))
     }
 }
@@ -0,0 +1,312 @@
+use chrono::{DateTime, Utc};
+use poem::{
+    IntoResponse, Response, handler,
+    http::StatusCode,
+    web::{Data, Path},
+};
+use poem_openapi::param::{Path as ApiPath, Query};
+use poem_openapi::{Object, OpenApi, payload::Json};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::models::search::saved::{SavedSearch, SavedSearchInput, SavedSearchMatch};
+use crate::models::topics::TopicAccess;
+use crate::models::user::User;
+use crate::server::ApiTags;
+use crate::server::auth::AuthUser;
+use crate::state::AppState;
+
+/// Saved searches a single user may keep
+const MAX_SAVED_SEARCHES: i64 = 50;
+const DEFAULT_MATCH_LIMIT: i64 = 50;
+const MAX_MATCH_LIMIT: i64 = 200;
+/// Entries in a feed, read or not
+const FEED_SIZE: i64 = 50;
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct MarkReadResponse {
+    pub marked: u64,
+}
+
+pub struct SavedSearchApi;
+
+#[OpenApi]
+impl SavedSearchApi {
+    /// /searches
+    ///
+    /// Saved searches of the current user with their unread match count
+    #[oai(path = "/searches", method = "get", tag = "ApiTags::Search")]
+    async fn list_saved_searches(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+    ) -> poem::Result<Json<Vec<SavedSearch>>> {
+        let searches = SavedSearch::find_by_user_id(auth_user.0.user_id(), &state)
+            .await
+            .map_err(|e| {
+                error!("Error listing saved searches: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(searches))
+    }
+
+    /// /searches
+    ///
+    /// Save a query, posts created from now on that match it are recorded as matches
+    /// The query uses web search syntax, e.g. `"inclusion lists" or EOF`
+    /// Restricted topics match for the forums the user can currently read
+    #[oai(path = "/searches", method = "post", tag = "ApiTags::Search")]
+    async fn create_saved_search(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+        payload: Json<SavedSearchInput>,
+    ) -> poem::Result<Json<SavedSearch>> {
+        let mut input = payload.0;
+        input.query = input.query.trim().to_string();
+        if input.query.is_empty() {
+            return Err(poem::Error::from_string(
+                "query must not be empty",
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+
+        let user_id = auth_user.0.user_id();
+        let count = SavedSearch::count_by_user_id(user_id, &state)
+            .await
+            .map_err(|e| {
+                error!("Error counting saved searches: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+        if count >= MAX_SAVED_SEARCHES {
+            return Err(poem::Error::from_string(
+                format!("at most {} saved searches are allowed", MAX_SAVED_SEARCHES),
+                StatusCode::CONFLICT,
+            ));
+        }
+
+        let search = SavedSearch::create(user_id, &input, &state)
+            .await
+            .map_err(|e| {
+                error!("Error creating saved search: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(search))
+    }
+
+    /// /searches/:search_id
+    ///
+    /// Delete a saved search and its matches
+    #[oai(path = "/searches/:search_id", method = "delete", tag = "ApiTags::Search")]
+    async fn delete_saved_search(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+        #[oai(style = "simple")] search_id: ApiPath<Uuid>,
+    ) -> poem::Result<()> {
+        let deleted = SavedSearch::delete(search_id.0, auth_user.0.user_id(), &state)
+            .await
+            .map_err(|e| {
+                error!("Error deleting saved search: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        if !deleted {
+            return Err(poem::Error::from_status(StatusCode::NOT_FOUND));
+        }
+
+        Ok(())
+    }
+
+    /// /searches/matches
+    ///
+    /// Posts matching the user's saved searches, newest first
+    /// `search_id` limits them to one saved search, `unread` (default true) to unread matches
+    #[oai(path = "/searches/matches", method = "get", tag = "ApiTags::Search")]
+    async fn list_saved_search_matches(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+        #[oai(style = "simple")] search_id: Query<Option<Uuid>>,
+        #[oai(style = "simple")] unread: Query<Option<bool>>,
+        #[oai(style = "simple")] limit: Query<Option<i64>>,
+    ) -> poem::Result<Json<Vec<SavedSearchMatch>>> {
+        let access = state.discourse.access_for(Some(&auth_user.0.claims));
+        let limit = limit.0.unwrap_or(DEFAULT_MATCH_LIMIT).clamp(1, MAX_MATCH_LIMIT);
+        let matches = SavedSearchMatch::find(
+            auth_user.0.user_id(),
+            search_id.0,
+            unread.0.unwrap_or(true),
+            &access,
+            limit,
+            &state,
+        )
+        .await
+        .map_err(|e| {
+            error!("Error listing saved search matches: {:?}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        Ok(Json(matches))
+    }
+
+    /// /searches/matches/read
+    ///
+    /// Mark matches as read, optionally of one saved search only
+    /// `until` only marks matches recorded up to then, pass the newest `matched_at` fetched
+    /// so matches recorded in the meantime stay unread
+    #[oai(path = "/searches/matches/read", method = "post", tag = "ApiTags::Search")]
+    async fn mark_saved_search_matches_read(
+        &self,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
+        #[oai(style = "simple")] search_id: Query<Option<Uuid>>,
+        #[oai(style = "simple")] until: Query<Option<DateTime<Utc>>>,
+    ) -> poem::Result<Json<MarkReadResponse>> {
+        let marked = SavedSearchMatch::mark_read(
+            auth_user.0.user_id(),
+            search_id.0,
+            until.0.unwrap_or_else(Utc::now),
+            &state,
+        )
+        .await
+        .map_err(|e| {
+            error!("Error marking saved search matches as read: {:?}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        Ok(Json(MarkReadResponse { marked }))
+    }
+}
+
+/// /feeds/searches/:feed_token
+///
+/// Atom feed of the latest matches of a saved search. Feed readers can't send a JWT,
+/// the token in the url authorizes it, so this lives outside of the OpenAPI service.
+#[handler]
+pub async fn saved_search_feed(
+    Path(feed_token): Path<Uuid>,
+    state: Data<&AppState>,
+) -> poem::Result<Response> {
+    let search = SavedSearch::find_by_feed_token(feed_token, &state)
+        .await
+        .map_err(|e| {
+            error!("Error finding saved search by feed token: {:?}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?
+        .ok_or_else(|| poem::Error::from_status(StatusCode::NOT_FOUND))?;
+
+    // The token outlives sessions, access is taken from the owner's current sign in
+    let access = User::find_by_id(&state.database.pool, search.user_id)
+        .await
+        .map_err(|e| {
+            error!("Error finding owner of saved search: {:?}", e);
+            poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?
+        .map(|owner| state.discourse.access_for_user(&owner))
+        .unwrap_or_else(TopicAccess::public);
+    let matches = SavedSearchMatch::find(
+        search.user_id,
+        Some(search.search_id),
+        false,
+        &access,
+        FEED_SIZE,
+        &state,
+    )
+    .await
+    .map_err(|e| {
+        error!("Error listing saved search matches: {:?}", e);
+        poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+    })?;
+
+    Ok(atom_feed(&search, &matches, &state.public_url)
+        .with_content_type("application/atom+xml; charset=utf-8")
+        .into_response())
+}
+
+fn atom_feed(search: &SavedSearch, matches: &[SavedSearchMatch], public_url: &str) -> String {
+    let updated = matches
+        .first()
+        .map(|entry| entry.matched_at)
+        .unwrap_or(search.created_at);
+
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n<id>urn:uuid:{}</id>\n<title>{}</title>\n<updated>{}</updated>\n",
+        search.search_id,
+        escape_xml(&search.name),
+        updated.to_rfc3339(),
+    );
+
+    for entry in matches {
+        let url = format!(
+            "{}/t/{}/{}#{}",
+            public_url, entry.discourse_id, entry.topic_id, entry.post_number
+        );
+        feed.push_str(&format!(
+            "<entry>\n<id>{}</id>\n<title>{}</title>\n<link href=\"{}\"/>\n<updated>{}</updated>\n<author><name>{}</name></author>\n<content type=\"html\">{}</content>\n</entry>\n",
+            escape_xml(&url),
+            escape_xml(&entry.title),
+            escape_xml(&url),
+            entry.created_at.unwrap_or(entry.matched_at).to_rfc3339(),
+            escape_xml(entry.username.as_deref().unwrap_or(&entry.discourse_id)),
+            escape_xml(entry.cooked.as_deref().unwrap_or_default()),
+        ));
+    }
+
+    feed.push_str("</feed>\n");
+    feed
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atom_feed() {
+        let search = SavedSearch {
+            search_id: Uuid::nil(),
+            user_id: Uuid::nil(),
+            name: "\"inclusion lists\" & EOF".to_string(),
+            query: "\"inclusion lists\" or EOF".to_string(),
+            discourse_id: None,
+            username: None,
+            pm_issue: None,
+            feed_token: Uuid::nil(),
+            created_at: DateTime::from_timestamp(0, 0).unwrap(),
+            unread: 0,
+        };
+        let matched = SavedSearchMatch {
+            search_id: Uuid::nil(),
+            discourse_id: "magicians".to_string(),
+            topic_id: 12,
+            post_id: 34,
+            post_number: 5,
+            title: "EOF <again>".to_string(),
+            slug: "eof-again".to_string(),
+            username: Some("alice".to_string()),
+            cooked: Some("<p>Ship EOF</p>".to_string()),
+            created_at: DateTime::from_timestamp(60, 0),
+            matched_at: DateTime::from_timestamp(120, 0).unwrap(),
+            read_at: None,
+        };
+
+        let feed = atom_feed(&search, &[matched], "https://ethereum.forum");
+
+        assert!(feed.contains("<title>&quot;inclusion lists&quot; &amp; EOF</title>"));
+        assert!(feed.contains("<updated>1970-01-01T00:02:00+00:00</updated>"));
+        assert!(feed.contains("<link href=\"https://ethereum.forum/t/magicians/12#5\"/>"));
+        assert!(feed.contains("<title>EOF &lt;again&gt;</title>"));
+        assert!(feed.contains("<content type=\"html\">&lt;p&gt;Ship EOF&lt;/p&gt;</content>"));
+    }
+}
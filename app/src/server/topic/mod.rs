@@ -5,8 +5,12 @@ use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
-use crate::models::topics::{post::Post, Topic, TopicSummary};
+use crate::models::crawl::job::IndexPriority;
+use crate::models::eips::TopicEip;
+use crate::models::topics::{link::TopicLink, post::{Post, PostRevision}, CitedTopicSummary, Topic};
+use crate::modules::workshop::provider::ProviderError;
 use crate::server::ApiTags;
+use crate::server::auth::OptionalAuthUser;
 use crate::state::AppState;
 
 #[derive(Debug, Serialize, Deserialize, Object)]
@@ -24,8 +28,9 @@ impl TopicApi {
     ///
     /// List topics by latest activity
     #[oai(path = "/topics", method = "get", tag = "ApiTags::Topic")]
-    async fn list(&self, state: Data<&AppState>) -> Result<Json<Vec<Topic>>> {
-        let topics = Topic::get_by_latest_post_at(&state).await.map_err(|e| {
+    async fn list(&self, state: Data<&AppState>, auth: OptionalAuthUser) -> Result<Json<Vec<Topic>>> {
+        let access = state.discourse.access_for(auth.claims());
+        let topics = Topic::get_by_latest_post_at(&access, &state).await.map_err(|e| {
             tracing::error!("Error getting topics: {:?}", e);
             poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
         })?;
@@ -37,8 +42,9 @@ impl TopicApi {
     ///
     /// List trending topics
     #[oai(path = "/topics/trending", method = "get", tag = "ApiTags::Topic")]
-    async fn trending(&self, state: Data<&AppState>) -> Result<Json<Vec<Topic>>> {
-        let topics = Topic::get_by_trending(&state).await.map_err(|e| {
+    async fn trending(&self, state: Data<&AppState>, auth: OptionalAuthUser) -> Result<Json<Vec<Topic>>> {
+        let access = state.discourse.access_for(auth.claims());
+        let topics = Topic::get_by_trending(&access, &state).await.map_err(|e| {
             tracing::error!("Error getting trending topics: {:?}", e);
             poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
         })?;
@@ -58,16 +64,11 @@ impl TopicApi {
     async fn get_topic(
         &self,
         state: Data<&AppState>,
+        auth: OptionalAuthUser,
         #[oai(style = "simple")] discourse_id: Path<String>,
         #[oai(style = "simple")] topic_id: Path<i32>,
     ) -> Result<Json<Topic>> {
-        let discourse_id = discourse_id.0;
-        let topic = Topic::get_by_topic_id(&discourse_id, topic_id.0, &state)
-            .await
-            .map_err(|e| {
-                tracing::error!("Error getting topic: {:?}", e);
-                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
-            })?;
+        let topic = get_visible_topic(&state, &auth, &discourse_id, topic_id.0).await?;
 
         Ok(Json(topic))
     }
@@ -88,7 +89,14 @@ impl TopicApi {
         #[oai(style = "simple")] topic_id: Path<i32>,
     ) -> Result<Json<serde_json::Value>> {
         info!("Refreshing topic: {} on {}", topic_id.0, discourse_id.0);
-        state.discourse.enqueue(&discourse_id, topic_id.0, 1).await;
+        state
+            .discourse
+            .enqueue(&discourse_id, topic_id.0, 1, IndexPriority::Refresh, &state)
+            .await
+            .map_err(|e| {
+                tracing::error!("Error enqueuing topic refresh: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
 
         Ok(Json(serde_json::json!({})))
     }
@@ -106,6 +114,7 @@ impl TopicApi {
     async fn get_posts(
         &self,
         state: Data<&AppState>,
+        auth: OptionalAuthUser,
         #[oai(style = "simple")] discourse_id: Path<String>,
         #[oai(style = "simple")] topic_id: Path<i32>,
         #[oai(style = "simple")] page: Query<i32>,
@@ -115,6 +124,8 @@ impl TopicApi {
         let topic_id = topic_id.0;
         let page = page.0;
 
+        get_visible_topic(&state, &auth, &discourse_id, topic_id).await?;
+
         let (posts, has_more) = Post::find_by_topic_id(&discourse_id, topic_id, page, size.0, &state)
             .await
             .map_err(|e| {
@@ -125,9 +136,101 @@ impl TopicApi {
         Ok(Json(PostsResponse { posts, has_more }))
     }
 
+    /// /t/:discourse_id/:topic_id/posts/:post_number/revisions
+    ///
+    /// Get every recorded version of a post, oldest first
+    /// Deleted posts keep their history
+    #[oai(
+        path = "/t/:discourse_id/:topic_id/posts/:post_number/revisions",
+        method = "get",
+        operation_id = "get_post_revisions",
+        tag = "ApiTags::Topic"
+    )]
+    async fn get_post_revisions(
+        &self,
+        state: Data<&AppState>,
+        auth: OptionalAuthUser,
+        #[oai(style = "simple")] discourse_id: Path<String>,
+        #[oai(style = "simple")] topic_id: Path<i32>,
+        #[oai(style = "simple")] post_number: Path<i32>,
+    ) -> Result<Json<Vec<PostRevision>>> {
+        get_visible_topic(&state, &auth, &discourse_id, topic_id.0).await?;
+
+        let revisions = Post::find_revisions(&discourse_id, topic_id.0, post_number.0, &state)
+            .await
+            .map_err(|e| {
+                tracing::error!("Error finding post revisions: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        if revisions.is_empty() {
+            return Err(poem::Error::from_status(StatusCode::NOT_FOUND));
+        }
+
+        Ok(Json(revisions))
+    }
+
+    /// /t/:discourse_id/:topic_id/links
+    ///
+    /// Get the GitHub issues, pull requests and EIPs referenced by a topic
+    #[oai(
+        path = "/t/:discourse_id/:topic_id/links",
+        method = "get",
+        operation_id = "get_topic_links",
+        tag = "ApiTags::Topic"
+    )]
+    async fn get_links(
+        &self,
+        state: Data<&AppState>,
+        auth: OptionalAuthUser,
+        #[oai(style = "simple")] discourse_id: Path<String>,
+        #[oai(style = "simple")] topic_id: Path<i32>,
+    ) -> Result<Json<Vec<TopicLink>>> {
+        get_visible_topic(&state, &auth, &discourse_id, topic_id.0).await?;
+
+        let links = TopicLink::find_by_topic(&discourse_id, topic_id.0, &state)
+            .await
+            .map_err(|e| {
+                tracing::error!("Error finding topic links: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(links))
+    }
+
+    /// /t/:discourse_id/:topic_id/eips
+    ///
+    /// Get the EIPs and ERCs mentioned in a topic, most mentioned first
+    #[oai(
+        path = "/t/:discourse_id/:topic_id/eips",
+        method = "get",
+        operation_id = "get_topic_eips",
+        tag = "ApiTags::Topic"
+    )]
+    async fn get_eips(
+        &self,
+        state: Data<&AppState>,
+        auth: OptionalAuthUser,
+        #[oai(style = "simple")] discourse_id: Path<String>,
+        #[oai(style = "simple")] topic_id: Path<i32>,
+    ) -> Result<Json<Vec<TopicEip>>> {
+        get_visible_topic(&state, &auth, &discourse_id, topic_id.0).await?;
+
+        let eips = TopicEip::find_by_topic(&discourse_id, topic_id.0, &state)
+            .await
+            .map_err(|e| {
+                tracing::error!("Error finding topic EIPs: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        Ok(Json(eips))
+    }
+
     /// /t/:discourse_id/:topic_id/summary
     ///
     /// Get summaries from topic
+    ///
+    /// Includes the claims of the summary with the post_numbers that back them
     #[oai(
         path = "/t/:discourse_id/:topic_id/summary",
         method = "get",
@@ -137,18 +240,56 @@ impl TopicApi {
     async fn get_summary(
         &self,
         state: Data<&AppState>,
+        auth: OptionalAuthUser,
         #[oai(style = "simple")] discourse_id: Path<String>,
         #[oai(style = "simple")] topic_id: Path<i32>,
-    ) -> Result<Json<TopicSummary>> {
+    ) -> Result<Json<CitedTopicSummary>> {
         let topic_id = topic_id.0;
 
+        get_visible_topic(&state, &auth, &discourse_id, topic_id).await?;
+
         let summary = Topic::get_summary_by_topic_id(&discourse_id, topic_id, &state)
             .await
-            .map_err(|e| {
-                tracing::error!("Error getting topic summary: {:?}", e);
-                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
-            })?;
+            .map_err(summary_error)?;
+
+        let summary = CitedTopicSummary::from_summary(summary, &state)
+            .await
+            .map_err(|e| summary_error(e.into()))?;
 
         Ok(Json(summary))
     }
 }
+
+/// 503 when a summary is missing and no model provider is configured to write it
+pub fn summary_error(e: opentelemetry_http::HttpError) -> poem::Error {
+    if let Some(ProviderError::Disabled) = e.downcast_ref::<ProviderError>() {
+        return poem::Error::from_string(e.to_string(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    tracing::error!("Error getting topic summary: {:?}", e);
+    poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Load a topic, answering 404 for topics the viewer may not see
+pub async fn get_visible_topic(
+    state: &AppState,
+    auth: &OptionalAuthUser,
+    discourse_id: &str,
+    topic_id: i32,
+) -> Result<Topic> {
+    let topic = Topic::get_by_topic_id(discourse_id, topic_id, state)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => poem::Error::from_status(StatusCode::NOT_FOUND),
+            e => {
+                tracing::error!("Error getting topic: {:?}", e);
+                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        })?;
+
+    if !state.discourse.access_for(auth.claims()).can_view(&topic) {
+        return Err(poem::Error::from_status(StatusCode::NOT_FOUND));
+    }
+
+    Ok(topic)
+}
@@ -0,0 +1,52 @@
+use poem::{
+    Body, Request, Result, handler,
+    http::StatusCode,
+    web::{Data, Path},
+};
+use tracing::warn;
+
+use crate::{
+    modules::discourse::webhook::{EVENT_HEADER, SIGNATURE_HEADER, WebhookError},
+    state::AppState,
+};
+
+/// Largest payload read before the signature is checked, posts with long content fit easily
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// /webhooks/discourse/:discourse_id
+///
+/// Receives Discourse webhooks, the signature is checked against the raw body so
+/// this lives outside of the OpenAPI service and its rate limiter.
+#[handler]
+pub async fn discourse_webhook(
+    req: &Request,
+    Path(discourse_id): Path<String>,
+    state: Data<&AppState>,
+    body: Body,
+) -> Result<StatusCode> {
+    let event = req.header(EVENT_HEADER).unwrap_or_default();
+    let signature = req.header(SIGNATURE_HEADER);
+    // Unsigned requests could otherwise make us buffer any amount of data, answers 413
+    let body = body.into_bytes_limit(MAX_BODY_SIZE).await?;
+
+    match state
+        .discourse
+        .handle_webhook(&discourse_id, event, signature, &body, &state)
+        .await
+    {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => {
+            warn!("Rejected {} webhook for {}: {}", event, discourse_id, e);
+
+            let status = match e {
+                WebhookError::UnknownInstance(_) | WebhookError::NotEnabled(_) => {
+                    StatusCode::NOT_FOUND
+                }
+                WebhookError::InvalidSignature => StatusCode::UNAUTHORIZED,
+                WebhookError::InvalidPayload(_) => StatusCode::BAD_REQUEST,
+                WebhookError::Index(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Err(poem::Error::from_status(status))
+        }
+    }
+}
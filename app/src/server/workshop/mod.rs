@@ -1,4 +1,7 @@
-use crate::models::topics::Topic;
+use crate::models::topics::{
+    Topic,
+    citation::{ClaimsFilter, SummaryCitation},
+};
 use crate::models::workshop::snapshot::{CreateChatSnapshotPayload, WorkshopSnapshotResponse};
 use crate::models::workshop::usage::{get_user_daily_usage, get_user_usage_by_model, get_user_usage_stats};
 use crate::models::workshop::{
@@ -13,7 +16,10 @@ use crate::modules::workshop::prompts::{
     ToolCallStatus as PromptsToolCallStatus,
 };
 use crate::server::ApiTags;
-use crate::server::auth::AuthUser;
+use crate::server::auth::{AuthUser, OptionalAuthUser};
+use crate::modules::workshop::provider::ProviderError;
+use crate::modules::workshop::registry::{ModelConfig, ModelError};
+use crate::server::topic::{get_visible_topic, summary_error};
 use crate::state::AppState;
 use async_std::task;
 use futures::{StreamExt, stream::BoxStream};
@@ -102,6 +108,13 @@ pub struct AvailableModel {
     pub name: String,
     pub provider: String,
     pub is_default: bool,
+    /// Tokens of input and output the model accepts
+    pub context_window: u64,
+    /// USD per prompt token
+    pub prompt_price: f64,
+    /// USD per completion token
+    pub completion_price: f64,
+    pub supports_tools: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Object)]
@@ -139,6 +152,39 @@ fn convert_tool_call_status(status: PromptsToolCallStatus) -> ToolCallStatus {
     }
 }
 
+/// Answer 503 instead of starting work no model is there to do
+fn ensure_enabled(state: &AppState) -> Result<()> {
+    if state.workshop.is_enabled() {
+        return Ok(());
+    }
+
+    Err(poem::Error::from_string(
+        ProviderError::Disabled.to_string(),
+        StatusCode::SERVICE_UNAVAILABLE,
+    ))
+}
+
+/// The requested model if the user's tier may use it, 400 for models not in the registry
+fn resolve_model(
+    state: &AppState,
+    auth_user: &AuthUser,
+    model: Option<&str>,
+) -> Result<ModelConfig> {
+    let registry = state.workshop.models().map_err(|e| {
+        poem::Error::from_string(e.to_string(), StatusCode::SERVICE_UNAVAILABLE)
+    })?;
+
+    let claims = &auth_user.0.claims;
+    let tiers = registry.tiers_for(&claims.provider, &claims.email);
+    registry
+        .resolve(model, &tiers)
+        .cloned()
+        .map_err(|e| match e {
+            ModelError::Unknown(_) => poem::Error::from_string(e.to_string(), StatusCode::BAD_REQUEST),
+            ModelError::NotAllowed(_) => poem::Error::from_string(e.to_string(), StatusCode::FORBIDDEN),
+        })
+}
+
 #[OpenApi]
 impl WorkshopApi {
     /// /ws/t/:discourse_id/:topic_id/summary/to-chat
@@ -159,6 +205,13 @@ impl WorkshopApi {
         let user_id = auth_user.0.user.user_id;
         let user_prompt = format!("Summarize ethereum.forum topic #{}", topic_id.0);
 
+        let topic = Topic::get_by_topic_id(&discourse_id, topic_id.0, &state)
+            .await
+            .map_err(|_| poem::Error::from_status(StatusCode::NOT_FOUND))?;
+        if !state.discourse.access_for(Some(&auth_user.0.claims)).can_view(&topic) {
+            return Err(poem::Error::from_status(StatusCode::NOT_FOUND));
+        }
+
         let message =
             WorkshopMessage::create_user_message(None, None, user_id, user_prompt, &state)
                 .await
@@ -169,10 +222,7 @@ impl WorkshopApi {
 
         let summary = Topic::get_summary_by_topic_id(&discourse_id, topic_id.0, &state)
             .await
-            .map_err(|e| {
-                tracing::error!("Error getting topic summary: {:?}", e);
-                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
-            })?;
+            .map_err(summary_error)?;
 
         let message2 = WorkshopMessage::create_system_response(
             &message.chat_id,
@@ -215,59 +265,34 @@ impl WorkshopApi {
     #[oai(path = "/ws/models", method = "get", tag = "ApiTags::Workshop")]
     async fn get_available_models(
         &self,
-        _state: Data<&AppState>,
-        _auth_user: AuthUser,
+        state: Data<&AppState>,
+        auth_user: AuthUser,
     ) -> Result<Json<AvailableModelsResponse>> {
-        // For now, return a hardcoded list of available models
-        // In the future, this could be dynamically fetched from the LLM provider
-        // or based on user permissions/subscription level
-        let models = vec![
-            AvailableModel {
-                id: "google/gemini-2.5-flash-preview-05-20".to_string(),
-                name: "Gemini 2.5 Flash Preview".to_string(),
-                provider: "Google".to_string(),
-                is_default: true,
-            },
-            AvailableModel {
-                id: "google/gemini-2.0-flash-001".to_string(),
-                name: "Gemini 2.0 Flash".to_string(),
-                provider: "Google".to_string(),
-                is_default: false,
-            },
-            AvailableModel {
-                id: "google/gemini-2.5-pro-preview".to_string(),
-                name: "Gemini 2.5 Pro Preview".to_string(),
-                provider: "Google".to_string(),
-                is_default: false,
-            },
-            AvailableModel {
-                id: "anthropic/claude-sonnet-4".to_string(),
-                name: "Claude Sonnet 4".to_string(),
-                provider: "Anthropic".to_string(),
-                is_default: false,
-            },
-            AvailableModel {
-                id: "openai/o4-mini".to_string(),
-                name: "OpenAI o4 Mini".to_string(),
-                provider: "OpenAI".to_string(),
-                is_default: false,
-            },
-            AvailableModel {
-                id: "mistralai/mistral-7b-instruct:free".to_string(),
-                name: "Mistral 7B Instruct (Free)".to_string(),
-                provider: "Mistral AI".to_string(),
-                is_default: false,
-            },
-            AvailableModel {
-                id: "deepseek/deepseek-chat-v3-0324".to_string(),
-                name: "DeepSeek V3 0324".to_string(),
-                provider: "DeepSeek".to_string(),
-                is_default: false,
-            },
-        ];
+        ensure_enabled(&state)?;
+        let registry = state.workshop.models().map_err(|e| {
+            poem::Error::from_string(e.to_string(), StatusCode::SERVICE_UNAVAILABLE)
+        })?;
+
+        let claims = &auth_user.0.claims;
+        let tiers = registry.tiers_for(&claims.provider, &claims.email);
+        let default_model = registry.default_model();
+        let models = registry
+            .available(&tiers)
+            .into_iter()
+            .map(|model| AvailableModel {
+                id: model.id.clone(),
+                name: model.name.clone(),
+                provider: model.provider.clone(),
+                is_default: model.id == default_model.id,
+                context_window: model.context_window as u64,
+                prompt_price: model.prompt_price,
+                completion_price: model.completion_price,
+                supports_tools: model.tools,
+            })
+            .collect();
 
         Ok(Json(AvailableModelsResponse {
-            default_model: "google/gemini-2.5-flash-preview-05-20".to_string(),
+            default_model: default_model.id.clone(),
             models,
         }))
     }
@@ -330,6 +355,11 @@ impl WorkshopApi {
         #[oai(style = "simple")] chat_id: Path<String>,
         #[oai(style = "simple")] parent_message: Query<Option<Uuid>>,
     ) -> Result<Json<WorkshopMessage>> {
+        ensure_enabled(&state)?;
+
+        // Check the model before storing anything, the default one when none is picked
+        let model = resolve_model(&state, &auth_user, payload.model.as_deref())?;
+
         let user_id = auth_user.0.user.user_id;
         let message = payload.message.clone();
 
@@ -382,9 +412,6 @@ impl WorkshopApi {
                 poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
             })?;
 
-        // Extract the model from the payload, or use default if not specified
-        let model = payload.model.clone();
-
         // Start processing the next message (this will create an OngoingPrompt)
         let (_ongoing_prompt, created_message) = WorkshopService::process_next_message_with_model(
             message.chat_id,
@@ -458,7 +485,10 @@ impl WorkshopApi {
                     poem::Error::from_status(StatusCode::UNAUTHORIZED)
                 })?;
 
-            crate::server::auth::AuthenticatedUser { user, claims }
+            crate::server::auth::AuthenticatedUser {
+                user: Box::new(user),
+                claims,
+            }
         } else {
             // Try to extract from Authorization header using our helper
             match crate::server::auth::extract_user_from_request(req).await {
@@ -555,20 +585,17 @@ impl WorkshopApi {
     async fn start_topic_summary_stream(
         &self,
         state: Data<&AppState>,
+        auth: OptionalAuthUser,
         #[oai(style = "simple")] discourse_id: Path<String>,
         #[oai(style = "simple")] topic_id: Path<i32>,
     ) -> Result<Json<serde_json::Value>> {
-        let topic = Topic::get_by_topic_id(&discourse_id, topic_id.0, &state)
-            .await
-            .map_err(|e| {
-                tracing::error!("Error getting topic: {:?}", e);
-                poem::Error::from_status(StatusCode::NOT_FOUND)
-            })?;
+        let topic = get_visible_topic(&state, &auth, &discourse_id, topic_id.0).await?;
 
         // First check if we already have a recent summary
         if let Ok(existing_summary) = sqlx::query_as!(
             crate::models::topics::TopicSummary,
-            "SELECT * FROM topic_summaries WHERE topic_id = $1 ORDER BY based_on DESC LIMIT 1",
+            "SELECT * FROM topic_summaries WHERE discourse_id = $1 AND topic_id = $2 ORDER BY based_on DESC LIMIT 1",
+            discourse_id.0,
             topic_id.0
         )
         .fetch_optional(&state.database.pool)
@@ -582,10 +609,16 @@ impl WorkshopApi {
 
                 // If summary is current, return existing
                 if summary.based_on.timestamp() == based_on as i64 {
+                    let citations =
+                        SummaryCitation::find_by_summary_id(summary.summary_id, &state)
+                            .await
+                            .unwrap_or_default();
+
                     return Ok(Json(serde_json::json!({
                         "status": "existing",
                         "topic_id": topic_id.0,
-                        "summary": summary.summary_text
+                        "summary": summary.summary_text,
+                        "citations": citations
                     })));
                 }
             }
@@ -603,6 +636,8 @@ impl WorkshopApi {
             })));
         }
 
+        ensure_enabled(&state)?;
+
         // Start the summary generation (or get existing ongoing prompt)
         let _ongoing_prompt = WorkshopService::create_workshop_summary_streaming(&topic, &state)
             .await
@@ -633,14 +668,13 @@ impl WorkshopApi {
                             chrono::DateTime::from_timestamp(based_on as i64, 0)
                                 .unwrap_or_else(|| chrono::Utc::now());
 
-                        if let Err(e) = sqlx::query!(
-                            "INSERT INTO topic_summaries (discourse_id, topic_id, based_on, summary_text, created_at) VALUES ($1, $2, $3, $4, NOW())",
-                            topic_clone.discourse_id,
+                        if let Err(e) = crate::models::topics::TopicSummary::create(
+                            &topic_clone.discourse_id,
                             topic_clone.topic_id,
                             based_on_datetime,
-                            content
+                            &content,
+                            &state_clone,
                         )
-                        .execute(&state_clone.database.pool)
                         .await {
                             tracing::error!("Error saving topic summary: {:?}", e);
                         } else {
@@ -672,6 +706,7 @@ impl WorkshopApi {
     async fn stream_topic_summary(
         &self,
         state: Data<&AppState>,
+        auth: OptionalAuthUser,
         #[oai(style = "simple")] discourse_id: Path<String>,
         #[oai(style = "simple")] topic_id: Path<i32>,
     ) -> Result<EventStream<BoxStream<'static, StreamingResponse>>> {
@@ -681,6 +716,8 @@ impl WorkshopApi {
             discourse_id.0
         );
 
+        get_visible_topic(&state, &auth, &discourse_id, topic_id.0).await?;
+
         // Try to get the ongoing summary prompt
         let ongoing_prompt = state
             .workshop
@@ -700,28 +737,62 @@ impl WorkshopApi {
         // Get the stream
         let stream = ongoing_prompt.get_stream().await;
 
-        // Convert to streaming response events
-        let response_stream = stream
-            .map(|result| match result {
-                Ok(entry) => StreamingResponse {
-                    content: entry.content,
-                    is_complete: false,
-                    error: None,
-                    entry_type: convert_entry_type(entry.entry_type),
-                    tool_call: entry.tool_call.map(convert_tool_call_entry),
-                },
-                Err(err) => {
-                    tracing::error!("Summary stream error: {}", err);
-                    StreamingResponse {
-                        content: String::new(),
-                        is_complete: true,
-                        error: Some(err),
-                        entry_type: StreamingEntryType::ToolCallError,
-                        tool_call: None,
+        // Convert to streaming response events, the claims block is only shown as citations
+        // once the summary is saved
+        let response_stream = futures::stream::unfold(
+            Some((stream.boxed(), ClaimsFilter::default())),
+            |state| async move {
+                let (mut stream, mut filter) = state?;
+                loop {
+                    match stream.next().await {
+                        Some(Ok(entry)) => {
+                            let content = match entry.entry_type {
+                                PromptsStreamingEntryType::Content => filter.push(&entry.content),
+                                _ => entry.content,
+                            };
+                            // Chunks held back entirely aren't sent
+                            if content.is_empty()
+                                && matches!(entry.entry_type, PromptsStreamingEntryType::Content)
+                            {
+                                continue;
+                            }
+
+                            let response = StreamingResponse {
+                                content,
+                                is_complete: false,
+                                error: None,
+                                entry_type: convert_entry_type(entry.entry_type),
+                                tool_call: entry.tool_call.map(convert_tool_call_entry),
+                            };
+                            return Some((response, Some((stream, filter))));
+                        }
+                        Some(Err(err)) => {
+                            tracing::error!("Summary stream error: {}", err);
+                            let response = StreamingResponse {
+                                content: String::new(),
+                                is_complete: true,
+                                error: Some(err),
+                                entry_type: StreamingEntryType::ToolCallError,
+                                tool_call: None,
+                            };
+                            return Some((response, Some((stream, filter))));
+                        }
+                        None => {
+                            let content = filter.finish();
+                            let response = StreamingResponse {
+                                content,
+                                is_complete: false,
+                                error: None,
+                                entry_type: StreamingEntryType::Content,
+                                tool_call: None,
+                            };
+                            return (!response.content.is_empty()).then_some((response, None));
+                        }
                     }
                 }
-            })
-            .boxed();
+            },
+        )
+        .boxed();
 
         Ok(EventStream::new(response_stream))
     }
@@ -2,20 +2,32 @@ use crate::{
     database::Database,
     modules::{
         discourse::{self, DiscourseService},
+        email::{self, Mailer},
         ical::{self, ICalConfig},
         meili,
         pm::PMModule,
+        search::{
+            self, SearchBackend,
+            embeddings::{self, EmbeddingProvider},
+            reconcile::Reconciler,
+            reindex::Reindexer,
+        },
         sso::SSOService,
-        workshop::WorkshopService,
+        workshop::{WorkshopService, registry},
     },
     tmp::CacheService,
 };
-use figment::{Figment, providers::Env};
+use figment::{
+    Figment,
+    providers::{Env, Format, Toml},
+};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 pub type AppState = Arc<AppStateInner>;
 
+const DEFAULT_PUBLIC_URL: &str = "https://ethereum.forum";
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DatabaseConfig {
     pub url: String,
@@ -30,12 +42,22 @@ pub struct AppStateInner {
     pub workshop: WorkshopService,
     pub cache: CacheService,
     pub meili: Option<meili::Client>,
+    pub search: Box<dyn SearchBackend>,
+    /// Set when semantic search is enabled
+    pub embeddings: Option<Arc<dyn EmbeddingProvider>>,
+    pub reindex: Reindexer,
+    pub reconcile: Reconciler,
+    /// Set when SMTP is configured
+    pub email: Option<Mailer>,
+    /// Base of the links in feeds and emails, `PUBLIC_URL`
+    pub public_url: String,
 }
 
 impl AppStateInner {
     /// # Panics
     /// Panics if the environment variables for the database configuration are not set.
-    /// Panics if the OpenAI-compatible API key or base URL for the intelligence is not set.
+    /// Panics if the discourse instances in `config.toml` are missing or invalid.
+    /// Panics if the `[workshop]` models in `config.toml` are invalid.
     pub async fn init() -> Self {
         // Load configuration from environment variables
         let database_config = Figment::new()
@@ -45,18 +67,49 @@ impl AppStateInner {
 
         let database = Database::init(&database_config).await;
 
-        let workshop = WorkshopService::init().await;
-
         let cache = CacheService::default();
 
         let ical = ical::init_ical(Figment::new()).await;
 
-        let discourse_configs = discourse::create_discourse_configs();
+        let email = email::init_email(Figment::new());
+        let public_url = std::env::var("PUBLIC_URL")
+            .unwrap_or_else(|_| DEFAULT_PUBLIC_URL.to_string())
+            .trim_end_matches('/')
+            .to_string();
+
+        // Load file based configuration, path can be overridden with CONFIG_PATH
+        let config_path = std::env::var("CONFIG_PATH").unwrap_or_else(|_| "config.toml".to_string());
+        let config = Figment::new().merge(Toml::file(&config_path));
+
+        let models = registry::init_registry(config.clone()).map(|registry| {
+            registry.unwrap_or_else(|e| {
+                panic!("Invalid workshop configuration in {}: {}", config_path, e)
+            })
+        });
+        let workshop = WorkshopService::init(models).await;
+
+        let discourse_configs = discourse::init_discourse_configs(config)
+            .unwrap_or_else(|e| panic!("Invalid discourse configuration in {}: {}", config_path, e));
+        for discourse_config in &discourse_configs {
+            tracing::info!(
+                "Configured discourse '{}' at {} (every {})",
+                discourse_config.discourse_id,
+                discourse_config.url,
+                discourse_config.scrape_interval
+            );
+        }
         let discourse = DiscourseService::new(discourse_configs);
 
         let pm = PMModule::default();
 
         let meili = meili::init_meili().await;
+        let embeddings = embeddings::init_embeddings(workshop.provider.as_ref());
+        if let (Some(meili), Some(embeddings)) = (&meili, &embeddings)
+            && let Err(e) = meili::configure_embedder(meili, "forum", embeddings.dimensions()).await
+        {
+            tracing::error!("Failed to configure the forum embedder: {}", e);
+        }
+        let search = search::init_search(meili.as_ref(), embeddings.clone(), &database);
 
         let sso = match SSOService::new(Figment::new().merge(Env::raw())).await {
             Ok(service) => {
@@ -81,6 +134,12 @@ impl AppStateInner {
             workshop,
             sso,
             meili,
+            search,
+            embeddings,
+            reindex: Reindexer::default(),
+            reconcile: Reconciler::default(),
+            email,
+            public_url,
         }
     }
 }